@@ -1,20 +1,193 @@
+use crate::epub::time::FromJST;
+use crate::epub::{Compression, Direction};
+use crate::narou::Proxy;
+use crate::output;
+use crate::output::OutputFormat;
 use std::default::Default;
 use std::mem::MaybeUninit;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+use std::time::Duration;
+use utcdatetime::DateTime;
 use windows_sys::Win32::Foundation::{GetLastError, WIN32_ERROR};
 use windows_sys::Win32::System::Environment::GetCommandLineW;
 use windows_sys::Win32::UI::Shell::CommandLineToArgvW;
 use windows_sys::w;
 
 pub struct Cmd {
-    pub horizontal: bool,
-    pub wait: f64,
+    /// 明示された書字方向で、指定がなければ縦書きか自動の判定
+    pub direction: Option<Direction>,
+    pub auto_orientation: bool,
+    pub quiet: bool,
+    pub verbose: bool,
+    pub json_summary: bool,
+    pub no_images: bool,
+    pub strip_author_notes: bool,
+    pub keep_links: bool,
+    pub keep_partial: bool,
+    pub skip_errors: bool,
+    pub random_id: bool,
+    pub dry_run: bool,
+    pub normalize_filename: bool,
+    pub format: OutputFormat,
+    pub compression: Compression,
+    pub wait: Wait,
+    pub force_no_wait: bool,
+    // 作品の間の待機の秒数で、指定がなければ各話の間隔の下限を使う
+    pub novel_wait: Option<f64>,
+    pub over18: bool,
+    pub user_agent: Option<String>,
+    pub proxy: Proxy,
+    pub overwrite: bool,
+    /// 出力済みの本が前回から変わっていなければ作り直さない
+    pub update: bool,
+    pub colophon: bool,
+    pub gen_cover: bool,
+    pub no_title_page: bool,
+    pub jobs: usize,
+    pub max_image_size: u64,
+    pub max_total_size: u64,
+    pub cache_dir: Option<String>,
+    pub output: Option<String>,
+    pub name_template: Option<String>,
+    pub css: Option<String>,
+    pub series: Option<String>,
+    pub title: Option<String>,
+    pub language: Option<String>,
+    pub publisher: Option<String>,
+    pub series_index: u32,
+    pub toc_depth: Option<u32>,
+    pub split_by: Option<u32>,
+    /// 本を作らずにこの話だけを取得して表示する
+    pub episode: Option<u32>,
+    /// この日時より前に更新された話を飛ばす
+    pub since: Option<DateTime>,
     pub ncodes: Vec<String>,
+    pub authors: Vec<u32>,
 }
 
+/// アクセスの間隔の秒数で、範囲で指定された場合はその中から毎回無作為に選ぶ
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Wait {
+    pub min: f64,
+    pub max: f64,
+}
+
+/// 年齢確認に同意するかを指定する環境変数
+pub const OVER18_VARIABLE: &str = "NAROU_OVER18";
+
+// 環境変数の値が yes のときだけ同意したものとする
+fn over18_from_env(value: Option<&str>) -> bool {
+    value.is_some_and(|value| value.trim().eq_ignore_ascii_case("yes"))
+}
+
+/// 名乗るユーザーエージェントを指定する環境変数
+pub const USER_AGENT_VARIABLE: &str = "NAROU_EPUB_UA";
+
+// 空の値は指定しなかったものとする
+fn user_agent_from_env(value: Option<String>) -> Option<String> {
+    value.filter(|value| !value.trim().is_empty())
+}
+
+/// サーバに負担をかけすぎないためのアクセスの間隔の下限の秒数
+pub const MIN_WAIT: f64 = 0.2;
+
+impl Wait {
+    pub fn range(&self) -> RangeInclusive<Duration> {
+        Duration::from_secs_f64(self.min)..=Duration::from_secs_f64(self.max)
+    }
+
+    /// 下限より短い間隔を下限まで引き上げ、引き上げたかどうかを返す
+    pub fn raise_to(&mut self, minimum: f64) -> bool {
+        if self.min >= minimum {
+            return false;
+        }
+        self.min = minimum;
+        self.max = self.max.max(minimum);
+        true
+    }
+}
+
+// 負でない有限の秒数
+fn parse_second(s: &str) -> Result<f64, Error> {
+    s.parse::<f64>()
+        .ok()
+        .filter(|x| x.is_finite() && *x >= 0.0)
+        .ok_or(Error::ParseErrorSecond)
+}
+
+impl FromStr for Wait {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (min, max) = match parse_second(s) {
+            Ok(x) => (x, x),
+            Err(_) => {
+                let (min, max) = s.split_once('-').ok_or(Error::ParseErrorSecond)?;
+                (parse_second(min)?, parse_second(max)?)
+            }
+        };
+        if min <= max {
+            Ok(Self { min, max })
+        } else {
+            Err(Error::ParseErrorSecond)
+        }
+    }
+}
+
+// メガバイト単位の大きさで、 0 は受け付けない
+fn parse_megabytes(s: &str) -> Result<u64, Error> {
+    s.parse::<u64>()
+        .ok()
+        .filter(|n| (1..=u64::MAX >> 20).contains(n))
+        .ok_or(Error::ParseErrorSize)
+}
+
+// YYYY-MM-DD の日付を日本時間のその日の始まりとする
+fn parse_since(s: &str) -> Result<DateTime, Error> {
+    DateTime::from_jst_str(&[s.trim(), " 00:00:00"].concat()).or(Err(Error::ParseErrorSince))
+}
+
+// [http:// か https://]ホスト[:ポート] の形のプロキシの指定
+fn parse_proxy(s: &str) -> Result<Proxy, Error> {
+    let server = s.trim().trim_end_matches('/');
+    let address = server
+        .strip_prefix("http://")
+        .or_else(|| server.strip_prefix("https://"))
+        .unwrap_or(server);
+    let (host, port) = match address.split_once(':') {
+        Some((host, port)) => (host, Some(port)),
+        None => (address, None),
+    };
+    let valid_host = !host.is_empty()
+        && host
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-'));
+    let valid_port = port.is_none_or(|port| port.parse::<u16>().is_ok_and(|port| port > 0));
+    if valid_host && valid_port {
+        Ok(Proxy::Server(server.to_string()))
+    } else {
+        Err(Error::ParseErrorProxy)
+    }
+}
+
+#[derive(Debug)]
 pub enum Error {
     UnknownOption,
     Help,
     ParseErrorSecond,
+    ParseErrorJobs,
+    ParseErrorSize,
+    ParseErrorSeriesIndex,
+    ParseErrorTocDepth,
+    ParseErrorSplitBy,
+    ParseErrorEpisode,
+    ParseErrorSince,
+    UnknownFormat,
+    ListFormats,
+    UnknownCompression,
+    UnknownDirection,
+    ParseErrorUserId,
+    ParseErrorProxy,
     Version,
     FromUtf16Error,
     SystemErrorCode(u32),
@@ -32,6 +205,43 @@ impl std::fmt::Display for Error {
                 env!("CARGO_PKG_VERSION")
             ),
             Error::ParseErrorSecond => write!(f, "秒の指定を解釈できませんでした。"),
+            Error::ParseErrorJobs => write!(f, "並列数の指定を解釈できませんでした。"),
+            Error::ParseErrorSize => write!(f, "大きさの上限の指定を解釈できませんでした。"),
+            Error::ParseErrorSeriesIndex => write!(f, "シリーズ番号の指定を解釈できませんでした。"),
+            Error::ParseErrorTocDepth => write!(f, "目次の深さの指定を解釈できませんでした。"),
+            Error::ParseErrorSplitBy => write!(f, "一冊あたりの話数の指定を解釈できませんでした。"),
+            Error::ParseErrorEpisode => write!(f, "話数の指定を解釈できませんでした。"),
+            Error::ParseErrorSince => {
+                write!(
+                    f,
+                    "日付の指定を解釈できませんでした。 (YYYY-MM-DD の形で指定してください)"
+                )
+            }
+            Error::ParseErrorUserId => write!(f, "作者のユーザ ID を解釈できませんでした。"),
+            Error::ParseErrorProxy => write!(
+                f,
+                "プロキシの指定を解釈できませんでした。 (http://ホスト:ポート の形で指定してください)"
+            ),
+            Error::UnknownFormat => write!(
+                f,
+                "知らない出力形式です。 ({} のいずれかを指定してください)",
+                OutputFormat::ALL
+                    .map(|format| format.to_string())
+                    .join(", ")
+            ),
+            Error::ListFormats => {
+                for format in OutputFormat::ALL {
+                    writeln!(f, "{:<6}{}", format, format.description())?;
+                }
+                Ok(())
+            }
+            Error::UnknownCompression => write!(
+                f,
+                "知らない圧縮方法です。 (auto か store か deflate を指定してください)"
+            ),
+            Error::UnknownDirection => {
+                write!(f, "知らない書字方向です。 (rtl か ltr を指定してください)")
+            }
             Error::SystemErrorCode(n) => write!(f, "ウィンドウズのシステムエラーです。 ({})", n),
             Error::FromUtf16Error => write!(f, "コマンドラインの文字コードの解釈に失敗しました。"),
         }
@@ -56,6 +266,29 @@ enum State {
     Start,
     Options,
     Wait,
+    NovelWait,
+    Jobs,
+    MaxImageSize,
+    MaxTotalSize,
+    CacheDir,
+    Output,
+    NameTemplate,
+    Css,
+    Series,
+    Title,
+    SeriesIndex,
+    TocDepth,
+    SplitBy,
+    Episode,
+    Since,
+    Language,
+    Publisher,
+    UserAgent,
+    Proxy,
+    Format,
+    Compression,
+    Direction,
+    Author,
     Ncodes,
 }
 
@@ -136,21 +369,183 @@ impl PartialEq<*const u16> for Arg {
 
 impl Cmd {
     pub fn parse() -> Result<Self, Error> {
-        let mut horizontal = false;
+        let mut direction = None;
+        let mut auto_orientation = false;
+        let mut quiet = false;
+        let mut verbose = false;
+        let mut json_summary = false;
+        let mut no_images = false;
+        let mut strip_author_notes = false;
+        let mut keep_links = false;
+        let mut keep_partial = false;
+        let mut skip_errors = false;
+        let mut random_id = false;
+        let mut dry_run = false;
+        let mut normalize_filename = false;
+        let mut format = OutputFormat::default();
+        let mut compression = Compression::Auto;
         let mut state = Default::default();
-        let mut wait = 1.0;
+        let mut wait = Wait { min: 1.0, max: 1.0 };
+        let mut force_no_wait = false;
+        let mut novel_wait = None;
+        // 環境変数よりもコマンドラインの指定を優先する
+        let mut over18 = over18_from_env(std::env::var(OVER18_VARIABLE).ok().as_deref());
+        let mut user_agent = user_agent_from_env(std::env::var(USER_AGENT_VARIABLE).ok());
+        let mut proxy = Proxy::System;
+        let mut overwrite = true;
+        let mut update = false;
+        let mut colophon = false;
+        let mut gen_cover = false;
+        let mut no_title_page = false;
+        let mut jobs = 1;
+        let mut max_image_size = 20;
+        let mut max_total_size = 1024;
+        let mut cache_dir = None;
+        let mut output = None;
+        let mut name_template = None;
+        let mut css = None;
+        let mut series = None;
+        let mut title = None;
+        let mut series_index = 1;
+        let mut toc_depth = None;
+        let mut split_by = None;
+        let mut episode = None;
+        let mut since = None;
+        let mut language = None;
+        let mut publisher = None;
         let mut ncodes: Vec<String> = vec![];
+        let mut authors: Vec<u32> = vec![];
         for i in Argv::new()?.iter() {
             state = match state {
                 State::Start => State::Options,
                 State::Options => {
                     if i == w!("--horizontal") {
-                        horizontal = true;
+                        direction = Some(Direction::Ltr);
+                        State::Options
+                    } else if i == w!("--auto-orientation") {
+                        auto_orientation = true;
+                        State::Options
+                    } else if i == w!("--quiet") || i == w!("-q") {
+                        quiet = true;
+                        State::Options
+                    } else if i == w!("--verbose") || i == w!("-v") {
+                        verbose = true;
+                        State::Options
+                    } else if i == w!("--json-summary") {
+                        json_summary = true;
+                        State::Options
+                    } else if i == w!("--no-images") {
+                        no_images = true;
+                        State::Options
+                    } else if i == w!("--strip-author-notes") {
+                        strip_author_notes = true;
+                        State::Options
+                    } else if i == w!("--keep-links") {
+                        keep_links = true;
+                        State::Options
+                    } else if i == w!("--skip-errors") {
+                        skip_errors = true;
+                        State::Options
+                    } else if i == w!("--keep-partial") {
+                        keep_partial = true;
+                        State::Options
+                    } else if i == w!("--random-id") {
+                        random_id = true;
+                        State::Options
+                    } else if i == w!("--dry-run") {
+                        dry_run = true;
                         State::Options
+                    } else if i == w!("--normalize-filename") {
+                        normalize_filename = true;
+                        State::Options
+                    } else if i == w!("--over18") {
+                        over18 = true;
+                        State::Options
+                    } else if i == w!("--no-over18") {
+                        over18 = false;
+                        State::Options
+                    } else if i == w!("--overwrite") {
+                        overwrite = true;
+                        State::Options
+                    } else if i == w!("--no-overwrite") {
+                        overwrite = false;
+                        State::Options
+                    } else if i == w!("--update") {
+                        update = true;
+                        State::Options
+                    } else if i == w!("--colophon") {
+                        colophon = true;
+                        State::Options
+                    } else if i == w!("--gen-cover") {
+                        gen_cover = true;
+                        State::Options
+                    } else if i == w!("--no-title-page") {
+                        no_title_page = true;
+                        State::Options
+                    } else if i == w!("--flat-name") {
+                        name_template = Some(output::FLAT_TEMPLATE.to_string());
+                        State::Options
+                    } else if i == w!("--author-folder") {
+                        name_template = Some(output::AUTHOR_FOLDER_TEMPLATE.to_string());
+                        State::Options
+                    } else if i == w!("--force-no-wait") {
+                        force_no_wait = true;
+                        State::Options
+                    } else if i == w!("--novel-wait") {
+                        State::NovelWait
                     } else if i == w!("--wait") || i == w!("-w") {
                         State::Wait
+                    } else if i == w!("--jobs") || i == w!("-j") {
+                        State::Jobs
+                    } else if i == w!("--max-image-size") {
+                        State::MaxImageSize
+                    } else if i == w!("--max-total-size") {
+                        State::MaxTotalSize
+                    } else if i == w!("--cache-dir") {
+                        State::CacheDir
+                    } else if i == w!("--output") || i == w!("-o") {
+                        State::Output
+                    } else if i == w!("--name-template") {
+                        State::NameTemplate
+                    } else if i == w!("--css") {
+                        State::Css
+                    } else if i == w!("--series") {
+                        State::Series
+                    } else if i == w!("--title") {
+                        State::Title
+                    } else if i == w!("--series-index") {
+                        State::SeriesIndex
+                    } else if i == w!("--toc-depth") {
+                        State::TocDepth
+                    } else if i == w!("--split-by") {
+                        State::SplitBy
+                    } else if i == w!("--episode") {
+                        State::Episode
+                    } else if i == w!("--since") {
+                        State::Since
+                    } else if i == w!("--lang") {
+                        State::Language
+                    } else if i == w!("--publisher") {
+                        State::Publisher
+                    } else if i == w!("--user-agent") {
+                        State::UserAgent
+                    } else if i == w!("--proxy") {
+                        State::Proxy
+                    } else if i == w!("--no-proxy") {
+                        proxy = Proxy::Direct;
+                        State::Options
+                    } else if i == w!("--format") {
+                        State::Format
+                    } else if i == w!("--compression") {
+                        State::Compression
+                    } else if i == w!("--direction") {
+                        State::Direction
+                    } else if i == w!("--author") {
+                        State::Author
                     } else if i == w!("--help") || i == w!("-h") {
                         return Err(Error::Help);
+                    } else if i == w!("--list-formats") {
+                        return Err(Error::ListFormats);
                     } else if i == w!("--version") || i == w!("-V") {
                         return Err(Error::Version);
                     } else if i == w!("--") {
@@ -163,9 +558,138 @@ impl Cmd {
                     }
                 }
                 State::Wait => {
-                    wait = String::from_utf16(i.as_slice())?
-                        .parse::<f64>()
-                        .or(Err(Error::ParseErrorSecond))?;
+                    wait = String::from_utf16(i.as_slice())?.parse::<Wait>()?;
+                    State::Options
+                }
+                State::NovelWait => {
+                    novel_wait = Some(parse_second(&String::from_utf16(i.as_slice())?)?);
+                    State::Options
+                }
+                State::Jobs => {
+                    jobs = String::from_utf16(i.as_slice())?
+                        .parse::<usize>()
+                        .ok()
+                        .filter(|&n| n > 0)
+                        .ok_or(Error::ParseErrorJobs)?;
+                    State::Options
+                }
+                State::MaxImageSize => {
+                    max_image_size = parse_megabytes(&String::from_utf16(i.as_slice())?)?;
+                    State::Options
+                }
+                State::MaxTotalSize => {
+                    max_total_size = parse_megabytes(&String::from_utf16(i.as_slice())?)?;
+                    State::Options
+                }
+                State::CacheDir => {
+                    cache_dir = Some(String::from_utf16(i.as_slice())?);
+                    State::Options
+                }
+                State::Output => {
+                    output = Some(String::from_utf16(i.as_slice())?);
+                    State::Options
+                }
+                State::NameTemplate => {
+                    name_template = Some(String::from_utf16(i.as_slice())?);
+                    State::Options
+                }
+                State::Css => {
+                    css = Some(String::from_utf16(i.as_slice())?);
+                    State::Options
+                }
+                State::Series => {
+                    series = Some(String::from_utf16(i.as_slice())?);
+                    State::Options
+                }
+                State::Title => {
+                    title = Some(String::from_utf16(i.as_slice())?);
+                    State::Options
+                }
+                State::SeriesIndex => {
+                    series_index = String::from_utf16(i.as_slice())?
+                        .parse::<u32>()
+                        .or(Err(Error::ParseErrorSeriesIndex))?;
+                    State::Options
+                }
+                State::TocDepth => {
+                    toc_depth = Some(
+                        String::from_utf16(i.as_slice())?
+                            .parse::<u32>()
+                            .ok()
+                            .filter(|&depth| depth > 0)
+                            .ok_or(Error::ParseErrorTocDepth)?,
+                    );
+                    State::Options
+                }
+                State::SplitBy => {
+                    split_by = Some(
+                        String::from_utf16(i.as_slice())?
+                            .parse::<u32>()
+                            .ok()
+                            .filter(|&size| size > 0)
+                            .ok_or(Error::ParseErrorSplitBy)?,
+                    );
+                    State::Options
+                }
+                State::Episode => {
+                    episode = Some(
+                        String::from_utf16(i.as_slice())?
+                            .parse::<u32>()
+                            .ok()
+                            .filter(|&number| number > 0)
+                            .ok_or(Error::ParseErrorEpisode)?,
+                    );
+                    State::Options
+                }
+                State::Since => {
+                    since = Some(parse_since(&String::from_utf16(i.as_slice())?)?);
+                    State::Options
+                }
+                State::Language => {
+                    language = Some(String::from_utf16(i.as_slice())?);
+                    State::Options
+                }
+                State::Publisher => {
+                    publisher = Some(String::from_utf16(i.as_slice())?);
+                    State::Options
+                }
+                State::UserAgent => {
+                    user_agent = user_agent_from_env(Some(String::from_utf16(i.as_slice())?));
+                    State::Options
+                }
+                State::Proxy => {
+                    proxy = parse_proxy(&String::from_utf16(i.as_slice())?)?;
+                    State::Options
+                }
+                State::Format => {
+                    format = String::from_utf16(i.as_slice())?
+                        .parse()
+                        .or(Err(Error::UnknownFormat))?;
+                    State::Options
+                }
+                State::Compression => {
+                    compression = match String::from_utf16(i.as_slice())?.as_str() {
+                        "auto" => Compression::Auto,
+                        "store" => Compression::Store,
+                        "deflate" => Compression::Deflate,
+                        _ => return Err(Error::UnknownCompression),
+                    };
+                    State::Options
+                }
+                State::Direction => {
+                    direction = Some(
+                        String::from_utf16(i.as_slice())?
+                            .parse()
+                            .or(Err(Error::UnknownDirection))?,
+                    );
+                    State::Options
+                }
+                State::Author => {
+                    authors.push(
+                        String::from_utf16(i.as_slice())?
+                            .parse::<u32>()
+                            .or(Err(Error::ParseErrorUserId))?,
+                    );
                     State::Options
                 }
                 State::Ncodes => {
@@ -174,13 +698,198 @@ impl Cmd {
                 }
             }
         }
-        if ncodes.is_empty() {
+        if ncodes.is_empty() && authors.is_empty() {
             return Err(Error::Help);
         }
         Ok(Self {
-            horizontal,
+            direction,
+            auto_orientation,
+            quiet,
+            verbose,
+            json_summary,
+            no_images,
+            strip_author_notes,
+            keep_links,
+            keep_partial,
+            skip_errors,
+            random_id,
+            dry_run,
+            normalize_filename,
+            format,
+            compression,
             wait,
+            force_no_wait,
+            novel_wait,
+            over18,
+            user_agent,
+            proxy,
+            overwrite,
+            update,
+            colophon,
+            gen_cover,
+            no_title_page,
+            jobs,
+            max_image_size: max_image_size * 1024 * 1024,
+            max_total_size: max_total_size * 1024 * 1024,
+            cache_dir,
+            output,
+            name_template,
+            css,
+            series,
+            title,
+            series_index,
+            toc_depth,
+            split_by,
+            episode,
+            since,
+            language,
+            publisher,
             ncodes,
+            authors,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Error, MIN_WAIT, OutputFormat, Proxy, Wait, over18_from_env, parse_megabytes, parse_proxy,
+        parse_since, user_agent_from_env,
+    };
+
+    #[test]
+    fn proxy() {
+        let server = |s: &str| Proxy::Server(s.to_string());
+        assert_eq!(
+            parse_proxy("http://proxy.example.com:8080").unwrap(),
+            server("http://proxy.example.com:8080")
+        );
+        assert_eq!(
+            parse_proxy(" https://proxy.example.com:8080/ ").unwrap(),
+            server("https://proxy.example.com:8080")
+        );
+        assert_eq!(
+            parse_proxy("127.0.0.1:3128").unwrap(),
+            server("127.0.0.1:3128")
+        );
+        assert_eq!(parse_proxy("proxy").unwrap(), server("proxy"));
+        for invalid in [
+            "",
+            "http://",
+            "ftp://proxy:21",
+            "proxy:0",
+            "proxy:65536",
+            "proxy:port",
+            "proxy:8080/path",
+            "user@proxy:8080",
+            "prox y",
+        ] {
+            assert!(
+                matches!(parse_proxy(invalid), Err(Error::ParseErrorProxy)),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn formats() {
+        assert_eq!(
+            Error::UnknownFormat.to_string(),
+            "知らない出力形式です。 (epub, txt のいずれかを指定してください)"
+        );
+        let list = Error::ListFormats.to_string();
+        assert!(list.lines().next().unwrap().starts_with("epub"));
+        assert_eq!(list.lines().count(), OutputFormat::ALL.len());
+    }
+
+    #[test]
+    fn wait() {
+        assert_eq!(
+            "1.0-2.5".parse::<Wait>().unwrap(),
+            Wait { min: 1.0, max: 2.5 }
+        );
+        assert_eq!("1.5".parse::<Wait>().unwrap(), Wait { min: 1.5, max: 1.5 });
+        assert_eq!("0".parse::<Wait>().unwrap(), Wait { min: 0.0, max: 0.0 });
+        assert!(matches!(
+            "2.5-1.0".parse::<Wait>(),
+            Err(Error::ParseErrorSecond)
+        ));
+        assert!(matches!("-1".parse::<Wait>(), Err(Error::ParseErrorSecond)));
+        assert!(matches!(
+            "a-b".parse::<Wait>(),
+            Err(Error::ParseErrorSecond)
+        ));
+    }
+
+    #[test]
+    fn minimum_wait() {
+        let mut wait = Wait { min: 0.0, max: 0.0 };
+        assert!(wait.raise_to(MIN_WAIT));
+        assert_eq!(
+            wait,
+            Wait {
+                min: MIN_WAIT,
+                max: MIN_WAIT
+            }
+        );
+        let mut wait = Wait { min: 0.1, max: 2.0 };
+        assert!(wait.raise_to(MIN_WAIT));
+        assert_eq!(
+            wait,
+            Wait {
+                min: MIN_WAIT,
+                max: 2.0
+            }
+        );
+        let mut wait = Wait { min: 1.0, max: 1.0 };
+        assert!(!wait.raise_to(MIN_WAIT));
+        assert_eq!(wait, Wait { min: 1.0, max: 1.0 });
+    }
+
+    #[test]
+    fn over18() {
+        assert!(over18_from_env(Some("yes")));
+        assert!(over18_from_env(Some("YES ")));
+        assert!(!over18_from_env(Some("no")));
+        assert!(!over18_from_env(Some("")));
+        assert!(!over18_from_env(None));
+    }
+
+    #[test]
+    fn user_agent() {
+        assert_eq!(
+            user_agent_from_env(Some("Mozilla/5.0".to_string())).as_deref(),
+            Some("Mozilla/5.0")
+        );
+        assert_eq!(user_agent_from_env(Some(" ".to_string())), None);
+        assert_eq!(user_agent_from_env(None), None);
+    }
+
+    #[test]
+    fn since() {
+        assert_eq!(
+            parse_since("2023-04-05").unwrap().to_string(),
+            "2023-04-04T15:00:00Z"
+        );
+        assert!(matches!(
+            parse_since("2023/04/05"),
+            Err(Error::ParseErrorSince)
+        ));
+        assert!(matches!(
+            parse_since("2023-02-30"),
+            Err(Error::ParseErrorSince)
+        ));
+    }
+
+    #[test]
+    fn megabytes() {
+        assert_eq!(parse_megabytes("20").unwrap(), 20);
+        assert!(matches!(parse_megabytes("0"), Err(Error::ParseErrorSize)));
+        assert!(matches!(parse_megabytes("1.5"), Err(Error::ParseErrorSize)));
+        assert!(matches!(
+            parse_megabytes(&u64::MAX.to_string()),
+            Err(Error::ParseErrorSize)
+        ));
+    }
+}
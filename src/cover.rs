@@ -0,0 +1,150 @@
+use crate::epub::Escape;
+
+const WIDTH: usize = 1200;
+const HEIGHT: usize = 1800;
+const MARGIN: usize = 120;
+// 左端の作者名に取っておく幅と、表題との間
+const AUTHOR_WIDTH: usize = 200;
+const GAP: usize = 40;
+
+// 縦書きの一列に per_column 文字ずつ区切る
+fn wrap(text: &str, per_column: usize) -> Vec<String> {
+    let chars: Vec<char> = text.chars().collect();
+    chars
+        .chunks(per_column.max(1))
+        .map(|x| x.iter().collect())
+        .collect()
+}
+
+// 列の間は文字の半分をあける
+fn columns_width(columns: usize, size: usize) -> usize {
+    columns * size * 3 / 2
+}
+
+// 幅と高さに収まるまで文字を小さくして列に区切る。
+// 最小の大きさでも収まらなければ入りきらない列を捨て、最後の文字を … にする
+fn fit(
+    text: &str,
+    max_size: usize,
+    min_size: usize,
+    width: usize,
+    height: usize,
+) -> (usize, Vec<String>) {
+    let mut size = max_size;
+    loop {
+        let mut columns = wrap(text, height / size);
+        if columns_width(columns.len(), size) <= width {
+            return (size, columns);
+        }
+        if size <= min_size {
+            columns.truncate((width / columns_width(1, size)).max(1));
+            if let Some(last) = columns.last_mut() {
+                last.pop();
+                last.push('…');
+            }
+            return (size, columns);
+        }
+        size = (size - 4).max(min_size);
+    }
+}
+
+// right を右端として右から左へ列を並べる
+fn write_columns(
+    out: &mut String,
+    columns: &[String],
+    size: usize,
+    right: usize,
+    y: usize,
+    anchor: &str,
+) {
+    for (i, column) in columns.iter().enumerate() {
+        let x = right - size / 2 - i * size * 3 / 2;
+        out.push_str(&format!(
+            r#"<text x="{}" y="{}" font-size="{}" writing-mode="tb-rl" text-anchor="{}">{}</text>"#,
+            x,
+            y,
+            size,
+            anchor,
+            column.escape()
+        ));
+    }
+}
+
+/// 表題を右から縦書きに、作者名を左下に置いた表紙の SVG
+pub fn make_cover(title: &str, author: &str) -> String {
+    let title_width = WIDTH - MARGIN * 2 - AUTHOR_WIDTH - GAP;
+    let (title_size, title_columns) = fit(title, 120, 48, title_width, HEIGHT - MARGIN * 2);
+    let (author_size, author_columns) = fit(author, 64, 32, AUTHOR_WIDTH, HEIGHT / 2 - MARGIN);
+    let mut out = format!(
+        concat!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" version="1.1" width="{0}" height="{1}" viewBox="0 0 {0} {1}">"#,
+            r#"<title>{2}</title>"#,
+            r#"<defs><linearGradient id="background" x1="0" y1="0" x2="0" y2="1">"#,
+            r##"<stop offset="0" stop-color="#1f3a5f"/><stop offset="1" stop-color="#4f7a94"/>"##,
+            r#"</linearGradient></defs>"#,
+            r#"<rect width="{0}" height="{1}" fill="url(#background)"/>"#,
+            r##"<g fill="#ffffff" font-family="serif">"##,
+        ),
+        WIDTH,
+        HEIGHT,
+        title.escape()
+    );
+    write_columns(
+        &mut out,
+        &title_columns,
+        title_size,
+        WIDTH - MARGIN,
+        MARGIN,
+        "start",
+    );
+    // 作者名は下端に揃える
+    write_columns(
+        &mut out,
+        &author_columns,
+        author_size,
+        MARGIN + AUTHOR_WIDTH,
+        HEIGHT - MARGIN,
+        "end",
+    );
+    out.push_str("</g></svg>");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HEIGHT, MARGIN, fit, make_cover, wrap};
+    use crate::epub::wellformed::is_well_formed;
+
+    #[test]
+    fn cover() {
+        let svg = make_cover("試験の<物語> & 続き", "作者");
+        assert!(is_well_formed(&svg), "{}", svg);
+        assert!(svg.contains("試験の&lt;物語&gt; &amp; 続き"));
+        assert!(svg.contains(">作者</text>"));
+    }
+
+    #[test]
+    fn wrapping() {
+        assert_eq!(wrap("一二三四五", 2), ["一二", "三四", "五"]);
+        // 短い表題は最大の大きさの一列
+        assert_eq!(
+            fit("表題", 120, 48, 700, 1560),
+            (120, vec!["表題".to_string()])
+        );
+        // 長い表題は小さくして折り返す
+        let title = "あ".repeat(40);
+        let (size, columns) = fit(&title, 120, 48, 700, HEIGHT - MARGIN * 2);
+        assert!(size < 120 && columns.len() > 1);
+        assert_eq!(columns.concat(), title);
+        // 最小でも収まらなければ末尾を省く
+        let (size, columns) = fit(&"あ".repeat(1000), 120, 48, 700, 1560);
+        assert_eq!(size, 48);
+        assert_eq!(columns.len(), 700 / 72);
+        assert!(columns.last().unwrap().ends_with('…'));
+        assert!(is_well_formed(&make_cover(
+            &"あ".repeat(1000),
+            &"い".repeat(100)
+        )));
+    }
+}
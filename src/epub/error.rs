@@ -0,0 +1,24 @@
+#[derive(Debug)]
+pub enum Error {
+    Zip(zip_builder::Error),
+    MalformedXhtml(String),
+    // 同じ名前のファイルを二度追加しようとした
+    DuplicateName(String),
+    // 読み戻そうとした書庫が壊れているか、このクレートの書いた形でない
+    InvalidArchive,
+    Io,
+}
+
+impl From<zip_builder::Error> for Error {
+    fn from(value: zip_builder::Error) -> Self {
+        Error::Zip(value)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(_: std::io::Error) -> Self {
+        Error::Io
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -11,9 +11,27 @@ impl<T: AsRef<str>> Escape for T {
                 '"' => newstr.push_str("&quot;"),
                 '<' => newstr.push_str("&lt;"),
                 '>' => newstr.push_str("&gt;"),
+                '\'' => newstr.push_str("&apos;"),
+                // XML 1.0 で使えない制御文字と非文字の U+FFFE, U+FFFF は取り除く
+                '\t' | '\n' | '\r' => newstr.push(ch),
+                '\0'..='\x1f' | '\u{fffe}' | '\u{ffff}' => {}
                 nchar => newstr.push(nchar),
             }
         }
         newstr
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Escape;
+
+    #[test]
+    fn escape() {
+        assert_eq!(r#"<&>"'"#.escape(), "&lt;&amp;&gt;&quot;&apos;");
+        assert_eq!("吾輩's 猫".escape(), "吾輩&apos;s 猫");
+        assert_eq!("表\0題\u{1}\u{1f}".escape(), "表題");
+        assert_eq!("表\u{fffe}題\u{ffff}\u{fffd}".escape(), "表題\u{fffd}");
+        assert_eq!("一\t二\n三\r\n".escape(), "一\t二\n三\r\n");
+    }
+}
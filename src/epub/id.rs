@@ -1,3 +1,6 @@
+/// 重ならない名前や id を順に割り当てる
+///
+/// usize::MAX 個目の手前で尽きるが、本に収める数としては実質的に無限と考えてよい
 pub struct Id {
     first_letter: &'static [u8],
     letter: &'static [u8],
@@ -25,6 +28,7 @@ impl Id {
 impl Iterator for Id {
     type Item = String;
     fn next(&mut self) -> Option<Self::Item> {
+        let next = self.number.checked_add(1)?;
         let mut n = self.number;
         let mut newstr = String::new();
         newstr.push(self.first_letter[n % self.first_letter.len()].into());
@@ -33,7 +37,58 @@ impl Iterator for Id {
             newstr.push(self.letter[n % self.letter.len()].into());
             n /= self.letter.len();
         }
-        self.number += 1;
+        self.number = next;
         Some(newstr)
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = usize::MAX - self.number;
+        (remaining, Some(remaining))
+    }
+}
+
+impl ExactSizeIterator for Id {}
+
+#[cfg(test)]
+mod tests {
+    use super::Id;
+    use std::collections::HashSet;
+
+    // Iterator::next の逆変換
+    fn parse(id: &Id, s: &str) -> usize {
+        let position = |letters: &[u8], ch: u8| letters.iter().position(|&x| x == ch).unwrap();
+        let bytes = s.as_bytes();
+        let rest = bytes[1..]
+            .iter()
+            .rev()
+            .fold(0, |n, &ch| n * id.letter.len() + position(id.letter, ch));
+        rest * id.first_letter.len() + position(id.first_letter, bytes[0])
+    }
+
+    #[test]
+    fn distinct() {
+        for id in [Id::new_for_name(), Id::new_for_id()] {
+            let template = Id {
+                first_letter: id.first_letter,
+                letter: id.letter,
+                number: 0,
+            };
+            let mut seen = HashSet::new();
+            for (number, s) in id.take(5000).enumerate() {
+                assert_eq!(parse(&template, &s), number);
+                assert!(seen.insert(s));
+            }
+        }
+    }
+
+    #[test]
+    fn exhaustion() {
+        let mut id = Id::new_for_id();
+        assert_eq!(id.len(), usize::MAX);
+        id.number = usize::MAX - 2;
+        assert_eq!(id.len(), 2);
+        assert_eq!(id.by_ref().count(), 2);
+        assert_eq!(id.len(), 0);
+        assert!(id.next().is_none());
+    }
 }
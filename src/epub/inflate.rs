@@ -0,0 +1,260 @@
+use super::error::{Error, Result};
+
+// 長さと距離の符号の基準値と追加のビット数 (RFC 1951 3.2.5)
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+// 符号長の符号の長さが並ぶ順序
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+// 下位のビットから順に読む
+struct Bits<'a> {
+    data: &'a [u8],
+    position: usize,
+    buffer: u32,
+    count: u32,
+}
+
+impl Bits<'_> {
+    fn take(&mut self, n: u32) -> Result<u32> {
+        while self.count < n {
+            let byte = *self.data.get(self.position).ok_or(Error::InvalidArchive)?;
+            self.position += 1;
+            self.buffer |= u32::from(byte) << self.count;
+            self.count += 8;
+        }
+        let value = self.buffer & ((1 << n) - 1);
+        self.buffer >>= n;
+        self.count -= n;
+        Ok(value)
+    }
+
+    // 読みかけのバイトの残りを捨てる
+    fn align(&mut self) {
+        self.buffer = 0;
+        self.count = 0;
+    }
+}
+
+// 符号長から組み立てた正準ハフマン符号
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0u16; 16];
+        for &length in lengths {
+            counts[usize::from(length)] += 1;
+        }
+        counts[0] = 0;
+        let mut symbols: Vec<(u8, u16)> = lengths
+            .iter()
+            .enumerate()
+            .filter(|(_, length)| **length != 0)
+            .map(|(symbol, &length)| (length, symbol as u16))
+            .collect();
+        symbols.sort_unstable();
+        Self {
+            counts,
+            symbols: symbols.into_iter().map(|(_, symbol)| symbol).collect(),
+        }
+    }
+
+    fn decode(&self, bits: &mut Bits) -> Result<u16> {
+        let mut code = 0i32;
+        let mut first = 0i32;
+        let mut index = 0i32;
+        for &count in &self.counts[1..] {
+            code |= bits.take(1)? as i32;
+            let count = i32::from(count);
+            if code - first < count {
+                return self
+                    .symbols
+                    .get((index + code - first) as usize)
+                    .copied()
+                    .ok_or(Error::InvalidArchive);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(Error::InvalidArchive)
+    }
+}
+
+fn fixed_tables() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+    (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+}
+
+fn dynamic_tables(bits: &mut Bits) -> Result<(Huffman, Huffman)> {
+    let literals = bits.take(5)? as usize + 257;
+    let distances = bits.take(5)? as usize + 1;
+    let code_lengths = bits.take(4)? as usize + 4;
+    let mut lengths = [0u8; 19];
+    for &i in &CODE_LENGTH_ORDER[..code_lengths] {
+        lengths[i] = bits.take(3)? as u8;
+    }
+    let code = Huffman::new(&lengths);
+    let mut lengths = Vec::with_capacity(literals + distances);
+    while lengths.len() < literals + distances {
+        let (length, repeat) = match code.decode(bits)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => (
+                *lengths.last().ok_or(Error::InvalidArchive)?,
+                3 + bits.take(2)?,
+            ),
+            17 => (0, 3 + bits.take(3)?),
+            _ => (0, 11 + bits.take(7)?),
+        };
+        lengths.extend(std::iter::repeat_n(length, repeat as usize));
+    }
+    if lengths.len() > literals + distances {
+        return Err(Error::InvalidArchive);
+    }
+    Ok((
+        Huffman::new(&lengths[..literals]),
+        Huffman::new(&lengths[literals..]),
+    ))
+}
+
+/// zip の項目の deflate で圧縮された中身を展開する。展開後の大きさが size を超えたら壊れているとみなす
+pub fn inflate(data: &[u8], size: usize) -> Result<Vec<u8>> {
+    let mut bits = Bits {
+        data,
+        position: 0,
+        buffer: 0,
+        count: 0,
+    };
+    let mut out = Vec::with_capacity(size);
+    loop {
+        let last = bits.take(1)? == 1;
+        match bits.take(2)? {
+            0 => {
+                bits.align();
+                let header = data
+                    .get(bits.position..bits.position + 4)
+                    .ok_or(Error::InvalidArchive)?;
+                let length = usize::from(u16::from_le_bytes([header[0], header[1]]));
+                if u16::from_le_bytes([header[2], header[3]]) != !(length as u16) {
+                    return Err(Error::InvalidArchive);
+                }
+                let start = bits.position + 4;
+                out.extend_from_slice(
+                    data.get(start..start + length)
+                        .ok_or(Error::InvalidArchive)?,
+                );
+                bits.position = start + length;
+            }
+            kind @ (1 | 2) => {
+                let (literal, distance) = if kind == 1 {
+                    fixed_tables()
+                } else {
+                    dynamic_tables(&mut bits)?
+                };
+                loop {
+                    let symbol = usize::from(literal.decode(&mut bits)?);
+                    if symbol < 256 {
+                        out.push(symbol as u8);
+                    } else if symbol == 256 {
+                        break;
+                    } else {
+                        let i = symbol - 257;
+                        let length = usize::from(*LENGTH_BASE.get(i).ok_or(Error::InvalidArchive)?)
+                            + bits.take(u32::from(LENGTH_EXTRA[i]))? as usize;
+                        let i = usize::from(distance.decode(&mut bits)?);
+                        let back = usize::from(*DISTANCE_BASE.get(i).ok_or(Error::InvalidArchive)?)
+                            + bits.take(u32::from(DISTANCE_EXTRA[i]))? as usize;
+                        let start = out.len().checked_sub(back).ok_or(Error::InvalidArchive)?;
+                        // 参照先と重なることがあるので一バイトずつ写す
+                        for j in 0..length {
+                            out.push(out[start + j]);
+                        }
+                    }
+                    if out.len() > size {
+                        return Err(Error::InvalidArchive);
+                    }
+                }
+            }
+            _ => return Err(Error::InvalidArchive),
+        }
+        if out.len() > size {
+            return Err(Error::InvalidArchive);
+        }
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::inflate;
+
+    // 試験の圧縮データは zlib で作った
+    #[test]
+    fn stored() {
+        const DATA: &[u8] = &[
+            0x01, 0x06, 0x00, 0xf9, 0xff, 0x73, 0x74, 0x6f, 0x72, 0x65, 0x64,
+        ];
+        assert_eq!(inflate(DATA, 6).unwrap(), b"stored");
+        assert!(inflate(&DATA[..8], 6).is_err());
+    }
+
+    #[test]
+    fn fixed() {
+        // 'a' 40 個で、一文字目の後は距離 1 の参照で繰り返す
+        const DATA: &[u8] = &[0x4b, 0x4c, 0x24, 0x0e, 0x00, 0x00];
+        assert_eq!(inflate(DATA, 40).unwrap(), [b'a'; 40]);
+        // 宣言された大きさを超えて展開しない
+        assert!(inflate(DATA, 39).is_err());
+    }
+
+    #[test]
+    fn dynamic() {
+        const DATA: &[u8] = &[
+            0x4d, 0x8c, 0x41, 0x0e, 0x80, 0x20, 0x10, 0x03, 0xbf, 0x6e, 0xf7, 0xa6, 0x40, 0x62,
+            0xa2, 0x77, 0xbc, 0x11, 0x83, 0x4f, 0x30, 0x7c, 0xa6, 0x21, 0xeb, 0x33, 0x04, 0x0c,
+            0xc4, 0x5b, 0x77, 0xda, 0x1d, 0x22, 0x10, 0x42, 0x31, 0xc4, 0x49, 0x6c, 0x44, 0x09,
+            0x46, 0x97, 0x8b, 0x48, 0xc4, 0x51, 0x42, 0x5e, 0xd3, 0x73, 0x97, 0xca, 0x7c, 0xa1,
+            0x9f, 0x81, 0x93, 0x34, 0xe8, 0xf2, 0xec, 0xc6, 0x4c, 0x6d, 0xac, 0xbc, 0x56, 0x3f,
+            0x9b, 0x8d, 0xba, 0x7b, 0x8a, 0x25, 0x7c, 0x7f, 0x6f, 0xf2, 0x0e, 0x87, 0xe7, 0x05,
+        ];
+        const TEXT: &str = "であるどこかか犬まだ犬吾輩か吾輩吾輩で。か名前犬吾輩猫。。どこかか猫生れた吾輩まだ生れたか名前";
+        assert_eq!(
+            String::from_utf8(inflate(DATA, TEXT.len()).unwrap()).unwrap(),
+            TEXT
+        );
+        assert!(inflate(&DATA[..40], TEXT.len()).is_err());
+    }
+
+    #[test]
+    fn broken() {
+        assert!(inflate(&[], 0).is_err());
+        // 種類 3 の区画は存在しない
+        assert!(inflate(&[0x07], 0).is_err());
+        // 書き出す前の位置を参照する
+        assert!(inflate(&[0x03, 0x02, 0x00], 10).is_err());
+    }
+}
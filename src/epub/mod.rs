@@ -1,9 +1,16 @@
+use std::borrow::Cow;
 use std::fs::File;
-use zip_builder::{Level, Result, ZipArchive};
+use std::io::Read;
+use zip_builder::{Level, ZipArchive};
+mod error;
 mod escape;
 mod id;
+mod inflate;
+pub mod read;
 pub mod time;
+pub mod wellformed;
 use super::uuid::UUIDv5;
+pub use error::{Error, Result};
 pub use escape::Escape;
 pub use id::Id;
 use utcdatetime::DateTime;
@@ -12,11 +19,27 @@ use utcdatetime::DateTime;
 pub enum ReferenceType {
     Title,
     Text,
+    /// 奥付など本文の後に置くページ
+    Backmatter,
     Navi,
     Image,
+    /// 表紙の画像
+    Cover,
     Style,
 }
 
+impl ReferenceType {
+    // 本文の body に付ける構造の意味
+    fn epub_type(&self) -> Option<&'static str> {
+        match self {
+            ReferenceType::Title => Some("titlepage"),
+            ReferenceType::Text => Some("bodymatter"),
+            ReferenceType::Backmatter => Some("backmatter"),
+            _ => None,
+        }
+    }
+}
+
 #[derive(PartialEq)]
 pub enum MediaType {
     Css,
@@ -24,6 +47,8 @@ pub enum MediaType {
     Jpg,
     Png,
     Gif,
+    Webp,
+    Svg,
 }
 
 impl From<&MediaType> for &str {
@@ -33,7 +58,9 @@ impl From<&MediaType> for &str {
             MediaType::Xhtml => "application/xhtml+xml",
             MediaType::Jpg => "image/jpeg",
             MediaType::Png => "image/png",
-            MediaType::Gif => "iamge/gif",
+            MediaType::Gif => "image/gif",
+            MediaType::Webp => "image/webp",
+            MediaType::Svg => "image/svg+xml",
         }
     }
 }
@@ -50,6 +77,38 @@ impl std::fmt::Display for MediaType {
     }
 }
 
+/// 収録するファイルの圧縮のしかた
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Compression {
+    /// 既に圧縮されている画像はそのまま格納し、それ以外は圧縮する
+    Auto,
+    /// 何も圧縮しない
+    Store,
+    /// 画像も含めて全て圧縮する
+    Deflate,
+}
+
+impl Compression {
+    fn level(&self, media_type: &MediaType) -> Level {
+        match self {
+            Compression::Store => Level::Raw,
+            Compression::Deflate => Level::High,
+            Compression::Auto => match media_type {
+                MediaType::Jpg | MediaType::Png | MediaType::Gif | MediaType::Webp => Level::Raw,
+                MediaType::Css | MediaType::Xhtml | MediaType::Svg => Level::High,
+            },
+        }
+    }
+
+    // 目録や付属のファイルのような、メディアタイプを持たない文字のファイルの圧縮
+    fn text_level(&self) -> Level {
+        match self {
+            Compression::Store => Level::Raw,
+            Compression::Deflate | Compression::Auto => Level::High,
+        }
+    }
+}
+
 struct ContentMetadata {
     name: String,
     title: String,
@@ -66,11 +125,29 @@ struct ResourceMetadata {
     id: String,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Direction {
     Rtl,
     Ltr,
 }
 
+/// rtl と ltr のどちらでもない書字方向の指定
+#[derive(Debug, PartialEq)]
+pub struct ParseDirectionError;
+
+impl std::str::FromStr for Direction {
+    type Err = ParseDirectionError;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("rtl") {
+            Ok(Direction::Rtl)
+        } else if s.eq_ignore_ascii_case("ltr") {
+            Ok(Direction::Ltr)
+        } else {
+            Err(ParseDirectionError)
+        }
+    }
+}
+
 impl std::fmt::Display for Direction {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -80,17 +157,103 @@ impl std::fmt::Display for Direction {
     }
 }
 
+/// 本全体の書字方向と異なる向きで組むページの指定
+pub enum WritingMode {
+    HorizontalTb,
+}
+
+impl std::fmt::Display for WritingMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WritingMode::HorizontalTb => write!(f, "horizontal-tb"),
+        }
+    }
+}
+
+// 根の html 要素に書字方向のクラスを付ける。スタイルシートの html.horizontal-tb などで向きを変える
+// 壊れた XHTML は読書端末で開くまで気付けないので、ここで止める
+fn check_xhtml(name: &str, media_type: &MediaType, body: &[u8]) -> Result<()> {
+    if *media_type == MediaType::Xhtml
+        && !std::str::from_utf8(body).is_ok_and(wellformed::is_well_formed)
+    {
+        return Err(Error::MalformedXhtml(name.to_string()));
+    }
+    Ok(())
+}
+
+// 参照の種類に応じた epub:type を body に付ける。名前空間の宣言がなければ html に加える
+fn set_epub_type<'b>(
+    body: &'b [u8],
+    media_type: &MediaType,
+    reftype: &ReferenceType,
+) -> Cow<'b, [u8]> {
+    let (Some(epub_type), MediaType::Xhtml) = (reftype.epub_type(), media_type) else {
+        return Cow::Borrowed(body);
+    };
+    let Ok(text) = std::str::from_utf8(body) else {
+        return Cow::Borrowed(body);
+    };
+    // 本文中に epub:type と書かれていても、body の開始タグに無ければ付ける
+    let Some((before, rest)) = text
+        .split_once("<body")
+        .filter(|(_, rest)| !rest.split('>').next().unwrap_or("").contains("epub:type"))
+    else {
+        return Cow::Borrowed(body);
+    };
+    let before = match before.split_once("<html") {
+        Some((head, html)) if !html.contains("xmlns:epub") => [
+            head,
+            r#"<html xmlns:epub="http://www.idpf.org/2007/ops""#,
+            html,
+        ]
+        .concat(),
+        _ => before.to_string(),
+    };
+    Cow::Owned(
+        [&before, r#"<body epub:type=""#, epub_type, "\"", rest]
+            .concat()
+            .into_bytes(),
+    )
+}
+
+// 根の html 要素に書字方向のクラスを付ける。スタイルシートの html.horizontal-tb などで向きを変える
+// 既に class 属性があればその先頭に加える
+fn set_writing_mode(body: &str, writing_mode: &WritingMode) -> String {
+    let Some((before, after)) = body.split_once("<html") else {
+        return body.to_string();
+    };
+    let tag = &after[..after.find('>').unwrap_or(after.len())];
+    let mode = writing_mode.to_string();
+    match tag.find("class=\"") {
+        Some(at) => {
+            let (head, tail) = after.split_at(at + "class=\"".len());
+            [before, "<html", head, &mode, " ", tail].concat()
+        }
+        None => [before, "<html class=\"", &mode, "\"", after].concat(),
+    }
+}
+
 pub struct Epub<'a> {
     zip: ZipArchive<'a, File>,
+    compression: Compression,
     title: String,
-    author: Option<(String, String)>,
+    author: Option<(String, Option<String>)>,
     modified: Option<DateTime>,
     description: Option<String>,
     source: Option<String>,
+    identifier: Option<String>,
+    series: Option<(String, u32)>,
+    language: String,
+    publisher: Option<String>,
+    toc_depth: Option<u32>,
+    subjects: Vec<String>,
+    char_count: Option<u32>,
     contents: Vec<ContentMetadata>,
     resources: Vec<ResourceMetadata>,
     direction: Direction,
     id_iter: Id,
+    name_iter: Id,
+    buffer: Vec<u8>,
 }
 
 struct Manifest<'a, 'b> {
@@ -122,6 +285,12 @@ impl<'a, 'b> std::fmt::Display for Manifest<'a, 'b> {
                     r#"<item media-type="{}" id="{}" href="{}" properties="nav"/>"#,
                     x.media_type, x.id, x.name
                 )?;
+            } else if x.reftype == ReferenceType::Cover {
+                write!(
+                    f,
+                    r#"<item media-type="{}" id="{}" href="{}" properties="cover-image"/>"#,
+                    x.media_type, x.id, x.name
+                )?;
             } else {
                 write!(
                     f,
@@ -166,33 +335,45 @@ impl<'a, 'b> std::fmt::Display for Topic<'a, 'b> {
         )?;
 
         let mut level: u32 = 0;
-        for i in self.epub.contents.as_slice() {
-            if i.level > level {
-                for _ in 0..(i.level - level) {
-                    write!(f, "<ol>")?;
-                }
-                write!(f, "<li>")?;
-            } else if i.level == level {
+        for i in self
+            .epub
+            .contents
+            .iter()
+            .filter(|x| self.epub.toc_depth.is_none_or(|depth| x.level <= depth))
+        {
+            // 入れ子は一段ずつしか深くできないので、飛ばされた階層は直前の項目の下に入れる
+            let next = i.level.clamp(1, level + 1);
+            if next > level {
+                write!(f, "<ol><li>")?;
+            } else if next == level {
                 write!(f, "</li><li>")?;
-            } else if i.level < level {
+            } else {
                 write!(f, "</li>")?;
-                for _ in 0..(level - i.level) {
+                for _ in 0..(level - next) {
                     write!(f, "</ol></li>")?;
                 }
                 write!(f, "<li>")?;
             }
 
             write!(f, r#"<a href="{}">{}</a>"#, i.name, i.title.escape())?;
-            level = i.level;
+            level = next;
         }
         for _ in 0..level {
             write!(f, "</li></ol>")?;
         }
 
-        write!(f, r#"</nav><nav epub:type = "landmarks"><ol>"#)?;
+        write!(f, "</nav>")?;
 
-        for i in self.epub.contents.as_slice() {
-            if i.reftype == ReferenceType::Title {
+        // 空の ol は許されないので、表題のページがなければ landmarks ごと省く
+        let mut titles = self
+            .epub
+            .contents
+            .iter()
+            .filter(|x| x.reftype == ReferenceType::Title)
+            .peekable();
+        if titles.peek().is_some() {
+            write!(f, r#"<nav epub:type = "landmarks"><ol>"#)?;
+            for i in titles {
                 write!(
                     f,
                     r#"<li><a epub:type="titlepage" href="{}">{}</a></li>"#,
@@ -200,8 +381,9 @@ impl<'a, 'b> std::fmt::Display for Topic<'a, 'b> {
                     i.title.escape()
                 )?;
             }
+            write!(f, "</ol></nav>")?;
         }
-        write!(f, r#"</ol></nav></body></html>"#)?;
+        write!(f, "</body></html>")?;
         Ok(())
     }
 }
@@ -217,15 +399,25 @@ impl<'a> Epub<'a> {
         )?;
         Ok(Epub {
             zip,
+            compression: Compression::Auto,
             title: String::new(),
             author: None,
             modified: None,
             description: None,
             source: None,
+            identifier: None,
+            series: None,
+            language: "ja".to_string(),
+            publisher: None,
+            toc_depth: None,
+            subjects: vec![],
+            char_count: None,
             contents: vec![],
             resources: vec![],
             direction: Direction::Rtl,
             id_iter: Id::new_for_id(),
+            name_iter: Id::new_for_name(),
+            buffer: vec![],
         })
     }
 
@@ -234,7 +426,8 @@ impl<'a> Epub<'a> {
         self
     }
 
-    pub fn set_author(&mut self, author: String, yomigana: String) -> &mut Self {
+    /// 作者名と、分かれば並べ替えに使う読み仮名を設定する
+    pub fn set_author(&mut self, author: String, yomigana: Option<String>) -> &mut Self {
         self.author = Some((author, yomigana));
         self
     }
@@ -254,11 +447,57 @@ impl<'a> Epub<'a> {
         self
     }
 
+    pub fn set_series(&mut self, name: String, index: u32) -> &mut Self {
+        self.series = Some((name, index));
+        self
+    }
+
+    /// 作品のキーワードで、それぞれを dc:subject として記録する
+    pub fn set_subjects(&mut self, subjects: Vec<String>) -> &mut Self {
+        self.subjects = subjects;
+        self
+    }
+
+    /// 目次に載せる階層の深さ。浅い階層に収まらないページも本文には含まれる
+    pub fn set_toc_depth(&mut self, depth: u32) -> &mut Self {
+        self.toc_depth = Some(depth);
+        self
+    }
+
+    /// 出典の URL から作る UUID の代わりに使う識別子
+    pub fn set_identifier(&mut self, identifier: String) -> &mut Self {
+        self.identifier = Some(identifier);
+        self
+    }
+
+    pub fn set_language(&mut self, language: String) -> &mut Self {
+        self.language = language;
+        self
+    }
+
+    pub fn set_publisher(&mut self, publisher: String) -> &mut Self {
+        self.publisher = Some(publisher);
+        self
+    }
+
+    pub fn set_char_count(&mut self, char_count: u32) -> &mut Self {
+        self.char_count = Some(char_count);
+        self
+    }
+
+    pub fn set_compression(&mut self, compression: Compression) -> &mut Self {
+        self.compression = compression;
+        self
+    }
+
     pub fn set_direction(&mut self, dir: Direction) -> &mut Self {
         self.direction = dir;
         self
     }
 
+    /// 本文を末尾に追加する
+    ///
+    /// spine と目次は追加した順 (insert_content_at で挿入した場合はその位置) に並ぶ
     pub fn add_content(
         &mut self,
         name: &str,
@@ -268,18 +507,168 @@ impl<'a> Epub<'a> {
         reftype: ReferenceType,
         body: &[u8],
     ) -> Result<&mut Self> {
-        self.zip.add_entry(name, body, Level::High)?;
-        self.contents.push(ContentMetadata {
-            name: name.into(),
-            title: title.into(),
+        self.insert_content_at(
+            self.contents.len(),
+            name,
+            title,
             media_type,
+            level,
             reftype,
+            body,
+        )
+    }
+
+    /// 本文を index 番目に挿入する。index が本文の数より大きければ末尾に追加する
+    ///
+    /// manifest の id は位置ではなく追加したときに割り当てるので、挿入しても既存の本文の id は変わらない
+    #[allow(clippy::too_many_arguments)]
+    pub fn insert_content_at(
+        &mut self,
+        index: usize,
+        name: &str,
+        title: &str,
+        media_type: MediaType,
+        level: u32,
+        reftype: ReferenceType,
+        body: &[u8],
+    ) -> Result<&mut Self> {
+        self.insert_content_from(index, name, title, media_type, level, reftype, body)
+    }
+
+    // 挿絵の名前は Epub の外で割り当てられるので、本文や他の資源と重ならないか確かめる
+    fn check_name(&self, name: &str) -> Result<()> {
+        if self.contents.iter().any(|x| x.name == name) || self.has_resource(name) {
+            return Err(Error::DuplicateName(name.to_string()));
+        }
+        Ok(())
+    }
+
+    /// 本文を読み出し元から読み込んで末尾に追加する
+    ///
+    /// 読み込みには Epub が持つ作業領域を使い回すので、呼び出し側で一話ごとに文字列を組み立てる必要はない
+    pub fn add_content_from(
+        &mut self,
+        name: &str,
+        title: &str,
+        media_type: MediaType,
+        level: u32,
+        reftype: ReferenceType,
+        body: impl Read,
+    ) -> Result<&mut Self> {
+        self.insert_content_from(
+            self.contents.len(),
+            name,
+            title,
+            media_type,
             level,
-            id: self.id_iter.next().unwrap(),
-        });
+            reftype,
+            body,
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn insert_content_from(
+        &mut self,
+        index: usize,
+        name: &str,
+        title: &str,
+        media_type: MediaType,
+        level: u32,
+        reftype: ReferenceType,
+        body: impl Read,
+    ) -> Result<&mut Self> {
+        self.check_name(name)?;
+        let buffer = self.read_into_buffer(body)?;
+        let annotated = set_epub_type(&buffer, &media_type, &reftype);
+        let result = check_xhtml(name, &media_type, &annotated)
+            .and_then(|_| self.write_entry(name, &media_type, &annotated));
+        drop(annotated);
+        self.buffer = buffer;
+        result?;
+        self.push_content(index, name, title, media_type, level, reftype);
         Ok(self)
     }
 
+    // zip-builder は項目の中身を一括で受け取るので、読み出し元の中身はいったん作業領域に溜める
+    // 使い終わった作業領域は self.buffer に戻すこと
+    fn read_into_buffer(&mut self, mut body: impl Read) -> Result<Vec<u8>> {
+        let mut buffer = std::mem::take(&mut self.buffer);
+        buffer.clear();
+        body.read_to_end(&mut buffer)?;
+        Ok(buffer)
+    }
+
+    fn write_entry(&mut self, name: &str, media_type: &MediaType, body: &[u8]) -> Result<()> {
+        self.zip
+            .add_entry(name, body, self.compression.level(media_type))?;
+        Ok(())
+    }
+
+    fn push_content(
+        &mut self,
+        index: usize,
+        name: &str,
+        title: &str,
+        media_type: MediaType,
+        level: u32,
+        reftype: ReferenceType,
+    ) {
+        self.contents.insert(
+            index.min(self.contents.len()),
+            ContentMetadata {
+                name: name.into(),
+                title: title.into(),
+                media_type,
+                reftype,
+                level,
+                id: self.id_iter.next().unwrap(),
+            },
+        );
+    }
+
+    /// 本文のページを追加して、割り当てたファイル名を返す
+    ///
+    /// ファイル名は Epub の中で一意に割り当てるので呼び出し側で重複を気にする必要はない
+    pub fn add_page(
+        &mut self,
+        title: &str,
+        level: u32,
+        reftype: ReferenceType,
+        body: &[u8],
+    ) -> Result<String> {
+        self.add_page_from(title, level, reftype, body)
+    }
+
+    /// 本文のページを読み出し元から読み込んで追加して、割り当てたファイル名を返す
+    pub fn add_page_from(
+        &mut self,
+        title: &str,
+        level: u32,
+        reftype: ReferenceType,
+        body: impl Read,
+    ) -> Result<String> {
+        let name = format!("{}.xhtml", self.name_iter.next().unwrap());
+        self.add_content_from(&name, title, MediaType::Xhtml, level, reftype, body)?;
+        Ok(name)
+    }
+
+    /// 書字方向を指定して本文のページを追加する
+    pub fn add_page_with_writing_mode(
+        &mut self,
+        title: &str,
+        level: u32,
+        reftype: ReferenceType,
+        writing_mode: WritingMode,
+        body: &str,
+    ) -> Result<String> {
+        self.add_page(
+            title,
+            level,
+            reftype,
+            set_writing_mode(body, &writing_mode).as_bytes(),
+        )
+    }
+
     pub fn add_resource(
         &mut self,
         name: &str,
@@ -287,14 +676,68 @@ impl<'a> Epub<'a> {
         reftype: ReferenceType,
         body: &[u8],
     ) -> Result<&mut Self> {
-        self.zip.add_entry(name, body, Level::High)?;
+        self.add_resource_from(name, media_type, reftype, body)
+    }
+
+    /// 資源を読み出し元から読み込んで追加する
+    pub fn add_resource_from(
+        &mut self,
+        name: &str,
+        media_type: MediaType,
+        reftype: ReferenceType,
+        body: impl Read,
+    ) -> Result<&mut Self> {
+        self.check_name(name)?;
+        let buffer = self.read_into_buffer(body)?;
+        let result = self.write_entry(name, &media_type, &buffer);
+        self.buffer = buffer;
+        result?;
+        self.push_resource(name, media_type, reftype);
+        Ok(self)
+    }
+
+    /// 目録に載せない付属のファイルを META-INF の下に置く
+    pub fn add_sidecar(&mut self, name: &str, body: &[u8]) -> Result<&mut Self> {
+        self.zip.add_entry(
+            &["META-INF/", name].concat(),
+            body,
+            self.compression.text_level(),
+        )?;
+        Ok(self)
+    }
+
+    /// 同じ名前の資源を追加済みかどうか
+    pub fn has_resource(&self, name: &str) -> bool {
+        self.resources.iter().any(|x| x.name == name)
+    }
+
+    fn push_resource(&mut self, name: &str, media_type: MediaType, reftype: ReferenceType) {
         self.resources.push(ResourceMetadata {
             name: name.into(),
             media_type,
             reftype,
             id: self.id_iter.next().unwrap(),
         });
-        Ok(self)
+    }
+
+    /// 追加した本文の名前と目次の階層
+    #[cfg(test)]
+    pub fn content_levels(&self) -> Vec<(&str, u32)> {
+        self.contents
+            .iter()
+            .map(|x| (x.name.as_str(), x.level))
+            .collect()
+    }
+
+    /// spine に並ぶ本文の名前
+    pub fn content_names(&self) -> Vec<&str> {
+        self.contents.iter().map(|x| x.name.as_str()).collect()
+    }
+
+    /// 追加した資源の名前
+    #[cfg(test)]
+    pub fn resource_names(&self) -> Vec<&str> {
+        self.resources.iter().map(|x| x.name.as_str()).collect()
     }
 
     fn make_manifest(&self) -> Manifest<'_, '_> {
@@ -310,24 +753,40 @@ impl<'a> Epub<'a> {
     }
 
     fn make_content(&self) -> String {
+        // 読み仮名が分からなければ file-as は空にせず省く
         let author = if let Some((ref author, ref yomigana)) = self.author {
+            let file_as = yomigana.as_ref().map_or(String::new(), |yomigana| {
+                [
+                    r##"<meta refines="#creator" property="file-as">"##,
+                    &yomigana.escape(),
+                    "</meta>",
+                ]
+                .concat()
+            });
             [r#"<dc:creator id="creator">"#, 
                 &author.escape(),
-                r##"</dc:creator><meta refines="#creator" property="role" scheme="marc:relators">aut</meta><meta refines="#creator" property="file-as">"##,
-                &yomigana.escape(),
-                 r#"</meta>"#].concat()
+                r##"</dc:creator><meta refines="#creator" property="role" scheme="marc:relators">aut</meta>"##,
+                &file_as].concat()
         } else {
             "".to_string()
         };
 
+        // 識別子の指定がなければ出典か、それもなければ表題から UUID を作る
+        let identifier = match (&self.identifier, &self.source) {
+            (Some(identifier), _) => identifier.escape(),
+            (None, Some(source)) => format!("urn:uuid:{}", UUIDv5::new(source.as_bytes()).unwrap()),
+            (None, None) => format!("urn:uuid:{}", UUIDv5::new(self.title.as_bytes()).unwrap()),
+        };
         let source = if let Some(ref source) = self.source {
-            let uuid = UUIDv5::new(source.as_bytes()).unwrap();
             format!(
-                r#"<dc:identifier id="epub-id">urn:uuid:{}</dc:identifier><meta property="dcterms:source">{}</meta>"#,
-                uuid, source
+                r#"<dc:identifier id="epub-id">{}</dc:identifier><meta property="dcterms:source">{}</meta>"#,
+                identifier, source
             )
         } else {
-            "".to_string()
+            format!(
+                r#"<dc:identifier id="epub-id">{}</dc:identifier>"#,
+                identifier
+            )
         };
 
         let modified = if let Some(ref modified) = self.modified {
@@ -345,16 +804,86 @@ impl<'a> Epub<'a> {
             "".to_string()
         };
 
+        // EPUB3 のコレクションと Calibre 独自の形式の両方で出力する
+        let series = if let Some((ref name, index)) = self.series {
+            format!(
+                r##"<meta property="belongs-to-collection" id="series">{0}</meta><meta refines="#series" property="collection-type">series</meta><meta refines="#series" property="group-position">{1}</meta><meta name="calibre:series" content="{0}"/><meta name="calibre:series_index" content="{1}"/>"##,
+                name.escape(),
+                index
+            )
+        } else {
+            "".to_string()
+        };
+
+        let publisher = if let Some(ref publisher) = self.publisher {
+            format!(r#"<dc:publisher>{}</dc:publisher>"#, publisher.escape())
+        } else {
+            "".to_string()
+        };
+
+        let subjects: String = self
+            .subjects
+            .iter()
+            .map(|subject| format!(r#"<dc:subject>{}</dc:subject>"#, subject.escape()))
+            .collect();
+
+        // 文字だけで読める本で、挿絵を収録したときだけ画像も読む手段になる
+        let has_images = self.resources.iter().any(|x| {
+            matches!(
+                x.media_type,
+                MediaType::Jpg | MediaType::Png | MediaType::Gif | MediaType::Webp | MediaType::Svg
+            )
+        });
+        let accessibility = [
+            r#"<meta property="schema:accessMode">textual</meta>"#,
+            if has_images {
+                r#"<meta property="schema:accessMode">visual</meta>"#
+            } else {
+                ""
+            },
+            r#"<meta property="schema:accessibilityFeature">tableOfContents</meta>"#,
+            r#"<meta property="schema:accessibilityFeature">readingOrder</meta>"#,
+            r#"<meta property="schema:accessibilityHazard">none</meta>"#,
+            r#"<meta property="schema:accessibilitySummary">目次と読む順序を備えたリフロー型の文章の本です。</meta>"#,
+        ]
+        .concat();
+
+        // EPUB2 の読書端末向けに表紙の画像を name="cover" でも示す
+        let cover = self
+            .resources
+            .iter()
+            .find(|x| x.reftype == ReferenceType::Cover)
+            .map_or(String::new(), |x| {
+                format!(r#"<meta name="cover" content="{}"/>"#, x.id)
+            });
+
+        // 文字数は独自の接頭辞で記録する
+        let (prefix, length) = if let Some(length) = self.char_count {
+            (
+                r#" prefix="narou: https://syosetu.com/""#,
+                format!(r#"<meta property="narou:length">{}</meta>"#, length),
+            )
+        } else {
+            ("", "".to_string())
+        };
+
         format!(
             include_str!("content.txt"),
             source,
             self.title.escape(),
-            "ja",
+            self.language.escape(),
             author,
             modified,
             description,
             self.make_manifest(),
-            self.make_spine()
+            self.make_spine(),
+            series,
+            publisher,
+            subjects,
+            accessibility,
+            prefix,
+            length,
+            cover
         )
     }
 
@@ -365,9 +894,697 @@ impl<'a> Epub<'a> {
             ReferenceType::Navi,
             self.make_topic().to_string().as_bytes(),
         )?;
-        self.zip
-            .add_entry("content.opf", self.make_content().as_bytes(), Level::High)?;
+        self.zip.add_entry(
+            "content.opf",
+            self.make_content().as_bytes(),
+            self.compression.text_level(),
+        )?;
         self.zip.flush()?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::read;
+    use super::wellformed::is_well_formed;
+    use super::{
+        Compression, Direction, Epub, Error, Id, MediaType, ParseDirectionError, ReferenceType,
+        WritingMode, set_epub_type, set_writing_mode,
+    };
+    use std::collections::HashSet;
+    use std::fs::File;
+    use zip_builder::Level;
+
+    // 一時ファイルに書き出す Epub を組み立てて content.opf の内容を返す
+    fn build_content(name: &str, build: impl FnOnce(&mut Epub)) -> String {
+        let path = std::env::temp_dir().join(name);
+        let mut file = File::create(&path).unwrap();
+        let content = {
+            let mut epub = Epub::new(&mut file).unwrap();
+            build(&mut epub);
+            epub.make_content()
+        };
+        drop(file);
+        std::fs::remove_file(&path).unwrap();
+        content
+    }
+
+    #[test]
+    fn media_type() {
+        assert_eq!(MediaType::Css.to_string(), "text/css");
+        assert_eq!(MediaType::Xhtml.to_string(), "application/xhtml+xml");
+        assert_eq!(MediaType::Jpg.to_string(), "image/jpeg");
+        assert_eq!(MediaType::Png.to_string(), "image/png");
+        assert_eq!(MediaType::Gif.to_string(), "image/gif");
+        assert_eq!(MediaType::Webp.to_string(), "image/webp");
+        assert_eq!(MediaType::Svg.to_string(), "image/svg+xml");
+    }
+
+    #[test]
+    fn cover() {
+        let content = build_content("narou-epub-test-cover.epub", |epub| {
+            epub.add_resource("cover.svg", MediaType::Svg, ReferenceType::Cover, b"<svg/>")
+                .unwrap();
+        });
+        assert!(content.contains(
+            r#"<item media-type="image/svg+xml" id="A" href="cover.svg" properties="cover-image"/>"#
+        ));
+        assert!(content.contains(r#"<meta name="cover" content="A"/>"#));
+        assert!(content.contains(r#"<meta property="schema:accessMode">visual</meta>"#));
+        assert!(is_well_formed(&content));
+
+        let content = build_content("narou-epub-test-no-cover.epub", |_| {});
+        assert!(!content.contains("cover"));
+    }
+
+    #[test]
+    fn author() {
+        let content = build_content("narou-epub-test-author.epub", |epub| {
+            epub.set_author("作者".to_string(), Some("さくしゃ".to_string()));
+        });
+        assert!(
+            content.contains(r##"<meta refines="#creator" property="file-as">さくしゃ</meta>"##)
+        );
+        // 読み仮名が分からなければ file-as を付けない
+        let content = build_content("narou-epub-test-author.epub", |epub| {
+            epub.set_author("作者".to_string(), None);
+        });
+        assert!(content.contains(r#"<dc:creator id="creator">作者</dc:creator>"#));
+        assert!(!content.contains("file-as"));
+        assert!(is_well_formed(&content));
+    }
+
+    #[test]
+    fn char_count() {
+        let content = build_content("narou-epub-test-char-count.epub", |epub| {
+            epub.set_char_count(123456);
+        });
+        assert!(content.contains(r#"<package version="3.0" xmlns="http://www.idpf.org/2007/opf" unique-identifier="epub-id" prefix="narou: https://syosetu.com/">"#));
+        assert!(content.contains(r#"<meta property="narou:length">123456</meta>"#));
+        assert!(is_well_formed(&content));
+
+        let content = build_content("narou-epub-test-no-char-count.epub", |_| {});
+        assert!(!content.contains("narou:"));
+    }
+
+    #[test]
+    fn series() {
+        let content = build_content("narou-epub-test-series.epub", |epub| {
+            epub.set_series("長編 & 続編".to_string(), 2);
+        });
+        assert!(content.contains(
+            r#"<meta property="belongs-to-collection" id="series">長編 &amp; 続編</meta>"#
+        ));
+        assert!(
+            content.contains(r##"<meta refines="#series" property="group-position">2</meta>"##)
+        );
+        assert!(content.contains(r#"<meta name="calibre:series" content="長編 &amp; 続編"/>"#));
+        assert!(content.contains(r#"<meta name="calibre:series_index" content="2"/>"#));
+
+        let content = build_content("narou-epub-test-no-series.epub", |_| {});
+        assert!(!content.contains("series"));
+    }
+
+    #[test]
+    fn language_and_publisher() {
+        let content = build_content("narou-epub-test-language.epub", |epub| {
+            epub.set_language("en".to_string());
+            epub.set_publisher("小説家に<なろう>".to_string());
+        });
+        assert!(content.contains("<dc:language>en</dc:language>"));
+        assert!(content.contains("<dc:publisher>小説家に&lt;なろう&gt;</dc:publisher>"));
+
+        let content = build_content("narou-epub-test-default-language.epub", |_| {});
+        assert!(content.contains("<dc:language>ja</dc:language>"));
+        assert!(!content.contains("dc:publisher"));
+    }
+
+    #[test]
+    fn identifier() {
+        let content = build_content("narou-epub-test-identifier.epub", |epub| {
+            epub.set_source("https://ncode.syosetu.com/n1234a/".to_string());
+            epub.set_identifier("isbn:978-4-00-000000-0".to_string());
+        });
+        assert!(
+            content
+                .contains(r#"<dc:identifier id="epub-id">isbn:978-4-00-000000-0</dc:identifier>"#)
+        );
+        assert!(content.contains(
+            r#"<meta property="dcterms:source">https://ncode.syosetu.com/n1234a/</meta>"#
+        ));
+
+        let content = build_content("narou-epub-test-source-identifier.epub", |epub| {
+            epub.set_source("https://ncode.syosetu.com/n1234a/".to_string());
+        });
+        assert!(content.contains(
+            r#"<dc:identifier id="epub-id">urn:uuid:270c7ebf-c164-5af5-8bda-4fb0e97144dd</dc:identifier>"#
+        ));
+
+        let content = build_content("narou-epub-test-title-identifier.epub", |epub| {
+            epub.set_title("表題".to_string());
+        });
+        assert!(content.contains(
+            r#"<dc:identifier id="epub-id">urn:uuid:df9b1746-8379-592f-b879-65d6f70b723a</dc:identifier>"#
+        ));
+        assert!(!content.contains("dcterms:source"));
+    }
+
+    #[test]
+    fn direction() {
+        assert_eq!("rtl".parse(), Ok(Direction::Rtl));
+        assert_eq!("ltr".parse(), Ok(Direction::Ltr));
+        assert_eq!("LTR".parse(), Ok(Direction::Ltr));
+        assert_eq!("Rtl".parse(), Ok(Direction::Rtl));
+        assert_eq!("ttb".parse::<Direction>(), Err(ParseDirectionError));
+        assert_eq!("".parse::<Direction>(), Err(ParseDirectionError));
+        for direction in [Direction::Rtl, Direction::Ltr] {
+            assert_eq!(direction.to_string().parse(), Ok(direction));
+        }
+    }
+
+    // 画像は圧縮せずに格納し、XHTML は圧縮する
+    #[test]
+    fn compression() {
+        for image in [
+            MediaType::Jpg,
+            MediaType::Png,
+            MediaType::Gif,
+            MediaType::Webp,
+        ] {
+            assert!(matches!(Compression::Auto.level(&image), Level::Raw));
+            assert!(matches!(Compression::Deflate.level(&image), Level::High));
+        }
+        assert!(matches!(
+            Compression::Auto.level(&MediaType::Xhtml),
+            Level::High
+        ));
+        assert!(matches!(
+            Compression::Auto.level(&MediaType::Css),
+            Level::High
+        ));
+        assert!(matches!(
+            Compression::Store.level(&MediaType::Xhtml),
+            Level::Raw
+        ));
+        assert!(matches!(Compression::Auto.text_level(), Level::High));
+        assert!(matches!(Compression::Deflate.text_level(), Level::High));
+        assert!(matches!(Compression::Store.text_level(), Level::Raw));
+    }
+
+    #[test]
+    fn epub_type() {
+        const TITLE: &str = r#"<html xmlns="http://www.w3.org/1999/xhtml" xml:lang="ja"><head><title>表題</title></head><body class="titlepage"><h1>表題</h1></body></html>"#;
+        let page = set_epub_type(TITLE.as_bytes(), &MediaType::Xhtml, &ReferenceType::Title);
+        let page = std::str::from_utf8(&page).unwrap();
+        assert_eq!(
+            page,
+            r#"<html xmlns:epub="http://www.idpf.org/2007/ops" xmlns="http://www.w3.org/1999/xhtml" xml:lang="ja"><head><title>表題</title></head><body epub:type="titlepage" class="titlepage"><h1>表題</h1></body></html>"#
+        );
+        assert!(is_well_formed(page));
+        let page = set_epub_type(
+            b"<html><body></body></html>",
+            &MediaType::Xhtml,
+            &ReferenceType::Text,
+        );
+        assert!(page.starts_with(
+            br#"<html xmlns:epub="http://www.idpf.org/2007/ops"><body epub:type="bodymatter">"#
+        ));
+        let page_with_text = set_epub_type(
+            "<html><body><p>epub:type という属性</p></body></html>".as_bytes(),
+            &MediaType::Xhtml,
+            &ReferenceType::Backmatter,
+        );
+        assert!(
+            std::str::from_utf8(&page_with_text)
+                .unwrap()
+                .contains(r#"<body epub:type="backmatter"><p>epub:type という属性</p>"#)
+        );
+        // 既に付いているものや body のないもの、本文でないものには付けない
+        for (body, media_type, reftype) in [
+            (page.as_ref(), MediaType::Xhtml, ReferenceType::Title),
+            (b"<html/>", MediaType::Xhtml, ReferenceType::Text),
+            (
+                b"<html><body/></html>",
+                MediaType::Xhtml,
+                ReferenceType::Navi,
+            ),
+            (b"body{}", MediaType::Css, ReferenceType::Style),
+        ] {
+            assert_eq!(set_epub_type(body, &media_type, &reftype), body);
+        }
+
+        // 書き出した表題のページに付いている
+        let path = std::env::temp_dir().join("narou-epub-test-epub-type.epub");
+        let mut file = File::create(&path).unwrap();
+        {
+            let mut epub = Epub::new(&mut file).unwrap();
+            epub.add_content(
+                "title.xhtml",
+                "表題",
+                MediaType::Xhtml,
+                1,
+                ReferenceType::Title,
+                TITLE.as_bytes(),
+            )
+            .unwrap();
+            epub.finish().unwrap();
+        }
+        drop(file);
+        let data = std::fs::read(&path).unwrap();
+        let entries = read::entries(&data).unwrap();
+        let title = read::extract(&data, &entries, "title.xhtml").unwrap();
+        assert!(title.contains(r#"<body epub:type="titlepage""#));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn writing_mode() {
+        let page = r#"<html xmlns="http://www.w3.org/1999/xhtml"><body><p>手紙</p></body></html>"#;
+        let horizontal = set_writing_mode(page, &WritingMode::HorizontalTb);
+        assert_eq!(
+            horizontal,
+            r#"<html class="horizontal-tb" xmlns="http://www.w3.org/1999/xhtml"><body><p>手紙</p></body></html>"#
+        );
+        assert!(is_well_formed(&horizontal));
+        // 既にある class 属性に加える
+        let classed = set_writing_mode(
+            r#"<html class="letter" xml:lang="ja"><body class="x"/></html>"#,
+            &WritingMode::HorizontalTb,
+        );
+        assert_eq!(
+            classed,
+            r#"<html class="horizontal-tb letter" xml:lang="ja"><body class="x"/></html>"#
+        );
+        assert!(is_well_formed(&classed));
+        // html の開始タグの外の class 属性には触れない
+        assert_eq!(
+            set_writing_mode(
+                r#"<html><body class="x"/></html>"#,
+                &WritingMode::HorizontalTb
+            ),
+            r#"<html class="horizontal-tb"><body class="x"/></html>"#
+        );
+
+        let path = std::env::temp_dir().join("narou-epub-test-writing-mode.epub");
+        let mut file = File::create(&path).unwrap();
+        {
+            let mut epub = Epub::new(&mut file).unwrap();
+            epub.add_page("本文", 1, ReferenceType::Text, page.as_bytes())
+                .unwrap();
+            epub.add_page_with_writing_mode(
+                "手紙",
+                1,
+                ReferenceType::Text,
+                WritingMode::HorizontalTb,
+                page,
+            )
+            .unwrap();
+            assert_eq!(epub.content_levels().len(), 2);
+        }
+        drop(file);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn subjects() {
+        let content = build_content("narou-epub-test-subjects.epub", |epub| {
+            epub.set_subjects(vec!["異世界".to_string(), "恋愛".to_string()]);
+        });
+        assert_eq!(content.matches("<dc:subject>").count(), 2);
+        assert!(content.contains("<dc:subject>異世界</dc:subject><dc:subject>恋愛</dc:subject>"));
+        let content = build_content("narou-epub-test-no-subjects.epub", |_| {});
+        assert!(!content.contains("dc:subject"));
+    }
+
+    #[test]
+    fn accessibility() {
+        let content = build_content("narou-epub-test-accessibility.epub", |epub| {
+            epub.add_resource("style.css", MediaType::Css, ReferenceType::Style, b"")
+                .unwrap();
+        });
+        assert!(content.contains(r#"<meta property="schema:accessMode">textual</meta>"#));
+        assert!(
+            content
+                .contains(r#"<meta property="schema:accessibilityFeature">tableOfContents</meta>"#)
+        );
+        assert!(
+            content.contains(r#"<meta property="schema:accessibilityFeature">readingOrder</meta>"#)
+        );
+        assert!(content.contains(r#"<meta property="schema:accessibilityHazard">none</meta>"#));
+        assert!(content.contains(r#"<meta property="schema:accessibilitySummary">"#));
+        assert!(!content.contains("visual"));
+
+        let content = build_content("narou-epub-test-accessibility-images.epub", |epub| {
+            epub.add_resource("0.png", MediaType::Png, ReferenceType::Image, b"")
+                .unwrap();
+        });
+        assert_eq!(content.matches("schema:accessMode").count(), 2);
+        assert!(content.contains(r#"<meta property="schema:accessMode">visual</meta>"#));
+        assert!(content.contains(r#"<meta property="schema:accessibilitySummary">"#));
+    }
+
+    #[test]
+    fn title_escape() {
+        let content = build_content("narou-epub-test-title-escape.epub", |epub| {
+            epub.set_title("表題 <第2巻> & 外伝".to_string());
+        });
+        assert!(content.contains("<dc:title>表題 &lt;第2巻&gt; &amp; 外伝</dc:title>"));
+
+        // 引用符や制御文字で content.opf が壊れないこと
+        let content = build_content("narou-epub-test-title-control.epub", |epub| {
+            epub.set_title("吾輩's\0猫".to_string());
+        });
+        assert!(content.contains("<dc:title>吾輩&apos;s猫</dc:title>"));
+        assert!(!content.contains('\0'));
+        assert!(is_well_formed(&content));
+    }
+
+    // 後から挿入した前書きが先頭に並び、既存の本文の id は変わらない
+    #[test]
+    fn insert_content() {
+        let path = std::env::temp_dir().join("narou-epub-test-insert-content.epub");
+        let mut file = File::create(&path).unwrap();
+        {
+            let mut epub = Epub::new(&mut file).unwrap();
+            epub.add_page("第一話", 1, ReferenceType::Text, b"<html/>")
+                .unwrap();
+            epub.add_page("第二話", 1, ReferenceType::Text, b"<html/>")
+                .unwrap();
+            let ids: Vec<String> = epub.contents.iter().map(|x| x.id.clone()).collect();
+            epub.insert_content_at(
+                0,
+                "preface.xhtml",
+                "前書き",
+                MediaType::Xhtml,
+                1,
+                ReferenceType::Text,
+                b"<html/>",
+            )
+            .unwrap();
+            epub.insert_content_at(
+                100,
+                "afterword.xhtml",
+                "後書き",
+                MediaType::Xhtml,
+                1,
+                ReferenceType::Text,
+                b"<html/>",
+            )
+            .unwrap();
+            assert_eq!(
+                epub.content_levels(),
+                [
+                    ("preface.xhtml", 1),
+                    ("0.xhtml", 1),
+                    ("1.xhtml", 1),
+                    ("afterword.xhtml", 1)
+                ]
+            );
+            assert_eq!(epub.contents[1].id, ids[0]);
+            assert_eq!(epub.contents[2].id, ids[1]);
+            let topic = epub.make_topic().to_string();
+            assert!(topic.find("前書き").unwrap() < topic.find("第一話").unwrap());
+            let content = epub.make_content();
+            let preface = format!(r#"<itemref idref="{}""#, epub.contents[0].id);
+            let first = format!(r#"<itemref idref="{}""#, ids[0]);
+            assert!(content.find(&preface).unwrap() < content.find(&first).unwrap());
+        }
+        drop(file);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    // 指定した階層のページを並べた目次
+    fn topic(levels: &[u32]) -> String {
+        let path = std::env::temp_dir().join(format!("narou-epub-test-topic-{:?}.epub", levels));
+        let mut file = File::create(&path).unwrap();
+        let topic = {
+            let mut epub = Epub::new(&mut file).unwrap();
+            for &level in levels {
+                epub.add_page("話", level, ReferenceType::Text, b"<html/>")
+                    .unwrap();
+            }
+            epub.make_topic().to_string()
+        };
+        drop(file);
+        std::fs::remove_file(&path).unwrap();
+        topic
+    }
+
+    #[test]
+    fn topic_nesting() {
+        for levels in [
+            &[1, 1, 1][..],
+            &[1, 2, 1],
+            &[1, 1, 1, 2, 2, 1, 2],
+            &[1, 2, 2, 1, 1],
+            &[2, 2, 1],
+            &[1, 3, 1],
+        ] {
+            let topic = topic(levels);
+            assert!(is_well_formed(&topic), "{:?}: {}", levels, topic);
+            assert!(!topic.contains("<ol><ol>"), "{:?}: {}", levels, topic);
+            assert_eq!(topic.matches("<li>").count(), levels.len());
+            assert_eq!(
+                topic.matches("<ol>").count(),
+                topic.matches("</ol>").count()
+            );
+        }
+        assert!(topic(&[1, 1, 1]).contains(
+            r#"<ol><li><a href="0.xhtml">話</a></li><li><a href="1.xhtml">話</a></li><li><a href="2.xhtml">話</a></li></ol>"#
+        ));
+        assert!(topic(&[1, 2, 1]).contains(
+            r#"<ol><li><a href="0.xhtml">話</a><ol><li><a href="1.xhtml">話</a></li></ol></li><li><a href="2.xhtml">話</a></li></ol>"#
+        ));
+    }
+
+    #[test]
+    fn landmarks() {
+        let path = std::env::temp_dir().join("narou-epub-test-landmarks.epub");
+        let mut file = File::create(&path).unwrap();
+        let (without, with) = {
+            let mut epub = Epub::new(&mut file).unwrap();
+            epub.add_page("話", 1, ReferenceType::Text, b"<html/>")
+                .unwrap();
+            let without = epub.make_topic().to_string();
+            epub.add_page("表題", 1, ReferenceType::Title, b"<html/>")
+                .unwrap();
+            (without, epub.make_topic().to_string())
+        };
+        drop(file);
+        std::fs::remove_file(&path).unwrap();
+        // 表題のページがなければ空の landmarks を作らない
+        assert!(is_well_formed(&without));
+        assert!(!without.contains("landmarks"));
+        assert!(!without.contains("titlepage"));
+        assert!(is_well_formed(&with));
+        assert!(with.contains(r#"<nav epub:type = "landmarks"><ol><li><a epub:type="titlepage" href="1.xhtml">表題</a></li></ol></nav>"#));
+    }
+
+    #[test]
+    fn toc_depth() {
+        let path = std::env::temp_dir().join("narou-epub-test-toc-depth.epub");
+        let mut file = File::create(&path).unwrap();
+        let (full, shallow) = {
+            let mut epub = Epub::new(&mut file).unwrap();
+            for (title, level) in [
+                ("一章", 1),
+                ("一話", 2),
+                ("二話", 2),
+                ("二章", 1),
+                ("三話", 2),
+            ] {
+                epub.add_page(title, level, ReferenceType::Text, b"<html/>")
+                    .unwrap();
+            }
+            let full = epub.make_topic().to_string();
+            epub.set_toc_depth(1);
+            let shallow = epub.make_topic().to_string();
+            (full, shallow)
+        };
+        drop(file);
+        std::fs::remove_file(&path).unwrap();
+        assert!(full.contains("一話") && full.contains("三話"));
+        assert!(shallow.contains(
+            r#"<ol><li><a href="0.xhtml">一章</a></li><li><a href="3.xhtml">二章</a></li></ol>"#
+        ));
+        assert!(!shallow.contains("一話") && !shallow.contains("三話"));
+    }
+
+    #[test]
+    fn malformed_xhtml() {
+        let path = std::env::temp_dir().join("narou-epub-test-malformed.epub");
+        let mut file = File::create(&path).unwrap();
+        {
+            let mut epub = Epub::new(&mut file).unwrap();
+            assert!(
+                epub.add_page(
+                    "話",
+                    1,
+                    ReferenceType::Text,
+                    "<html><p>本文</p></html>".as_bytes()
+                )
+                .is_ok()
+            );
+            assert!(matches!(
+                epub.add_page("話", 1, ReferenceType::Text, "<html><p>本文</html>".as_bytes()),
+                Err(Error::MalformedXhtml(name)) if name == "1.xhtml"
+            ));
+            // XHTML 以外は調べない
+            assert!(
+                epub.add_resource("style.css", MediaType::Css, ReferenceType::Style, b"<")
+                    .is_ok()
+            );
+        }
+        drop(file);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    // 同じ中身なら読み出し元から追加しても出来上がる EPUB は同じになる
+    #[test]
+    fn add_from_reader() {
+        const PAGE: &[u8] = "<html><p>本文</p></html>".as_bytes();
+        const IMAGE: &[u8] = b"\x89PNG\r\n\x1a\n";
+        fn build(name: &str, from_reader: bool) -> Vec<u8> {
+            let path = std::env::temp_dir().join(name);
+            let mut file = File::create(&path).unwrap();
+            {
+                let mut epub = Epub::new(&mut file).unwrap();
+                epub.set_title("題名".to_string())
+                    .set_identifier("urn:uuid:test".to_string());
+                for title in ["一話", "二話"] {
+                    if from_reader {
+                        epub.add_content_from(
+                            &format!("{}.xhtml", title),
+                            title,
+                            MediaType::Xhtml,
+                            1,
+                            ReferenceType::Text,
+                            PAGE,
+                        )
+                        .unwrap();
+                    } else {
+                        epub.add_content(
+                            &format!("{}.xhtml", title),
+                            title,
+                            MediaType::Xhtml,
+                            1,
+                            ReferenceType::Text,
+                            PAGE,
+                        )
+                        .unwrap();
+                    }
+                }
+                if from_reader {
+                    epub.add_resource_from("a.png", MediaType::Png, ReferenceType::Image, IMAGE)
+                        .unwrap();
+                } else {
+                    epub.add_resource("a.png", MediaType::Png, ReferenceType::Image, IMAGE)
+                        .unwrap();
+                }
+                epub.finish().unwrap();
+            }
+            drop(file);
+            let bytes = std::fs::read(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            bytes
+        }
+        assert_eq!(
+            build("narou-epub-test-from-reader.epub", true),
+            build("narou-epub-test-from-slice.epub", false)
+        );
+
+        let path = std::env::temp_dir().join("narou-epub-test-from-reader-malformed.epub");
+        let mut file = File::create(&path).unwrap();
+        {
+            let mut epub = Epub::new(&mut file).unwrap();
+            assert!(matches!(
+                epub.add_content_from(
+                    "1.xhtml",
+                    "話",
+                    MediaType::Xhtml,
+                    1,
+                    ReferenceType::Text,
+                    "<html>".as_bytes()
+                ),
+                Err(Error::MalformedXhtml(name)) if name == "1.xhtml"
+            ));
+            assert!(epub.content_levels().is_empty());
+            // 作業領域を使い回しても前の中身は残らない
+            epub.add_content_from(
+                "2.xhtml",
+                "話",
+                MediaType::Xhtml,
+                1,
+                ReferenceType::Text,
+                PAGE,
+            )
+            .unwrap();
+            assert_eq!(epub.buffer, PAGE);
+        }
+        drop(file);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn unique_names() {
+        let path = std::env::temp_dir().join("narou-epub-test-unique-names.epub");
+        let mut file = File::create(&path).unwrap();
+        let names = {
+            let mut epub = Epub::new(&mut file).unwrap();
+            // 画像の名前は話の取得時に別の Id で割り当てられる
+            let mut image_names = Id::new_for_name();
+            let mut pages = vec![];
+            for chapter in 0..40 {
+                pages.push(
+                    epub.add_page("章", 1, ReferenceType::Text, b"<html/>")
+                        .unwrap(),
+                );
+                for _ in 0..3 {
+                    let name = format!("{}.jpg", image_names.next().unwrap());
+                    epub.add_resource(&name, MediaType::Jpg, ReferenceType::Image, b"")
+                        .unwrap();
+                    pages.push(
+                        epub.add_page("話", 2, ReferenceType::Text, b"<html/>")
+                            .unwrap(),
+                    );
+                }
+                assert_eq!(pages.len(), (chapter + 1) * 4);
+            }
+            epub.finish().unwrap();
+            let names: Vec<String> = epub
+                .contents
+                .iter()
+                .map(|x| x.name.clone())
+                .chain(epub.resources.iter().map(|x| x.name.clone()))
+                .collect();
+            assert!(pages.iter().all(|page| names.contains(page)));
+            // 既にある名前では追加できない
+            assert!(matches!(
+                epub.add_resource("0.jpg", MediaType::Jpg, ReferenceType::Image, b""),
+                Err(Error::DuplicateName(name)) if name == "0.jpg"
+            ));
+            assert!(matches!(
+                epub.add_resource(&pages[0], MediaType::Xhtml, ReferenceType::Image, b""),
+                Err(Error::DuplicateName(_))
+            ));
+            assert!(matches!(
+                epub.add_content(
+                    "0.jpg",
+                    "話",
+                    MediaType::Xhtml,
+                    1,
+                    ReferenceType::Text,
+                    b"<html/>"
+                ),
+                Err(Error::DuplicateName(_))
+            ));
+            names
+        };
+        drop(file);
+        std::fs::remove_file(&path).unwrap();
+        let unique: HashSet<&String> = names.iter().collect();
+        assert_eq!(unique.len(), names.len());
+        assert_eq!(names.len(), 40 * 4 + 40 * 3 + 1);
+    }
+}
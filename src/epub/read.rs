@@ -0,0 +1,233 @@
+use super::error::{Error, Result};
+use super::inflate::inflate;
+use super::time::FromIso8601;
+use std::path::Path;
+use utcdatetime::DateTime;
+
+/// 作成済みの EPUB から読み戻したメタデータ
+#[derive(Debug)]
+pub struct EpubSummary {
+    pub title: String,
+    pub identifier: String,
+    pub modified: Option<DateTime>,
+    // spine の順に並べたコンテンツのファイル名
+    pub contents: Vec<String>,
+}
+
+// zip の中央ディレクトリの項目
+pub(super) struct Entry {
+    name: String,
+    method: u16,
+    compressed_size: usize,
+    size: usize,
+    offset: usize,
+}
+
+fn u16_at(data: &[u8], position: usize) -> Result<u16> {
+    data.get(position..position + 2)
+        .map(|x| u16::from_le_bytes([x[0], x[1]]))
+        .ok_or(Error::InvalidArchive)
+}
+
+fn u32_at(data: &[u8], position: usize) -> Result<u32> {
+    data.get(position..position + 4)
+        .map(|x| u32::from_le_bytes([x[0], x[1], x[2], x[3]]))
+        .ok_or(Error::InvalidArchive)
+}
+
+pub(super) fn entries(data: &[u8]) -> Result<Vec<Entry>> {
+    // 末尾の注釈の分だけ遡って終端レコードを探す
+    let end = (0..data.len().saturating_sub(21))
+        .rev()
+        .take(0x10000)
+        .find(|&i| data[i..].starts_with(&[0x50, 0x4b, 0x05, 0x06]))
+        .ok_or(Error::InvalidArchive)?;
+    let count = u16_at(data, end + 10)?;
+    let mut position = u32_at(data, end + 16)? as usize;
+    let mut entries = vec![];
+    for _ in 0..count {
+        if u32_at(data, position)? != 0x02014b50 {
+            return Err(Error::InvalidArchive);
+        }
+        let name_length = usize::from(u16_at(data, position + 28)?);
+        let name = data
+            .get(position + 46..position + 46 + name_length)
+            .ok_or(Error::InvalidArchive)?;
+        entries.push(Entry {
+            name: String::from_utf8_lossy(name).into_owned(),
+            method: u16_at(data, position + 10)?,
+            compressed_size: u32_at(data, position + 20)? as usize,
+            size: u32_at(data, position + 24)? as usize,
+            offset: u32_at(data, position + 42)? as usize,
+        });
+        position += 46
+            + name_length
+            + usize::from(u16_at(data, position + 30)?)
+            + usize::from(u16_at(data, position + 32)?);
+    }
+    Ok(entries)
+}
+
+pub(super) fn extract(data: &[u8], entries: &[Entry], name: &str) -> Result<String> {
+    let entry = entries
+        .iter()
+        .find(|x| x.name == name)
+        .ok_or(Error::InvalidArchive)?;
+    if u32_at(data, entry.offset)? != 0x04034b50 {
+        return Err(Error::InvalidArchive);
+    }
+    let start = entry.offset
+        + 30
+        + usize::from(u16_at(data, entry.offset + 26)?)
+        + usize::from(u16_at(data, entry.offset + 28)?);
+    let body = data
+        .get(start..start + entry.compressed_size)
+        .ok_or(Error::InvalidArchive)?;
+    let body = match entry.method {
+        0 => body.to_vec(),
+        8 => inflate(body, entry.size)?,
+        _ => return Err(Error::InvalidArchive),
+    };
+    String::from_utf8(body).map_err(|_| Error::InvalidArchive)
+}
+
+// before と after に挟まれた最初の部分
+fn between<'a>(s: &'a str, before: &str, after: &str) -> Option<&'a str> {
+    let (_, rest) = s.split_once(before)?;
+    Some(rest.split_once(after)?.0)
+}
+
+// 要素の属性の値
+fn attribute<'a>(element: &'a str, name: &str) -> Option<&'a str> {
+    between(element, &format!(r#" {}=""#, name), "\"")
+}
+
+// Escape で置き換えた文字を元に戻す
+fn unescape(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+fn parse_content(content: &str) -> Result<EpubSummary> {
+    let title = between(content, "<dc:title>", "</dc:title>").ok_or(Error::InvalidArchive)?;
+    let identifier = between(
+        content,
+        r#"<dc:identifier id="epub-id">"#,
+        "</dc:identifier>",
+    )
+    .ok_or(Error::InvalidArchive)?;
+    let modified = between(content, r#"<meta property="dcterms:modified">"#, "</meta>")
+        .and_then(|x| DateTime::from_iso8601_str(x).ok());
+    let items: Vec<(&str, &str)> = content
+        .split("<item ")
+        .skip(1)
+        .filter_map(|x| {
+            let element = x.split_once("/>")?.0;
+            Some((attribute(element, "id")?, attribute(element, "href")?))
+        })
+        .collect();
+    let contents = content
+        .split("<itemref")
+        .skip(1)
+        .map(|x| {
+            let idref = attribute(x, "idref").ok_or(Error::InvalidArchive)?;
+            items
+                .iter()
+                .find(|(id, _)| *id == idref)
+                .map(|(_, href)| unescape(href))
+                .ok_or(Error::InvalidArchive)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(EpubSummary {
+        title: unescape(title),
+        identifier: unescape(identifier),
+        modified,
+        contents,
+    })
+}
+
+/// EPUB を開いて META-INF/container.xml から辿った content.opf のメタデータを読む
+pub fn read(path: &Path) -> Result<EpubSummary> {
+    let data = std::fs::read(path)?;
+    let entries = entries(&data)?;
+    let container = extract(&data, &entries, "META-INF/container.xml")?;
+    let path = between(&container, r#" full-path=""#, "\"").ok_or(Error::InvalidArchive)?;
+    parse_content(&extract(&data, &entries, &unescape(path))?)
+}
+
+/// EPUB の META-INF の下に置いた付属のファイルを読む。無ければ None
+pub fn read_sidecar(path: &Path, name: &str) -> Result<Option<String>> {
+    let data = std::fs::read(path)?;
+    let entries = entries(&data)?;
+    let name = ["META-INF/", name].concat();
+    if entries.iter().any(|x| x.name == name) {
+        extract(&data, &entries, &name).map(Some)
+    } else {
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::time::FromJST;
+    use super::super::{Epub, Error, ReferenceType};
+    use super::{read, read_sidecar};
+    use std::fs::File;
+    use utcdatetime::DateTime;
+
+    #[test]
+    fn read_back() {
+        let path = std::env::temp_dir().join("narou-epub-test-read.epub");
+        let modified = DateTime::from_jst_str("2023-04-05 06:07:08").unwrap();
+        let mut file = File::create(&path).unwrap();
+        let mut epub = Epub::new(&mut file).unwrap();
+        epub.set_title("表題 & <副題>'\0".to_string())
+            .set_identifier("isbn:978-4-00-000000-0".to_string())
+            .set_modified(modified.clone());
+        let first = epub
+            .add_page("第一話", 1, ReferenceType::Text, b"<html/>")
+            .unwrap();
+        let second = epub
+            .add_page("第二話", 1, ReferenceType::Text, b"<html/>")
+            .unwrap();
+        epub.finish().unwrap();
+        drop(epub);
+        drop(file);
+
+        let summary = read(&path).unwrap();
+        // 制御文字は書き出すときに取り除かれる
+        assert_eq!(summary.title, "表題 & <副題>'");
+        assert_eq!(summary.identifier, "isbn:978-4-00-000000-0");
+        assert_eq!(summary.modified.unwrap().to_string(), modified.to_string());
+        assert_eq!(summary.contents, [first, second]);
+
+        // zip でないファイルは読めない
+        std::fs::write(&path, b"not a zip").unwrap();
+        assert!(matches!(read(&path), Err(Error::InvalidArchive)));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn sidecar() {
+        let path = std::env::temp_dir().join("narou-epub-test-sidecar.epub");
+        let mut file = File::create(&path).unwrap();
+        let mut epub = Epub::new(&mut file).unwrap();
+        epub.add_sidecar("memo.txt", "1\t覚え書き\n".as_bytes())
+            .unwrap();
+        epub.finish().unwrap();
+        drop(epub);
+        drop(file);
+
+        assert_eq!(
+            read_sidecar(&path, "memo.txt").unwrap().as_deref(),
+            Some("1\t覚え書き\n")
+        );
+        assert!(read_sidecar(&path, "missing.txt").unwrap().is_none());
+        // 付属のファイルは目録に載らない
+        assert!(read(&path).unwrap().contents.is_empty());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,3 +1,4 @@
+use std::cmp::Ordering;
 use utcdatetime::{DateTime, DateTimeParseError};
 
 const fn is_leap_year(year: u16) -> bool {
@@ -6,6 +7,201 @@ const fn is_leap_year(year: u16) -> bool {
 
 const DAYS_IN_MONTH: [u16; 12] = [31, 28, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31];
 
+/// 日時の前後関係の比較
+///
+/// UTC の年から秒までのフィールドを上から順に比べる
+pub trait Chronological {
+    fn chronological_cmp(&self, other: &Self) -> Ordering;
+}
+
+impl Chronological for DateTime {
+    fn chronological_cmp(&self, other: &Self) -> Ordering {
+        self.fields().cmp(&other.fields())
+    }
+}
+
+// 年月日と時分秒を区切り文字 separator で繋いだ形式を解釈して値を検証する
+// 23:59:60 の閏秒は受け付けるが、多くの ISO 8601 の解釈器が :60 を拒否するので同じ分の :59 に丸める
+fn parse_fields(s: &str, separator: u8) -> Result<(u16, u8, u8, u8, u8, u8), DateTimeParseError> {
+    match s.as_bytes() {
+        [
+            y1,
+            y2,
+            y3,
+            y4,
+            b'-',
+            mo1,
+            mo2,
+            b'-',
+            d1,
+            d2,
+            sep,
+            h1,
+            h2,
+            b':',
+            mi1,
+            mi2,
+            b':',
+            s1,
+            s2,
+        ] if *sep == separator
+            && [y1, y2, y3, y4, mo1, mo2, d1, d2, h1, h2, mi1, mi2, s1, s2]
+                .iter()
+                .all(|&&x| x.is_ascii_digit()) =>
+        {
+            let year: u16 = s[0..4].parse().map_err(|_| DateTimeParseError)?;
+            let month: u8 = s[5..7].parse().map_err(|_| DateTimeParseError)?;
+            let day: u8 = s[8..10].parse().map_err(|_| DateTimeParseError)?;
+            let hour: u8 = s[11..13].parse().map_err(|_| DateTimeParseError)?;
+            let minute: u8 = s[14..16].parse().map_err(|_| DateTimeParseError)?;
+            let second: u8 = s[17..19].parse().map_err(|_| DateTimeParseError)?;
+            if (1970..=9999).contains(&year)
+                && (1..=12).contains(&month)
+                && (1..=DAYS_IN_MONTH[month as usize - 1] as u8
+                    + u8::from(is_leap_year(year) && month == 2))
+                    .contains(&day)
+                && (0..=23).contains(&hour)
+                && (0..=59).contains(&minute)
+                && (0..=59 + u8::from(hour == 23 && minute == 59)).contains(&second)
+            {
+                Ok((year, month, day, hour, minute, second.min(59)))
+            } else {
+                Err(DateTimeParseError)
+            }
+        }
+        _ => Err(DateTimeParseError),
+    }
+}
+
+// 1970-01-01 からの日数
+const fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+// days_from_civil の逆変換
+const fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let doe = days - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// `DateTime` の各フィールドと UNIX 時間の取得
+///
+/// `DateTime` は外部のクレートの型なので表示形式を解釈しなおして値を得る。
+/// 表示形式が想定と異なれば None になる
+pub trait DateTimeExt {
+    fn fields(&self) -> Option<(u16, u8, u8, u8, u8, u8)>;
+
+    fn year(&self) -> Option<u16> {
+        self.fields().map(|x| x.0)
+    }
+
+    fn month(&self) -> Option<u8> {
+        self.fields().map(|x| x.1)
+    }
+
+    fn day(&self) -> Option<u8> {
+        self.fields().map(|x| x.2)
+    }
+
+    fn hour(&self) -> Option<u8> {
+        self.fields().map(|x| x.3)
+    }
+
+    fn minute(&self) -> Option<u8> {
+        self.fields().map(|x| x.4)
+    }
+
+    fn second(&self) -> Option<u8> {
+        self.fields().map(|x| x.5)
+    }
+
+    /// 1970-01-01T00:00:00Z からの秒数
+    fn to_unix_time(&self) -> Option<u64> {
+        let (year, month, day, hour, minute, second) = self.fields()?;
+        Some(
+            days_from_civil(year.into(), month.into(), day.into()) as u64 * 86400
+                + u64::from(hour) * 3600
+                + u64::from(minute) * 60
+                + u64::from(second),
+        )
+    }
+}
+
+// `YYYY-MM-DDTHH:MM:SSZ` の 20 バイトに収まる表示をヒープを使わずに受ける
+struct Buffer {
+    bytes: [u8; 20],
+    len: usize,
+}
+
+impl std::fmt::Write for Buffer {
+    fn write_str(&mut self, s: &str) -> std::fmt::Result {
+        let end = self.len + s.len();
+        self.bytes
+            .get_mut(self.len..end)
+            .ok_or(std::fmt::Error)?
+            .copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
+
+impl DateTimeExt for DateTime {
+    fn fields(&self) -> Option<(u16, u8, u8, u8, u8, u8)> {
+        use std::fmt::Write;
+        let mut buffer = Buffer {
+            bytes: [0; 20],
+            len: 0,
+        };
+        write!(buffer, "{}", self).ok()?;
+        let s = std::str::from_utf8(&buffer.bytes[..buffer.len]).ok()?;
+        parse_fields(s.strip_suffix('Z')?, b'T').ok()
+    }
+}
+
+/// UTC から offset_minutes 分ずれた地方時として解釈する
+pub trait FromOffset {
+    fn from_str_with_offset(s: &str, offset_minutes: i16) -> Result<Self, DateTimeParseError>
+    where
+        Self: Sized;
+}
+
+impl FromOffset for DateTime {
+    fn from_str_with_offset(s: &str, offset_minutes: i16) -> Result<DateTime, DateTimeParseError> {
+        let (year, month, day, hour, minute, second) = parse_fields(s, b' ')?;
+        let minutes = days_from_civil(year.into(), month.into(), day.into()) * 1440
+            + i64::from(hour) * 60
+            + i64::from(minute)
+            - i64::from(offset_minutes);
+        let (year, month, day) = civil_from_days(minutes.div_euclid(1440));
+        let minutes = minutes.rem_euclid(1440);
+        DateTime::new(
+            u16::try_from(year)
+                .ok()
+                .filter(|year| (1970..=9999).contains(year))
+                .ok_or(DateTimeParseError)?,
+            month as u8,
+            day as u8,
+            (minutes / 60) as u8,
+            (minutes % 60) as u8,
+            second,
+        )
+        .ok_or(DateTimeParseError)
+    }
+}
+
 pub trait FromJST {
     fn from_jst_str(s: &str) -> Result<Self, DateTimeParseError>
     where
@@ -14,74 +210,144 @@ pub trait FromJST {
 
 impl FromJST for DateTime {
     fn from_jst_str(s: &str) -> Result<DateTime, DateTimeParseError> {
-        match s.as_bytes() {
-            [
-                y1,
-                y2,
-                y3,
-                y4,
-                b'-',
-                mo1,
-                mo2,
-                b'-',
-                d1,
-                d2,
-                b' ',
-                h1,
-                h2,
-                b':',
-                mi1,
-                mi2,
-                b':',
-                s1,
-                s2,
-            ] if [y1, y2, y3, y4, mo1, mo2, d1, d2, h1, h2, mi1, mi2, s1, s2]
-                .iter()
-                .all(|&&x| x.is_ascii_digit()) =>
-            {
-                let year: u16 = s[0..4].parse().map_err(|_| DateTimeParseError)?;
-                let month: u8 = s[5..7].parse().map_err(|_| DateTimeParseError)?;
-                let day: u8 = s[8..10].parse().map_err(|_| DateTimeParseError)?;
-                let hour: u8 = s[11..13].parse().map_err(|_| DateTimeParseError)?;
-                let minute: u8 = s[14..16].parse().map_err(|_| DateTimeParseError)?;
-                let second: u8 = s[17..19].parse().map_err(|_| DateTimeParseError)?;
-                if (1970..=9999).contains(&year)
-                    && (1..=12).contains(&month)
-                    && (1..=DAYS_IN_MONTH[month as usize - 1] as u8
-                        + u8::from(is_leap_year(year) && month == 2))
-                        .contains(&day)
-                    && (0..=23).contains(&hour)
-                    && (0..=59).contains(&minute)
-                    && (0..=59 + u8::from(hour == 23 && minute == 59)).contains(&second)
-                {
-                    let (hour, bf) = if hour >= 9 {
-                        (hour - 9, 0)
-                    } else {
-                        (hour + 15, 1)
-                    };
-                    let (day, bf) = if day > bf {
-                        (day - bf, 0)
-                    } else {
-                        (
-                            DAYS_IN_MONTH[((month + 10) % 12) as usize] as u8
-                                + u8::from(is_leap_year(year) && (month - 1 == 2)),
-                            1,
-                        )
-                    };
-                    let (month, bf) = if month > bf {
-                        (month - bf, 0u16)
-                    } else {
-                        (12, 1u16)
-                    };
-                    let year = year - bf;
-
-                    Ok(DateTime::new(year, month, day, hour, minute, second)
-                        .ok_or(DateTimeParseError))?
-                } else {
-                    Err(DateTimeParseError)
-                }
-            }
-            _ => Err(DateTimeParseError),
-        }
+        DateTime::from_str_with_offset(s, 540)
+    }
+}
+
+/// `DateTime` の表示形式と同じ `YYYY-MM-DDTHH:MM:SSZ` を UTC として解釈する
+pub trait FromIso8601 {
+    fn from_iso8601_str(s: &str) -> Result<Self, DateTimeParseError>
+    where
+        Self: Sized;
+}
+
+impl FromIso8601 for DateTime {
+    fn from_iso8601_str(s: &str) -> Result<DateTime, DateTimeParseError> {
+        let s = s.strip_suffix('Z').ok_or(DateTimeParseError)?;
+        let (year, month, day, hour, minute, second) = parse_fields(s, b'T')?;
+        DateTime::new(year, month, day, hour, minute, second).ok_or(DateTimeParseError)
+    }
+}
+
+/// 1970-01-01T00:00:00Z からの秒数から作る
+pub trait FromUnixTime {
+    fn from_unix_time(seconds: u64) -> Option<Self>
+    where
+        Self: Sized;
+
+    /// 現在の時刻
+    fn now() -> Option<Self>
+    where
+        Self: Sized,
+    {
+        let elapsed = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .ok()?;
+        Self::from_unix_time(elapsed.as_secs())
+    }
+}
+
+impl FromUnixTime for DateTime {
+    fn from_unix_time(seconds: u64) -> Option<DateTime> {
+        let days = i64::try_from(seconds / 86400).ok()?;
+        let seconds = seconds % 86400;
+        let (year, month, day) = civil_from_days(days);
+        DateTime::new(
+            u16::try_from(year).ok().filter(|year| *year <= 9999)?,
+            month as u8,
+            day as u8,
+            (seconds / 3600) as u8,
+            (seconds / 60 % 60) as u8,
+            (seconds % 60) as u8,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chronological() {
+        let before = DateTime::from_jst_str("2024-01-01 23:59:59").unwrap();
+        let after = DateTime::from_jst_str("2024-01-02 00:00:00").unwrap();
+        assert_eq!(before.chronological_cmp(&after), Ordering::Less);
+        assert_eq!(after.chronological_cmp(&before), Ordering::Greater);
+        assert_eq!(after.chronological_cmp(&after.clone()), Ordering::Equal);
+
+        // 日本時間の夜は UTC では翌日に繰り上がらず、朝は前日に繰り下がる
+        let morning = DateTime::from_jst_str("2024-01-02 08:00:00").unwrap();
+        let evening = DateTime::from_jst_str("2024-01-01 21:00:00").unwrap();
+        assert_eq!(morning.to_string(), "2024-01-01T23:00:00Z");
+        assert_eq!(evening.chronological_cmp(&morning), Ordering::Less);
+    }
+
+    #[test]
+    fn iso8601() {
+        let jst = DateTime::from_jst_str("2024-03-01 06:25:17").unwrap();
+        let utc = DateTime::from_iso8601_str(&jst.to_string()).unwrap();
+        assert_eq!(utc.to_string(), "2024-02-29T21:25:17Z");
+        assert_eq!(utc.chronological_cmp(&jst), Ordering::Equal);
+        assert!(DateTime::from_iso8601_str("2024-02-29 21:25:17").is_err());
+        assert!(DateTime::from_iso8601_str("2024-02-29T21:25:17").is_err());
+    }
+
+    #[test]
+    fn offset() {
+        let jst = DateTime::from_jst_str("2024-01-01 08:59:59").unwrap();
+        assert_eq!(jst.to_string(), "2023-12-31T23:59:59Z");
+        let utc = DateTime::from_str_with_offset("2024-02-29 12:00:00", 0).unwrap();
+        assert_eq!(utc.to_string(), "2024-02-29T12:00:00Z");
+        let eastern = DateTime::from_str_with_offset("2024-12-31 22:30:00", -300).unwrap();
+        assert_eq!(eastern.to_string(), "2025-01-01T03:30:00Z");
+        let eastern = DateTime::from_str_with_offset("2023-02-28 20:00:00", -300).unwrap();
+        assert_eq!(eastern.to_string(), "2023-03-01T01:00:00Z");
+        assert!(DateTime::from_jst_str("1970-01-01 08:59:59").is_err());
+    }
+
+    #[test]
+    fn leap_second() {
+        let leap = DateTime::from_jst_str("1998-12-31 23:59:60").unwrap();
+        assert_eq!(leap.to_string(), "1998-12-31T14:59:59Z");
+        let leap = DateTime::from_iso8601_str("1998-12-31T23:59:60Z").unwrap();
+        assert_eq!(leap.to_string(), "1998-12-31T23:59:59Z");
+        assert!(DateTime::from_jst_str("1998-12-31 23:58:60").is_err());
+    }
+
+    #[test]
+    fn accessors() {
+        let time = DateTime::from_jst_str("2024-03-01 06:25:17").unwrap();
+        assert_eq!(time.year(), Some(2024));
+        assert_eq!(time.month(), Some(2));
+        assert_eq!(time.day(), Some(29));
+        assert_eq!(time.hour(), Some(21));
+        assert_eq!(time.minute(), Some(25));
+        assert_eq!(time.second(), Some(17));
+        assert_eq!(time.to_unix_time(), Some(1709241917));
+        let epoch = DateTime::from_jst_str("1970-01-01 09:00:00").unwrap();
+        assert_eq!(epoch.to_unix_time(), Some(0));
+    }
+
+    #[test]
+    fn from_unix_time() {
+        assert_eq!(
+            DateTime::from_unix_time(0).unwrap().to_string(),
+            "1970-01-01T00:00:00Z"
+        );
+        let time = DateTime::from_unix_time(1709241917).unwrap();
+        assert_eq!(time.to_string(), "2024-02-29T21:25:17Z");
+        assert_eq!(time.to_unix_time(), Some(1709241917));
+        assert!(DateTime::from_unix_time(u64::MAX).is_none());
+        assert!(DateTime::now().is_some_and(|now| now.year().is_some_and(|year| year >= 2024)));
+    }
+
+    #[test]
+    fn length() {
+        assert!(DateTime::from_jst_str("2024-01-01 00:00:0").is_err());
+        assert!(DateTime::from_jst_str("2024-01-01 00:00:000").is_err());
+        assert!(DateTime::from_jst_str("2024-01-01 00:00").is_err());
+        assert!(DateTime::from_jst_str("").is_err());
+        assert!(DateTime::from_jst_str("２０２４-01-01 00:00:00").is_err());
+        assert!(DateTime::from_iso8601_str("2024-01-01T00:00:0Z").is_err());
     }
 }
@@ -0,0 +1,137 @@
+// XML の名前に使える文字。厳密な定義より緩く、記号と空白以外は受け付ける
+fn name_char(ch: char) -> bool {
+    !ch.is_whitespace() && !"<>/=&\"'!?;".contains(ch)
+}
+
+fn name(s: &str) -> Option<(&str, &str)> {
+    let end = s.find(|ch| !name_char(ch)).unwrap_or(s.len());
+    (end > 0 && !s.starts_with(|ch: char| ch.is_ascii_digit() || ch == '-' || ch == '.'))
+        .then(|| s.split_at(end))
+}
+
+// & で始まる参照を読み飛ばす。 XML で定義済みの実体と数値文字参照だけを受け付ける
+fn reference(s: &str) -> Option<&str> {
+    let (body, rest) = s.strip_prefix('&')?.split_once(';')?;
+    let valid = match body.strip_prefix('#') {
+        Some(hex) if hex.starts_with('x') => {
+            hex.len() > 1 && hex[1..].chars().all(|ch| ch.is_ascii_hexdigit())
+        }
+        Some(dec) => !dec.is_empty() && dec.chars().all(|ch| ch.is_ascii_digit()),
+        None => ["amp", "lt", "gt", "quot", "apos"].contains(&body),
+    };
+    valid.then_some(rest)
+}
+
+// 文字データを次の < まで読み飛ばす
+fn text(mut s: &str) -> Option<&str> {
+    loop {
+        match s.find(['<', '&']) {
+            Some(i) if s[i..].starts_with('&') => s = reference(&s[i..])?,
+            Some(i) => return Some(&s[i..]),
+            None => return Some(""),
+        }
+    }
+}
+
+// 属性の並びを読み、タグの残りと空要素かどうかを返す
+fn attributes(mut s: &str) -> Option<(&str, bool)> {
+    loop {
+        s = s.trim_start();
+        if let Some(rest) = s.strip_prefix("/>") {
+            return Some((rest, true));
+        } else if let Some(rest) = s.strip_prefix('>') {
+            return Some((rest, false));
+        }
+        let (_, rest) = name(s)?;
+        let rest = rest.trim_start().strip_prefix('=')?.trim_start();
+        let quote = rest.chars().next().filter(|&ch| ch == '"' || ch == '\'')?;
+        let (value, rest) = rest[1..].split_once(quote)?;
+        if value.contains('<') || !text(value)?.is_empty() {
+            return None;
+        }
+        // 属性と属性の間には空白が要る
+        if !rest.starts_with(|ch: char| ch.is_whitespace() || ch == '/' || ch == '>') {
+            return None;
+        }
+        s = rest;
+    }
+}
+
+/// 整形式の XML かを簡易に調べる
+///
+/// タグの対応、属性値の引用符、参照の形だけを確かめ、 DTD や名前空間は扱わない
+pub fn is_well_formed(document: &str) -> bool {
+    let mut stack: Vec<&str> = vec![];
+    let mut root = false;
+    let mut rest = document;
+    loop {
+        rest = match text(rest) {
+            Some(r) => r,
+            None => return false,
+        };
+        if rest.is_empty() {
+            return root && stack.is_empty();
+        }
+        let next = if let Some(r) = rest.strip_prefix("<!--") {
+            r.split_once("-->").map(|x| x.1)
+        } else if let Some(r) = rest.strip_prefix("<![CDATA[") {
+            r.split_once("]]>").map(|x| x.1)
+        } else if let Some(r) = rest.strip_prefix("<?") {
+            r.split_once("?>").map(|x| x.1)
+        } else if let Some(r) = rest.strip_prefix("<!DOCTYPE") {
+            r.split_once('>').map(|x| x.1)
+        } else if let Some(r) = rest.strip_prefix("</") {
+            name(r).and_then(|(name, r)| {
+                let r = r.trim_start().strip_prefix('>')?;
+                (stack.pop()? == name).then_some(r)
+            })
+        } else {
+            name(&rest[1..]).and_then(|(name, r)| {
+                // 根の要素は一つだけ
+                if stack.is_empty() && std::mem::replace(&mut root, true) {
+                    return None;
+                }
+                let (r, empty) = attributes(r)?;
+                if !empty {
+                    stack.push(name);
+                }
+                Some(r)
+            })
+        };
+        match next {
+            Some(r) => rest = r,
+            None => return false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_well_formed;
+
+    #[test]
+    fn balanced() {
+        assert!(is_well_formed(
+            r#"<?xml version="1.0" encoding="UTF-8"?><!DOCTYPE html><html xmlns="http://www.w3.org/1999/xhtml" xml:lang='ja'><head><title>表題 &amp; 副題</title></head><body><p>本文<br/>&#12354;&#x3042;</p><!-- 注釈 --><img src="a.png?x=1&amp;y=2" /></body></html>"#
+        ));
+    }
+
+    #[test]
+    fn malformed() {
+        // 閉じられていない p
+        assert!(!is_well_formed("<html><body><p>本文</body></html>"));
+        assert!(!is_well_formed("<html><body><p>本文</p></body>"));
+        // 対応しない終了タグ
+        assert!(!is_well_formed("<html><b><i>強調</b></i></html>"));
+        // 参照になっていない &
+        assert!(!is_well_formed("<p>a & b</p>"));
+        assert!(!is_well_formed("<p>&nbsp;</p>"));
+        // 引用符のない属性値と属性値の中の &
+        assert!(!is_well_formed("<img src=a.png/>"));
+        assert!(!is_well_formed(r#"<img src="a?x=1&y=2"/>"#));
+        assert!(!is_well_formed(r#"<img src="a"alt="b"/>"#));
+        // 根の要素が二つ
+        assert!(!is_well_formed("<p>一</p><p>二</p>"));
+        assert!(!is_well_formed(""));
+    }
+}
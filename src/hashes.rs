@@ -0,0 +1,179 @@
+use crate::narou::block::{self, Block, Inline};
+use crate::narou::episode::Episode;
+use crate::uuid::sha1;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Write};
+
+/// 各話のハッシュを書き留める META-INF の下のファイル名
+pub const SIDECAR: &str = "narou-episodes.txt";
+
+/// 話数ごとの題名と本文の SHA-1 で、前回から変わった話を見分けるのに使う
+#[derive(Debug, Default, PartialEq)]
+pub struct EpisodeHashes(BTreeMap<u32, String>);
+
+fn without_image_inlines(inlines: &[Inline]) -> Vec<Inline> {
+    inlines
+        .iter()
+        .map(|x| match x {
+            Inline::Image(_) => Inline::Image(String::new()),
+            x => x.clone(),
+        })
+        .collect()
+}
+
+// 挿絵の名前は取得した順で決まるので、ハッシュには含めない
+fn without_images(blocks: &[Block]) -> Vec<Block> {
+    blocks
+        .iter()
+        .map(|x| match x {
+            Block::Paragraph(inlines) => Block::Paragraph(without_image_inlines(inlines)),
+            Block::Image(_) => Block::Image(String::new()),
+            Block::Preface(blocks) => Block::Preface(without_images(blocks)),
+            Block::Afterword(blocks) => Block::Afterword(without_images(blocks)),
+            Block::Loose(inlines) => Block::Loose(without_image_inlines(inlines)),
+            x => x.clone(),
+        })
+        .collect()
+}
+
+/// 話の題名と本文の SHA-1 を 16 進数で表したもの
+pub fn episode_hash(episode: &Episode) -> Option<String> {
+    let body = block::render(&without_images(&episode.blocks));
+    let hash = sha1([&episode.title, "\n", &body].concat().as_bytes())?;
+    Some(hash.iter().fold(String::new(), |mut s, x| {
+        let _ = write!(s, "{:02x}", x);
+        s
+    }))
+}
+
+impl EpisodeHashes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 書き留めたファイルの内容を読む。形の崩れた行は無いものとして扱う
+    pub fn parse(s: &str) -> Self {
+        Self(
+            s.lines()
+                .filter_map(|line| {
+                    let (number, hash) = line.split_once('\t')?;
+                    Some((number.parse().ok()?, hash.to_string()))
+                })
+                .collect(),
+        )
+    }
+
+    pub fn insert(&mut self, episode: &Episode) -> &mut Self {
+        if let Some(hash) = episode_hash(episode) {
+            self.0.insert(episode.number, hash);
+        }
+        self
+    }
+
+    /// 前回から本文が変わったか新たに加わった話の番号
+    pub fn changed(&self, current: &EpisodeHashes) -> Vec<u32> {
+        current
+            .0
+            .iter()
+            .filter(|(number, hash)| self.0.get(number) != Some(hash))
+            .map(|(number, _)| *number)
+            .collect()
+    }
+}
+
+impl Display for EpisodeHashes {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (number, hash) in &self.0 {
+            writeln!(f, "{}\t{}", number, hash)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EpisodeHashes, episode_hash};
+    use crate::narou::block::{Block, Inline};
+    use crate::narou::{Recorded, Site, fetch_episode_with_fetcher};
+    use std::sync::Arc;
+
+    #[test]
+    fn round_trip() {
+        let recorded = Arc::new(Recorded::new());
+        let mut hashes = EpisodeHashes::new();
+        for number in 1..=2 {
+            let episode =
+                fetch_episode_with_fetcher("n0001a", number, Site::General, recorded.clone())
+                    .unwrap();
+            hashes.insert(&episode);
+        }
+        let text = hashes.to_string();
+        assert_eq!(text.lines().count(), 2);
+        assert!(text.starts_with("1\t"));
+        assert_eq!(EpisodeHashes::parse(&text), hashes);
+        // 形の崩れた行は読み飛ばす
+        assert_eq!(
+            EpisodeHashes::parse(&[&text, "壊れた行\nx\tabc\n"].concat()),
+            hashes
+        );
+    }
+
+    // 本文が変われば話のハッシュも変わる
+    #[test]
+    fn edited() {
+        let recorded = Arc::new(Recorded::new());
+        let fetch =
+            || fetch_episode_with_fetcher("n0001a", 2, Site::General, recorded.clone()).unwrap();
+        let (episode, mut edited) = (fetch(), fetch());
+        assert_eq!(episode_hash(&edited), episode_hash(&episode));
+        edited
+            .blocks
+            .push(Block::Paragraph(vec![Inline::Text("加筆".to_string())]));
+        assert_ne!(episode_hash(&edited), episode_hash(&episode));
+        edited = fetch();
+        edited.title.push_str("（改稿）");
+        assert_ne!(episode_hash(&edited), episode_hash(&episode));
+    }
+
+    // 書き留めたハッシュと本文が違う話は作り直す対象になる
+    #[test]
+    fn changed() {
+        let recorded = Arc::new(Recorded::new());
+        let fetch = |number| {
+            fetch_episode_with_fetcher("n0001a", number, Site::General, recorded.clone()).unwrap()
+        };
+        let mut stored = EpisodeHashes::new();
+        let mut edited = fetch(1);
+        edited
+            .blocks
+            .push(Block::Paragraph(vec![Inline::Text("加筆".to_string())]));
+        stored.insert(&edited);
+        let stored = EpisodeHashes::parse(&stored.to_string());
+        let mut current = EpisodeHashes::new();
+        current.insert(&fetch(1));
+        assert_eq!(stored.changed(&current), vec![1]);
+        current.insert(&fetch(2));
+        assert_eq!(stored.changed(&current), vec![1, 2]);
+        assert!(current.changed(&current).is_empty());
+    }
+
+    // 挿絵の名前が変わってもハッシュは変わらない
+    #[test]
+    fn image_names() {
+        let recorded = Arc::new(Recorded::new());
+        let fetch =
+            || fetch_episode_with_fetcher("n0001a", 2, Site::General, recorded.clone()).unwrap();
+        let (episode, mut renamed) = (fetch(), fetch());
+        for block in renamed.blocks.iter_mut() {
+            if let Block::Paragraph(inlines) = block {
+                for inline in inlines.iter_mut() {
+                    if let Inline::Image(name) = inline {
+                        *name = "renamed.png".to_string();
+                    }
+                }
+            }
+        }
+        assert_ne!(renamed.blocks, episode.blocks);
+        assert_eq!(episode_hash(&renamed), episode_hash(&episode));
+    }
+}
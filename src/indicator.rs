@@ -1,5 +1,8 @@
+use crate::progress::Progress;
+use crate::width::{display_width, truncate_to_width};
 use std::fmt::Write;
 use std::mem::MaybeUninit;
+use std::time::{Duration, Instant};
 use std::{ffi::os_str::OsStr, os::windows::ffi::OsStrExt};
 
 use windows_sys::{
@@ -113,66 +116,114 @@ pub struct Indicator {
     cursor: u32,
     original_attributes: u16,
     buffer: Vec<u16>,
+    start: Instant,
+}
+
+// 幅 width に収まる進捗の棒と数字の欄を作る。
+// 棒を描く余地がなければ数字だけにする
+fn render_bar(width: usize, cursor: u32, limit: u32, number_field: &str) -> String {
+    const BLOCKS: [char; 8] = [' ', '▏', '▎', '▍', '▌', '▋', '▊', '▉'];
+    let bar_length = match width.checked_sub(display_width(number_field) + 2) {
+        Some(length) if length > 0 => length,
+        _ => {
+            let counter = number_field.trim_start_matches("] ");
+            return truncate_to_width(counter, width.saturating_sub(1)).to_string();
+        }
+    };
+    let ratio = if limit == 0 {
+        0.0
+    } else {
+        (cursor as f64 / limit as f64).min(1.0)
+    };
+    let current = ratio * bar_length as f64;
+    let integer_part = current as usize;
+    let fractional_part = ((current - integer_part as f64) * 8.0) as usize;
+    let rest_part = bar_length - integer_part - usize::from(fractional_part != 0);
+    let mut line = String::with_capacity(width * 3);
+    line.push('[');
+    line.extend(std::iter::repeat_n('█', integer_part));
+    if fractional_part != 0 {
+        line.push(BLOCKS[fractional_part]);
+    }
+    line.extend(std::iter::repeat_n(' ', rest_part));
+    line.push_str(number_field);
+    line
+}
+
+// これまでの平均から残り時間を見積もって mm:ss の形にする
+fn format_eta(elapsed: Duration, cursor: u32, limit: u32) -> String {
+    if cursor == 0 {
+        "--:--".to_string()
+    } else {
+        let remaining =
+            (elapsed.as_secs_f64() / cursor as f64 * limit.saturating_sub(cursor) as f64) as u64;
+        format!("{:02}:{:02}", remaining / 60, remaining % 60)
+    }
 }
 
 impl Indicator {
-    pub fn new(limit: u32) -> Result<Self, WIN32_ERROR> {
+    // 表示は start で全体の話数を受け取ってから始める
+    pub fn new() -> Result<Self, WIN32_ERROR> {
         let terminal = Terminal::new()?;
         let info = terminal.info()?;
         let position = info.dwCursorPosition;
         let original_attributes = info.wAttributes;
-        let mut obj = Self {
+        Ok(Self {
             terminal,
             position,
-            limit,
+            limit: 0,
             cursor: 0,
             original_attributes,
             buffer: vec![],
-        };
-        let _ = obj.display();
-        Ok(obj)
-    }
-
-    pub fn increment(&mut self) {
-        self.cursor += 1;
-        let _ = self.display();
+            start: Instant::now(),
+        })
     }
 
     pub fn display(&mut self) -> Result<(), WIN32_ERROR> {
-        const BLOCKS: [char; 8] = [' ', '▏', '▎', '▍', '▌', '▋', '▊', '▉'];
         self.terminal.set_cursor_position(self.position);
         let console_width = self.terminal.console_width()?;
-        let number_field = format!("] {}/{}", self.cursor, self.limit);
-        let bar_length = (console_width - number_field.len() as i16 - 2) as usize;
-        let current = (self.cursor as f64 / self.limit as f64) * bar_length as f64;
-        let integer_part = current as usize;
-        let fractional_part = ((current - integer_part as f64) * 8.0) as usize;
-        let rest_part = bar_length - integer_part - usize::from(fractional_part != 0);
+        let number_field = format!(
+            "] {}/{} ETA {}",
+            self.cursor,
+            self.limit,
+            format_eta(self.start.elapsed(), self.cursor, self.limit)
+        );
+        let line = render_bar(
+            console_width.max(0) as usize,
+            self.cursor,
+            self.limit,
+            &number_field,
+        );
         self.buffer.clear();
+        self.buffer.extend(line.encode_utf16());
         unsafe {
             SetConsoleTextAttribute(
                 self.terminal.handle,
                 FOREGROUND_BLUE | FOREGROUND_GREEN | FOREGROUND_INTENSITY | BACKGROUND_BLUE,
             );
         }
-        self.buffer.push('[' as u16);
-        for _ in 0..integer_part {
-            self.buffer.push('█' as u16);
-        }
-        if fractional_part != 0 {
-            self.buffer.push(BLOCKS[fractional_part] as u16);
-        }
-        for _ in 0..rest_part {
-            self.buffer.push(' ' as u16);
-        }
-        for e in number_field.chars() {
-            self.buffer.push(e as u16);
-        }
         self.terminal.write(&self.buffer).unwrap();
         Ok(())
     }
 }
 
+impl Progress for Indicator {
+    fn start(&mut self, total: u32) {
+        self.limit = total;
+        self.cursor = 0;
+        self.start = Instant::now();
+        let _ = self.display();
+    }
+
+    fn advance(&mut self, current: u32) {
+        self.cursor = current;
+        let _ = self.display();
+    }
+
+    // 文字色とカーソルの後始末は Drop で行う
+    fn finish(&mut self) {}
+}
+
 impl Drop for Indicator {
     fn drop(&mut self) {
         unsafe {
@@ -184,3 +235,37 @@ impl Drop for Indicator {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{format_eta, render_bar};
+    use std::time::Duration;
+
+    #[test]
+    fn bar() {
+        let field = "] 5/10 ETA 00:10";
+        let line = render_bar(field.len() + 12, 5, 10, field);
+        assert_eq!(line, ["[", &"█".repeat(5), &" ".repeat(5), field].concat());
+        assert_eq!(line.chars().count(), field.len() + 11);
+        // 幅が足りなければ数字だけ
+        for width in [field.len(), field.len() + 2] {
+            assert_eq!(render_bar(width, 5, 10, field), "5/10 ETA 00:10");
+        }
+        // 数字も収まらなければ行末で折り返さないように切り詰める
+        assert_eq!(render_bar(0, 5, 10, field), "");
+        assert_eq!(render_bar(5, 5, 10, field), "5/10");
+        let field = format!("] 1234/{} ETA --:--", u32::MAX);
+        assert_eq!(render_bar(10, 1234, u32::MAX, &field), "1234/4294");
+        // 話数が 0 のときや上限を超えたときも棒の長さは変わらない
+        assert_eq!(render_bar(40, 0, 0, field.as_str()).chars().count(), 39);
+        assert_eq!(render_bar(40, 20, 10, field.as_str()).chars().count(), 39);
+    }
+
+    #[test]
+    fn eta() {
+        assert_eq!(format_eta(Duration::from_secs(0), 0, 10), "--:--");
+        assert_eq!(format_eta(Duration::from_secs(30), 3, 10), "01:10");
+        assert_eq!(format_eta(Duration::from_secs(90), 10, 10), "00:00");
+        assert_eq!(format_eta(Duration::from_secs(60), 1, 100), "99:00");
+    }
+}
@@ -7,6 +7,19 @@ pub enum Error {
     EarlyTerminate,
 }
 
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::UnexpectedChar(ch) => write!(f, "想定外の文字 {:?}", ch),
+            Error::UnknownEscapeChar(ch) => write!(f, "知らないエスケープ文字 {:?}", ch),
+            Error::InvalidCodePoint(n) => write!(f, "不正なコードポイント U+{:04X}", n),
+            Error::EarlyTerminate => write!(f, "字句の途中で終わっています"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
 fn is_whitespace(ch: char) -> bool {
     matches!(ch, ' ' | '\n' | '\t' | '\r')
 }
@@ -30,6 +43,23 @@ pub enum JsonToken {
     Value(JsonValue),
 }
 
+impl std::fmt::Display for JsonToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JsonToken::LeftSquare => write!(f, "["),
+            JsonToken::RightSquare => write!(f, "]"),
+            JsonToken::LeftCurly => write!(f, "{{"),
+            JsonToken::RightCurly => write!(f, "}}"),
+            JsonToken::Colon => write!(f, ":"),
+            JsonToken::Comma => write!(f, ","),
+            JsonToken::Value(JsonValue::String(s)) => write!(f, "{:?}", s),
+            JsonToken::Value(JsonValue::Number(n)) => write!(f, "{}", n),
+            JsonToken::Value(JsonValue::Null) => write!(f, "null"),
+            JsonToken::Value(JsonValue::Bool(b)) => write!(f, "{}", b),
+        }
+    }
+}
+
 impl std::convert::From<JsonValue> for JsonToken {
     fn from(value: JsonValue) -> Self {
         JsonToken::Value(value)
@@ -76,6 +106,7 @@ impl<'a, T: Iterator<Item = char>> Tokens<'a, T> {
                             'n' => newstr.push('\n'),
                             't' => newstr.push('\t'),
                             'r' => newstr.push('\r'),
+                            '"' => newstr.push('"'),
                             '\\' => newstr.push('\\'),
                             '/' => newstr.push('/'),
                             'b' => newstr.push('\u{8}'),
@@ -159,6 +190,31 @@ impl<T: Iterator<Item = char>> Iterator for Tokens<'_, T> {
 #[cfg(test)]
 mod tests {
     use super::{Error, JsonToken, JsonValue, Tokens};
+
+    #[test]
+    fn display() {
+        assert_eq!(Error::UnexpectedChar('x').to_string(), "想定外の文字 'x'");
+        assert_eq!(
+            Error::UnknownEscapeChar('q').to_string(),
+            "知らないエスケープ文字 'q'"
+        );
+        assert_eq!(
+            Error::InvalidCodePoint(0xd800).to_string(),
+            "不正なコードポイント U+D800"
+        );
+        assert_eq!(
+            Error::EarlyTerminate.to_string(),
+            "字句の途中で終わっています"
+        );
+        let error: Box<dyn std::error::Error> = Box::new(Error::EarlyTerminate);
+        assert!(!error.to_string().is_empty());
+        assert_eq!(JsonToken::LeftCurly.to_string(), "{");
+        assert_eq!(
+            JsonToken::Value(JsonValue::String("a\"b".to_string())).to_string(),
+            r#""a\"b""#
+        );
+    }
+
     #[test]
     fn it_works() -> Result<(), Error> {
         let json1 = r#"[{"allcount":1},
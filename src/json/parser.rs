@@ -1,6 +1,7 @@
 pub use super::lexer::JsonToken;
 use super::lexer::{Error as LexerError, JsonValue, Tokens};
 use std::convert::From;
+use std::fmt::Write;
 use std::ops::Index;
 use std::str::FromStr;
 
@@ -13,6 +14,26 @@ pub enum Error {
     TokenizeFailure(LexerError),
 }
 
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::ImpossibleConversion => write!(f, "値の型が想定と異なります"),
+            Error::EarlyTerminate => write!(f, "途中で終わっています"),
+            Error::UnexpectedToken(token) => write!(f, "想定外の字句 {}", token),
+            Error::TokenizeFailure(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::TokenizeFailure(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
 impl std::convert::From<LexerError> for Error {
     fn from(value: LexerError) -> Self {
         Error::TokenizeFailure(value)
@@ -189,19 +210,53 @@ impl JsonKey for &str {
     }
 }
 
+/// 添字とキーを混ぜて並べられる JsonKey
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PathKey<'a> {
+    Index(usize),
+    Key(&'a str),
+}
+
+impl From<usize> for PathKey<'_> {
+    fn from(value: usize) -> Self {
+        PathKey::Index(value)
+    }
+}
+
+impl<'a> From<&'a str> for PathKey<'a> {
+    fn from(value: &'a str) -> Self {
+        PathKey::Key(value)
+    }
+}
+
+impl JsonKey for PathKey<'_> {
+    fn get(self, json: &JsonNode) -> Option<&JsonNode> {
+        match self {
+            PathKey::Index(n) => JsonKey::get(n, json),
+            PathKey::Key(k) => JsonKey::get(k, json),
+        }
+    }
+}
+
 impl JsonNode {
     pub fn get<T: JsonKey>(&self, key: T) -> Option<&JsonNode> {
         key.get(self)
     }
 
-    pub fn get_string(&self) -> Option<String> {
+    /// 添字とキーの並びを順に辿る
+    pub fn get_path(&self, path: &[PathKey]) -> Option<&JsonNode> {
+        path.iter().try_fold(self, |json, key| json.get(*key))
+    }
+
+    /// 複製せずに借りる文字列
+    pub fn as_str(&self) -> Option<&str> {
         match self {
-            JsonNode::String(s) => Some(s.clone()),
+            JsonNode::String(s) => Some(s),
             _ => None,
         }
     }
 
-    pub fn get_number(&self) -> Option<u32> {
+    pub fn as_u32(&self) -> Option<u32> {
         match self {
             &JsonNode::Number(n) => Some(n),
             _ => None,
@@ -209,6 +264,55 @@ impl JsonNode {
     }
 }
 
+// JSON の文字列として引用符で囲み、必要な文字をエスケープする
+fn write_string(f: &mut std::fmt::Formatter<'_>, s: &str) -> std::fmt::Result {
+    f.write_char('"')?;
+    for ch in s.chars() {
+        match ch {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            ch if ch < ' ' => write!(f, "\\u{:04x}", ch as u32)?,
+            ch => f.write_char(ch)?,
+        }
+    }
+    f.write_char('"')
+}
+
+impl std::fmt::Display for JsonNode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JsonNode::String(s) => write_string(f, s),
+            JsonNode::Number(n) => write!(f, "{}", n),
+            JsonNode::Null => f.write_str("null"),
+            JsonNode::Bool(b) => write!(f, "{}", b),
+            JsonNode::Array(arr) => {
+                f.write_char('[')?;
+                for (i, v) in arr.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    write!(f, "{}", v)?;
+                }
+                f.write_char(']')
+            }
+            JsonNode::Object(obj) => {
+                f.write_char('{')?;
+                for (i, (k, v)) in obj.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    write_string(f, k)?;
+                    write!(f, ":{}", v)?;
+                }
+                f.write_char('}')
+            }
+        }
+    }
+}
+
 impl<T: JsonKey> Index<T> for JsonNode {
     type Output = JsonNode;
     fn index(&self, index: T) -> &Self::Output {
@@ -218,7 +322,26 @@ impl<T: JsonKey> Index<T> for JsonNode {
 
 #[cfg(test)]
 mod tests {
-    use super::JsonNode;
+    use super::{Error, JsonNode, JsonToken, LexerError, PathKey};
+    use std::error::Error as _;
+
+    #[test]
+    fn error_display() {
+        assert_eq!(
+            Error::ImpossibleConversion.to_string(),
+            "値の型が想定と異なります"
+        );
+        assert_eq!(Error::EarlyTerminate.to_string(), "途中で終わっています");
+        assert_eq!(
+            Error::UnexpectedToken(JsonToken::Colon).to_string(),
+            "想定外の字句 :"
+        );
+        let error = Error::TokenizeFailure(LexerError::UnexpectedChar('@'));
+        assert_eq!(error.to_string(), "想定外の文字 '@'");
+        assert!(error.source().is_some());
+        let error = "[1,]".parse::<JsonNode>().unwrap_err();
+        assert!(error.to_string().starts_with("想定外の字句"), "{}", error);
+    }
 
     #[test]
     fn it_works() {
@@ -250,4 +373,52 @@ mod tests {
             "1981-03-08 06:25:17".into()
         );
     }
+
+    #[test]
+    fn accessors() {
+        let json: JsonNode = r#"[{"allcount":1},{"title":"表題","tags":["a","b"]}]"#
+            .parse()
+            .unwrap();
+        let title = json[1]["title"].as_str().unwrap();
+        // 複製ではなく木の中の文字列を指す
+        let JsonNode::String(inner) = &json[1]["title"] else {
+            panic!();
+        };
+        assert!(std::ptr::eq(title, inner.as_str()));
+        assert_eq!(json[0]["allcount"].as_u32(), Some(1));
+        assert_eq!(json[0]["allcount"].as_str(), None);
+        assert_eq!(json[1]["title"].as_u32(), None);
+
+        assert_eq!(
+            json.get_path(&[1.into(), "tags".into(), 1.into()])
+                .and_then(JsonNode::as_str),
+            Some("b")
+        );
+        assert_eq!(
+            json.get_path(&[PathKey::Index(0), PathKey::Key("allcount")])
+                .and_then(JsonNode::as_u32),
+            Some(1)
+        );
+        assert_eq!(json.get_path(&[]), Some(&json));
+        assert_eq!(json.get_path(&[1.into(), "missing".into()]), None);
+        assert_eq!(json.get_path(&["title".into()]), None);
+    }
+
+    #[test]
+    fn display() {
+        let json = JsonNode::Object(vec![
+            ("title".into(), "「引用\"符」\\\n".into()),
+            ("count".into(), 18.into()),
+            (
+                "list".into(),
+                JsonNode::Array(vec![JsonNode::Null, JsonNode::Bool(true)]),
+            ),
+        ]);
+        let string = json.to_string();
+        assert_eq!(
+            string,
+            r#"{"title":"「引用\"符」\\\n","count":18,"list":[null,true]}"#
+        );
+        assert_eq!(string.parse::<JsonNode>().unwrap(), json);
+    }
 }
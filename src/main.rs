@@ -1,23 +1,41 @@
 #[cfg(not(all(target_os = "windows", target_arch = "x86_64")))]
 compile_error!("This project only supports x86_64 Windows. Please use the correct target.");
 mod command;
+mod cover;
 mod epub;
+mod hashes;
 mod indicator;
 mod json;
 mod narou;
+mod output;
+mod progress;
+mod report;
 mod sanitize;
+mod summary;
 mod uuid;
+mod width;
 use crate::epub::ReferenceType;
+use crate::epub::time::{Chronological, DateTimeExt, FromUnixTime};
 use crate::narou::episode::ImageType;
-use epub::{Epub, Escape, Id, MediaType};
+use epub::{Epub, Escape, MediaType};
+use hashes::EpisodeHashes;
 use indicator::Indicator;
-use narou::episode::ImageInfo;
-use sanitize::sanitize;
+use narou::episode::{Episode, ImageInfo};
+use narou::ncode_validate_and_normalize;
+use output::{NameFields, OutputFormat};
+use progress::Progress;
+use std::borrow::Cow;
+use std::cell::RefCell;
 use std::fs::File;
+use std::io::{IsTerminal, Write};
+use std::iter::Peekable;
+use std::os::windows::ffi::OsStrExt;
 use std::os::windows::io::{FromRawHandle, OwnedHandle};
+use std::path::{Path, PathBuf};
 use std::sync::atomic::AtomicBool;
-use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+use summary::Summary;
+use utcdatetime::DateTime;
 use windows_sys::Win32::Storage::FileSystem::GetTempFileNameW;
 use windows_sys::Win32::System::Console::SetConsoleCtrlHandler;
 use windows_sys::{
@@ -30,16 +48,32 @@ use windows_sys::{
 
 #[derive(Debug)]
 struct TemporaryFile {
-    true_name: String,
+    true_name: PathBuf,
     temporary_name: String,
     pub handle: Option<File>,
 }
 
 impl TemporaryFile {
-    pub fn new(true_name: &str) -> Result<Self, WIN32_ERROR> {
+    // 最後の名前の変更が同じボリューム内で済むように一時ファイルは出力先と同じディレクトリに作る
+    pub fn new(true_name: &Path) -> Result<Self, WIN32_ERROR> {
+        let directory = match true_name.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let directory: Vec<u16> = directory
+            .as_os_str()
+            .encode_wide()
+            .chain(std::iter::once(0))
+            .collect();
         unsafe {
             let mut temporary_name = [0; MAX_PATH as usize];
-            if GetTempFileNameW(w!("."), w!("etf"), 0, temporary_name.as_mut_ptr()) == 0 {
+            if GetTempFileNameW(
+                directory.as_ptr(),
+                w!("etf"),
+                0,
+                temporary_name.as_mut_ptr(),
+            ) == 0
+            {
                 Err(GetLastError())
             } else {
                 let handle = CreateFileW(
@@ -63,7 +97,7 @@ impl TemporaryFile {
                     let temporary_name = String::from_utf16_lossy(&temporary_name[0..zero]);
                     Ok(Self {
                         temporary_name,
-                        true_name: true_name.to_string(),
+                        true_name: true_name.to_path_buf(),
                         handle: Some(OwnedHandle::from_raw_handle(handle).into()),
                     })
                 }
@@ -71,19 +105,27 @@ impl TemporaryFile {
         }
     }
 
+    // 上書きできなかった場合は読み取り専用の属性を外して既存の出力先を消してから置き換える
     pub fn finish(&mut self) -> Result<(), narou::Error> {
         if let Some(handle) = std::mem::take(&mut self.handle) {
             drop(handle);
-            if std::fs::rename(&self.temporary_name, &self.true_name).is_err() {
-                if std::fs::remove_file(&self.temporary_name).is_err() {
-                    Err(narou::Error::OverWriteFail)
-                } else {
-                    Ok(std::fs::rename(&self.temporary_name, &self.true_name)
-                        .or(Err(narou::Error::OverWriteFail))?)
-                }
-            } else {
-                Ok(())
+            if std::fs::rename(&self.temporary_name, &self.true_name).is_ok() {
+                return Ok(());
+            }
+            let replaced = std::fs::metadata(&self.true_name)
+                .and_then(|metadata| {
+                    let mut permissions = metadata.permissions();
+                    #[allow(clippy::permissions_set_readonly_false)]
+                    permissions.set_readonly(false);
+                    std::fs::set_permissions(&self.true_name, permissions)
+                })
+                .and_then(|_| std::fs::remove_file(&self.true_name))
+                .and_then(|_| std::fs::rename(&self.temporary_name, &self.true_name));
+            if replaced.is_err() {
+                let _ = std::fs::remove_file(&self.temporary_name);
+                return Err(narou::Error::OverWriteFail);
             }
+            Ok(())
         } else {
             panic!();
         }
@@ -112,37 +154,84 @@ fn make_title_page(novel: &narou::Novel) -> String {
     .concat()
 }
 
+// 作った表紙の画像だけを置いた表題のページ
+fn make_cover_page(novel: &narou::Novel) -> String {
+    [
+        r#"<html xmlns="http://www.w3.org/1999/xhtml" xml:lang="ja"><head><title>"#,
+        &novel.title().escape(),
+        r#"</title><link rel="stylesheet" href="style.css"/></head><body class="titlepage"><img class="cover" src="cover.svg" alt=""#,
+        &novel.title().escape(),
+        r#""/></body></html>"#,
+    ]
+    .concat()
+}
+
 fn make_chapter(title: &str) -> String {
+    let title = title.escape();
     [
         r#"<html xmlns="http://www.w3.org/1999/xhtml" xml:lang="ja"><head><title>"#,
-        title,
+        &title,
         r#"</title><link rel="stylesheet" href="style.css"/></head><body class="titlepage"><h1>"#,
-        title,
+        &title,
         r#"</h1></body></html>"#,
     ]
     .concat()
 }
 
-fn ncode_validate_and_normalize(s: &str) -> Option<String> {
-    let mut normalized = String::new();
-    let mut iter = s.chars();
-    normalized.push(iter.next().and_then(|ch| {
-        if ch == 'n' || ch == 'N' {
-            Some('n')
-        } else {
-            None
-        }
-    })?);
-    for _ in 0..4 {
-        normalized.push(iter.next().filter(char::is_ascii_digit)?);
-    }
-    for ch in iter {
-        normalized.push(
-            ch.is_ascii_alphabetic()
-                .then_some(ch.to_ascii_lowercase())?,
-        );
+// 読む速さの目安の一分あたりの文字数
+const CHARS_PER_MINUTE: u32 = 500;
+
+// 文字数から読み終えるまでの分数の目安を切り上げで求める
+fn reading_minutes(length: u32) -> u32 {
+    length.div_ceil(CHARS_PER_MINUTE)
+}
+
+// 奥付に記す取得時点の掲載の状況
+fn status_label(novel: &narou::Novel) -> String {
+    match novel.novel_type() {
+        narou::NovelType::ShortStory => "短編".to_string(),
+        narou::NovelType::Serial => novel.status().to_string(),
     }
-    Some(normalized)
+}
+
+// 奥付の取得日時は日本時間で記す。求められなければ UTC のまま記す
+fn jst_label(time: &DateTime) -> String {
+    time.to_unix_time()
+        .and_then(|seconds| DateTime::from_unix_time(seconds + 9 * 3600))
+        .and_then(|jst| {
+            Some(format!(
+                "{}年{}月{}日 {}時{:02}分{:02}秒 (日本時間)",
+                jst.year()?,
+                jst.month()?,
+                jst.day()?,
+                jst.hour()?,
+                jst.minute()?,
+                jst.second()?
+            ))
+        })
+        .unwrap_or_else(|| time.to_string())
+}
+
+// 取得元と取得した日時などを記録する奥付のページ
+fn make_colophon(novel: &narou::Novel, episodes: u32, downloaded: &DateTime) -> String {
+    // 文字数は分冊しても作品全体のもの
+    let length = novel.char_count().map_or(String::new(), |length| {
+        format!(
+            "<p>作品の文字数: {} 字 (読了まで約 {} 分)</p>",
+            length,
+            reading_minutes(length)
+        )
+    });
+    format!(
+        include_str!("colophon.txt"),
+        novel.title().escape(),
+        novel.source().escape(),
+        jst_label(downloaded),
+        episodes,
+        env!("CARGO_PKG_VERSION"),
+        length,
+        status_label(novel)
+    )
 }
 
 fn image_type_to_media_type(it: ImageType) -> MediaType {
@@ -150,112 +239,705 @@ fn image_type_to_media_type(it: ImageType) -> MediaType {
         ImageType::Gif => MediaType::Gif,
         ImageType::Jpg => MediaType::Jpg,
         ImageType::Png => MediaType::Png,
+        ImageType::Webp => MediaType::Webp,
     }
 }
 
-fn make_epub(ncode: &str, horizontal: bool, wait: f64) -> std::result::Result<(), narou::Error> {
-    let ncode = ncode_validate_and_normalize(ncode).ok_or(narou::Error::InvalidNcode)?;
-    let novel = narou::Novel::new(&ncode)?;
-    eprintln!("{}", novel.title());
-    let mut pb = Indicator::new(novel.episode()).ok();
-    let mut temporary = TemporaryFile::new(
-        &[
-            "[",
-            &sanitize(novel.author_name()),
-            "] ",
-            &sanitize(novel.title()),
-            ".epub",
-        ]
-        .concat(),
-    )
-    .or(Err(narou::Error::EpubBuildFailure))?;
-    let mut epub = Epub::new(temporary.handle.as_mut().unwrap())?;
-    epub.set_source(["https://ncode.syosetu.com/", &ncode, "/"].concat());
-    epub.set_author(
-        novel.author_name().to_string(),
-        novel.author_yomigana().to_string(),
-    );
-    // builder.set_uuid(uuid);
-    epub.set_title(novel.title().to_string());
-    epub.set_modified(novel.last_update().clone());
-    epub.set_description(novel.story().to_string());
-    epub.add_resource(
-        "style.css",
-        MediaType::Css,
-        ReferenceType::Style,
-        if horizontal {
-            include_bytes!("horizontal_style.css")
-        } else {
-            include_bytes!("style.css")
-        },
-    )?;
+// 本の表題とファイル名に使う表題。表題のページには作品の本来の表題を載せる
+fn book_title<'a>(novel: &'a narou::Novel, title: Option<&'a str>) -> &'a str {
+    title.unwrap_or(novel.title())
+}
+
+// ジャンルに続けてキーワードを並べる。キーワードにジャンル名が含まれていれば一つにまとめる
+fn subjects(novel: &narou::Novel) -> Vec<String> {
+    let mut subjects: Vec<String> = novel.genres().iter().map(|x| x.to_string()).collect();
+    for keyword in novel.keywords() {
+        if !subjects.contains(keyword) {
+            subjects.push(keyword.clone());
+        }
+    }
+    // 完結済みかどうかで本を探せるようにする
+    let completed = narou::Status::Completed.to_string();
+    if novel.status() == narou::Status::Completed && !subjects.contains(&completed) {
+        subjects.push(completed);
+    }
+    subjects
+}
+
+// 空白を除いた文字のうち英字の割合がこれを超えれば横書きにする
+const LATIN_RATIO_THRESHOLD: f64 = 0.5;
 
-    epub.set_direction(if horizontal {
+// 本文の英字の割合から書字方向を選ぶ
+fn detect_direction(body: &str) -> epub::Direction {
+    let (latin, total) =
+        body.chars()
+            .filter(|ch| !ch.is_whitespace())
+            .fold((0, 0), |(latin, total), ch| {
+                let is_latin = ch.is_ascii_alphanumeric()
+                    || ch.is_ascii_punctuation()
+                    || ('\u{c0}'..='\u{24f}').contains(&ch);
+                (latin + usize::from(is_latin), total + 1)
+            });
+    if total > 0 && latin as f64 / total as f64 > LATIN_RATIO_THRESHOLD {
         epub::Direction::Ltr
     } else {
         epub::Direction::Rtl
-    });
+    }
+}
 
-    epub.add_content(
-        "title.xhtml",
-        "表題",
-        MediaType::Xhtml,
-        1,
-        ReferenceType::Title,
-        make_title_page(&novel).as_bytes(),
-    )?;
-    let mut prev_chapter: Option<String> = None;
-    let mut filename_iter = Id::new_for_name();
-    for i in novel.episodes()? {
-        if INTERRUPTED.load(std::sync::atomic::Ordering::SeqCst) {
-            return Err(narou::Error::Interrupted);
-        }
-        if let Some(pb) = pb.as_mut() {
-            pb.increment();
-        }
-        let mut episode = i?;
-        // 新しい章の始まり
-        if prev_chapter != episode.chapter {
-            let chapter_title = episode
-                .chapter
-                .as_deref()
-                .ok_or(narou::Error::InvalidData)?;
-            epub.add_content(
-                format!("{}.xhtml", filename_iter.next().unwrap()).as_str(),
-                chapter_title,
-                MediaType::Xhtml,
-                1,
-                ReferenceType::Text,
-                make_chapter(chapter_title).as_bytes(),
-            )?;
-            prev_chapter = episode.chapter.clone();
-        };
+// 指定されたスタイルシートか、なければ同梱のものを返す
+fn load_style(css: Option<&str>, horizontal: bool) -> Result<Cow<'static, [u8]>, narou::Error> {
+    match css {
+        Some(path) => std::fs::read(path)
+            .ok()
+            .filter(|body| !body.is_empty())
+            .map(Cow::Owned)
+            .ok_or(narou::Error::InvalidStyleSheet),
+        None if horizontal => Ok(Cow::Borrowed(include_bytes!("horizontal_style.css"))),
+        None => Ok(Cow::Borrowed(include_bytes!("style.css"))),
+    }
+}
+
+/// 章と話を順に受け取って本を組み立てる出力先
+trait BookWriter {
+    fn add_chapter(&mut self, title: &str) -> Result<(), narou::Error>;
+    fn add_episode(&mut self, episode: Episode) -> Result<(), narou::Error>;
+}
+
+impl BookWriter for Epub<'_> {
+    fn add_chapter(&mut self, title: &str) -> Result<(), narou::Error> {
+        self.add_page(
+            title,
+            1,
+            ReferenceType::Text,
+            make_chapter(title).as_bytes(),
+        )?;
+        Ok(())
+    }
+
+    fn add_episode(&mut self, mut episode: Episode) -> Result<(), narou::Error> {
         for ImageInfo {
             name,
             image_type,
             body,
         } in std::mem::take(&mut episode.images)
         {
-            epub.add_resource(
+            // 前の話と同じ挿絵はこの本に一度だけ収録する
+            if self.has_resource(&name) {
+                continue;
+            }
+            self.add_resource_from(
                 name.as_str(),
                 image_type_to_media_type(image_type),
                 ReferenceType::Image,
-                &body,
+                &body[..],
             )?;
         }
-        epub.add_content(
-            format!("{}.xhtml", filename_iter.next().unwrap()).as_str(),
+        self.add_page_from(
             &episode.title,
-            MediaType::Xhtml,
             if episode.chapter.is_none() { 1 } else { 2 },
             ReferenceType::Text,
             episode.to_string().as_bytes(),
         )?;
-        thread::sleep(Duration::from_millis((wait * 1000.0) as u64));
+        Ok(())
+    }
+}
+
+/// 作品全体を一つにまとめたプレーンテキスト
+struct TextBook {
+    text: String,
+}
+
+impl TextBook {
+    fn new(novel: &narou::Novel) -> Self {
+        Self {
+            text: [novel.title(), "\n", novel.author_name(), "\n\n"].concat(),
+        }
+    }
+}
+
+impl BookWriter for TextBook {
+    fn add_chapter(&mut self, title: &str) -> Result<(), narou::Error> {
+        self.text.push_str(&["■ ", title, "\n\n"].concat());
+        Ok(())
+    }
+
+    fn add_episode(&mut self, episode: Episode) -> Result<(), narou::Error> {
+        self.text
+            .push_str(&["◆ ", &episode.title, "\n\n", &episode.plain_body(), "\n"].concat());
+        Ok(())
+    }
+}
+
+// 取得した話を順に出力先に渡し、章が変わったところで章の見出しを挟む
+//
+// 中断された場合はそこで止めて、それまでに渡した話数を返す。中断として扱うかは呼び出し側が決める
+fn write_episodes(
+    episodes: impl Iterator<Item = narou::Result<Episode>>,
+    progress: &mut dyn Progress,
+    writer: &mut impl BookWriter,
+    interrupted: &AtomicBool,
+) -> Result<u32, narou::Error> {
+    let mut prev_chapter: Option<String> = None;
+    let mut count = 0;
+    for i in episodes {
+        if interrupted.load(std::sync::atomic::Ordering::SeqCst) {
+            break;
+        }
+        let episode = match i {
+            Err(narou::Error::Interrupted) => break,
+            i => i?,
+        };
+        progress.advance(episode.number);
+        // 新しい章の始まり。章は話のページからしか分からないので、見出しは必ずその章の最初の話の直前に入り、
+        // 話のない章や見出しの連続は生じない
+        if prev_chapter != episode.chapter {
+            let chapter_title = episode
+                .chapter
+                .as_deref()
+                .ok_or(narou::Error::InvalidData)?;
+            writer.add_chapter(chapter_title)?;
+            prev_chapter = episode.chapter.clone();
+        };
+        writer.add_episode(episode)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+// 本文の前に置くもの
+#[derive(Clone, Copy)]
+struct FrontMatter {
+    title_page: bool,
+    cover: bool,
+}
+
+impl Default for FrontMatter {
+    fn default() -> Self {
+        Self {
+            title_page: true,
+            cover: false,
+        }
+    }
+}
+
+// --update で比べる前回の本のメタデータと各話のハッシュ
+struct PreviousBook {
+    summary: epub::read::EpubSummary,
+    hashes: EpisodeHashes,
+}
+
+impl PreviousBook {
+    // 読めないか各話のハッシュを書き留めていない本は作り直す
+    fn read(path: &Path) -> Option<Self> {
+        let summary = epub::read::read(path).ok()?;
+        let hashes = epub::read::read_sidecar(path, hashes::SIDECAR).ok()??;
+        Some(Self {
+            summary,
+            hashes: EpisodeHashes::parse(&hashes),
+        })
+    }
+
+    fn same_update(&self, modified: &DateTime) -> bool {
+        self.summary
+            .modified
+            .as_ref()
+            .is_some_and(|x| x.chronological_cmp(modified).is_eq())
+    }
+}
+
+// スタイルシートと表題のページに続けて各話を並べ、収録した話のハッシュを付属のファイルに書き留める。
+// 通信やファイルの後始末は呼び出し側で行う
+fn assemble_epub(
+    novel: &narou::Novel,
+    episodes: impl Iterator<Item = narou::Result<Episode>>,
+    epub: &mut Epub,
+    style: &[u8],
+    front: FrontMatter,
+    progress: &mut dyn Progress,
+    interrupted: &AtomicBool,
+) -> Result<u32, narou::Error> {
+    epub.add_resource("style.css", MediaType::Css, ReferenceType::Style, style)?;
+    // 表紙を作るときは表題のページにもその画像を使う
+    if front.cover {
+        epub.add_resource(
+            "cover.svg",
+            MediaType::Svg,
+            ReferenceType::Cover,
+            cover::make_cover(novel.title(), novel.author_name()).as_bytes(),
+        )?;
+    }
+    if front.title_page {
+        let title_page = if front.cover {
+            make_cover_page(novel)
+        } else {
+            make_title_page(novel)
+        };
+        epub.add_content(
+            "title.xhtml",
+            "表題",
+            MediaType::Xhtml,
+            1,
+            ReferenceType::Title,
+            title_page.as_bytes(),
+        )?;
+    }
+    let mut hashes = EpisodeHashes::new();
+    let episodes = episodes.inspect(|x| {
+        if let Ok(episode) = x {
+            hashes.insert(episode);
+        }
+    });
+    let count = write_episodes(episodes, progress, epub, interrupted)?;
+    epub.add_sidecar(hashes::SIDECAR, hashes.to_string().as_bytes())?;
+    Ok(count)
+}
+
+// 分冊するときの一冊分の話。size 話に達したら次の章の始まりで区切り、章の途中では区切らない。
+// 章のない作品は size 話ちょうどで区切る
+struct Volume<'a, I: Iterator<Item = narou::Result<Episode>>> {
+    episodes: &'a mut Peekable<I>,
+    size: u32,
+    count: u32,
+    chapter: Option<String>,
+}
+
+impl<'a, I: Iterator<Item = narou::Result<Episode>>> Volume<'a, I> {
+    fn new(episodes: &'a mut Peekable<I>, size: u32) -> Self {
+        Self {
+            episodes,
+            size,
+            count: 0,
+            chapter: None,
+        }
+    }
+}
+
+impl<I: Iterator<Item = narou::Result<Episode>>> Iterator for Volume<'_, I> {
+    type Item = narou::Result<Episode>;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.count >= self.size {
+            match self.episodes.peek() {
+                Some(Ok(episode))
+                    if episode.chapter.is_some() && episode.chapter == self.chapter => {}
+                _ => return None,
+            }
+        }
+        let episode = self.episodes.next()?;
+        if let Ok(episode) = &episode {
+            self.chapter = episode.chapter.clone();
+        }
+        self.count += 1;
+        Some(episode)
+    }
+}
+
+// 作品の情報を本のメタデータに記録する
+fn set_metadata(
+    epub: &mut Epub,
+    novel: &narou::Novel,
+    cmd: &command::Cmd,
+) -> Result<(), narou::Error> {
+    epub.set_source(novel.source());
+    epub.set_author(
+        novel.author_name().to_string(),
+        novel.author_yomigana().map(String::from),
+    );
+    if cmd.random_id {
+        let uuid = uuid::UUIDv4::new().ok_or(narou::Error::EpubBuildFailure)?;
+        epub.set_identifier(format!("urn:uuid:{}", uuid));
+    }
+    epub.set_title(book_title(novel, cmd.title.as_deref()).to_string());
+    epub.set_modified(novel.last_update().clone());
+    epub.set_description(novel.story().to_string());
+    epub.set_subjects(subjects(novel));
+    if let Some(length) = novel.char_count() {
+        epub.set_char_count(length);
+    }
+    if let Some(language) = &cmd.language {
+        epub.set_language(language.clone());
+    }
+    if let Some(publisher) = &cmd.publisher {
+        epub.set_publisher(publisher.clone());
+    }
+    if let Some(series) = &cmd.series {
+        epub.set_series(series.clone(), cmd.series_index);
+    }
+    if let Some(depth) = cmd.toc_depth {
+        epub.set_toc_depth(depth);
+    }
+    Ok(())
+}
+
+// 分冊した巻は表題に巻数を付け、作品の表題 (指定があればシリーズ名) をシリーズとして巻数を記録する。
+// 識別子も巻ごとに変える
+fn set_volume_metadata(
+    epub: &mut Epub,
+    novel: &narou::Novel,
+    cmd: &command::Cmd,
+    index: u32,
+) -> Result<(), narou::Error> {
+    let title = book_title(novel, cmd.title.as_deref());
+    epub.set_title(format!("{} ({})", title, index));
+    epub.set_series(
+        cmd.series.clone().unwrap_or_else(|| title.to_string()),
+        index,
+    );
+    if !cmd.random_id {
+        let name = format!("{}#{}", novel.source(), index);
+        let uuid = uuid::UUIDv5::new(name.as_bytes()).ok_or(narou::Error::EpubBuildFailure)?;
+        epub.set_identifier(format!("urn:uuid:{}", uuid));
+    }
+    Ok(())
+}
+
+// 本を作らずに作品の情報と目次だけを表示する
+fn print_outline(novel: &narou::Novel, cmd: &command::Cmd) -> Result<(), narou::Error> {
+    println!("{}", novel.title());
+    println!("作者: {}", novel.author_name());
+    println!("話数: {}", novel.episode());
+    println!("更新: {}", novel.last_update());
+    let outline = novel.outline(cmd.jobs, cmd.wait.range(), &INTERRUPTED, |episodes| {
+        if let Some(cache_dir) = &cmd.cache_dir {
+            episodes.set_cache(narou::Cache::new(Path::new(cache_dir))?);
+        }
+        if cmd.verbose {
+            episodes.set_verbose();
+        }
+        if let Some(since) = &cmd.since {
+            episodes.set_since(since.clone());
+        }
+        Ok(())
+    })?;
+    if INTERRUPTED.load(std::sync::atomic::Ordering::SeqCst) {
+        return Err(narou::Error::Interrupted);
+    }
+    let mut prev_chapter: Option<String> = None;
+    for episode in outline {
+        if prev_chapter != episode.chapter {
+            if let Some(chapter) = &episode.chapter {
+                println!("{}", chapter);
+            }
+            prev_chapter = episode.chapter.clone();
+        }
+        println!("  {}: {}", episode.number, episode.title);
+    }
+    Ok(())
+}
+
+// 本を作らずに一話だけを取得して本文を表示する
+fn print_episode(ncode: &str, number: u32) -> Result<(), narou::Error> {
+    // 一般の API で見つからなければ年齢制限のある作品として探し直す
+    let episode = match narou::fetch_episode(ncode, number, narou::Site::General) {
+        Err(narou::Error::NovelUnavailable) => {
+            narou::fetch_episode(ncode, number, narou::Site::R18)?
+        }
+        episode => episode?,
+    };
+    println!("{}\n\n{}", episode.title, episode.plain_body());
+    Ok(())
+}
+
+fn make_epub(
+    ncode: &str,
+    cmd: &command::Cmd,
+    summary: &mut Summary,
+) -> std::result::Result<(), narou::Error> {
+    let ncode = ncode_validate_and_normalize(ncode).ok_or(narou::Error::InvalidNcode)?;
+    if let Some(number) = cmd.episode {
+        return print_episode(&ncode, number);
+    }
+    let horizontal = cmd.direction == Some(epub::Direction::Ltr);
+    let style = load_style(cmd.css.as_deref(), horizontal)?;
+    let novel = narou::Novel::new(&ncode)?;
+    summary.title = Some(novel.title().to_string());
+    summary.author = Some(novel.author_name().to_string());
+    summary.episodes = Some(novel.episode());
+    if cmd.dry_run {
+        return print_outline(&novel, cmd);
+    }
+    let modified = novel.last_update().to_string();
+    let fold = |s: &str| {
+        if cmd.normalize_filename {
+            sanitize::normalize_width(s)
+        } else {
+            s.to_string()
+        }
+    };
+    let path = output::output_path(
+        cmd.output.as_deref(),
+        cmd.name_template
+            .as_deref()
+            .unwrap_or(output::DEFAULT_TEMPLATE),
+        &NameFields {
+            ncode: &ncode,
+            author: &fold(novel.author_name()),
+            title: &fold(book_title(&novel, cmd.title.as_deref())),
+            date: modified.get(..10).unwrap_or_default(),
+        },
+        cmd.format.extension(),
+    );
+    summary.output = Some(path.clone());
+    // --update では前回の本を読んでおき、全話を取得してから作り直すかを決める
+    let previous = match cmd.format {
+        OutputFormat::Epub if cmd.update && cmd.split_by.is_none() => PreviousBook::read(&path),
+        _ => None,
+    };
+    // 出力済みの本は作品の情報を取得しただけで飛ばす
+    if let Some(existing) =
+        output::existing_output(&path, cmd.overwrite).filter(|_| previous.is_none())
+    {
+        if !cmd.quiet {
+            report::line(&format!(
+                "{} は既にあるので飛ばします。",
+                existing.display()
+            ));
+        }
+        summary.output = Some(existing);
+        return Ok(());
+    }
+    // 雛形で作者ごとなどに分けたディレクトリはなければ作る
+    if let Some(parent) = path.parent().filter(|x| !x.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)?;
+    }
+    if !cmd.quiet {
+        report::progress(novel.title());
+    }
+    // 標準出力がコンソールでないか詳細を表示する場合は進捗表示で出力を乱さないようにする
+    let mut progress: Box<dyn Progress> =
+        if cmd.quiet || cmd.verbose || !std::io::stdout().is_terminal() {
+            Box::new(progress::Silent)
+        } else {
+            match Indicator::new() {
+                Ok(indicator) => Box::new(indicator),
+                Err(_) => Box::new(progress::Silent),
+            }
+        };
+    progress.start(novel.episode());
+    let mut iter = novel.episodes(cmd.jobs, cmd.wait.range(), &INTERRUPTED)?;
+    if let Some(cache_dir) = &cmd.cache_dir {
+        iter.set_cache(narou::Cache::new(Path::new(cache_dir))?);
+    }
+    if cmd.verbose {
+        iter.set_verbose();
+    }
+    if cmd.no_images {
+        iter.set_no_images();
+    }
+    if cmd.strip_author_notes {
+        iter.set_strip_notes();
+    }
+    if cmd.keep_links {
+        iter.set_keep_links();
+    }
+    iter.set_size_limits(cmd.max_image_size, cmd.max_total_size);
+    if let Some(since) = &cmd.since {
+        iter.set_since(since.clone());
+    }
+    if cmd.skip_errors {
+        iter.set_skip_errors();
+    }
+    // 作品の更新日時が前回と同じでも黙って改稿された話は見つけられるように、各話のハッシュを控える
+    let current = RefCell::new(EpisodeHashes::new());
+    let mut episodes = iter
+        .by_ref()
+        .inspect(|x| {
+            if let Ok(episode) = x {
+                current.borrow_mut().insert(episode);
+            }
+        })
+        .peekable();
+    // 指定の日付以降に更新された話がなければ本を作らない
+    if cmd.since.is_some() && episodes.peek().is_none() {
+        progress.finish();
+        if !cmd.quiet {
+            report::line(&format!(
+                "{} には指定の日付以降に更新された話がないので飛ばします。",
+                novel.title()
+            ));
+        }
+        summary.output = None;
+        return Ok(());
+    }
+    // 中断されたときは指定があればそれまでの話で本を仕上げる
+    let keep = |count: u32| {
+        if !INTERRUPTED.load(std::sync::atomic::Ordering::SeqCst) {
+            Ok(())
+        } else if cmd.keep_partial && count > 0 {
+            report::line(&format!("中断されたため {} 話までで本を作ります。", count));
+            Ok(())
+        } else {
+            Err(narou::Error::Interrupted)
+        }
+    };
+    match cmd.format {
+        OutputFormat::Txt => {
+            let mut temporary =
+                TemporaryFile::new(&path).or(Err(narou::Error::EpubBuildFailure))?;
+            let mut book = TextBook::new(&novel);
+            keep(write_episodes(
+                &mut episodes,
+                progress.as_mut(),
+                &mut book,
+                &INTERRUPTED,
+            )?)?;
+            temporary
+                .handle
+                .as_mut()
+                .unwrap()
+                .write_all(book.text.as_bytes())?;
+            temporary.finish()?;
+            summary.size = std::fs::metadata(&path).ok().map(|x| x.len());
+        }
+        OutputFormat::Epub => {
+            // 自動の場合は最初の話を覗いて決める。明示された書字方向が優先する
+            let direction = match (cmd.direction, episodes.peek()) {
+                (Some(direction), _) => direction,
+                (None, Some(Ok(episode))) if cmd.auto_orientation => {
+                    detect_direction(&episode.plain_body())
+                }
+                _ => epub::Direction::Rtl,
+            };
+            let style = match direction {
+                epub::Direction::Ltr if !horizontal => load_style(cmd.css.as_deref(), true)?,
+                _ => style,
+            };
+            let front = FrontMatter {
+                title_page: !cmd.no_title_page,
+                cover: cmd.gen_cover,
+            };
+            // 一冊に収まる話数なら分冊しない
+            let split_by = cmd.split_by.filter(|&size| novel.episode() > size);
+            // 巻の数は章の区切りによって変わるので、全巻を作り終えてから名前を決める
+            let mut volumes = vec![];
+            let mut written = 0;
+            // 表題のページや奥付の有無など、本文の並びが前回と違えば作り直す
+            let mut layout = vec![];
+            // 奥付には取得を始めた日時を記録し、分冊しても全巻で同じにする
+            let downloaded = if cmd.colophon {
+                Some(DateTime::now().ok_or(narou::Error::EpubBuildFailure)?)
+            } else {
+                None
+            };
+            loop {
+                let index = volumes.len() as u32 + 1;
+                let mut temporary =
+                    TemporaryFile::new(&path).or(Err(narou::Error::EpubBuildFailure))?;
+                let mut epub = Epub::new(temporary.handle.as_mut().unwrap())?;
+                set_metadata(&mut epub, &novel, cmd)?;
+                // 作り直しても同じ本として扱われるように前回の識別子を引き継ぐ
+                if let Some(previous) = &previous {
+                    epub.set_identifier(previous.summary.identifier.clone());
+                }
+                if split_by.is_some() {
+                    set_volume_metadata(&mut epub, &novel, cmd, index)?;
+                }
+                epub.set_direction(direction);
+                epub.set_compression(cmd.compression);
+                let count = match split_by {
+                    Some(size) => assemble_epub(
+                        &novel,
+                        Volume::new(&mut episodes, size),
+                        &mut epub,
+                        &style,
+                        front,
+                        progress.as_mut(),
+                        &INTERRUPTED,
+                    )?,
+                    None => assemble_epub(
+                        &novel,
+                        &mut episodes,
+                        &mut epub,
+                        &style,
+                        front,
+                        progress.as_mut(),
+                        &INTERRUPTED,
+                    )?,
+                };
+                written += count;
+                keep(written)?;
+                // 巻の区切りの直後に中断されたときは空の巻を作らない
+                if count == 0 && !volumes.is_empty() {
+                    break;
+                }
+                // 奥付は URL や日時を並べるので縦書きの本でも横書きにする
+                if let Some(downloaded) = &downloaded {
+                    epub.add_page_with_writing_mode(
+                        "この本について",
+                        1,
+                        ReferenceType::Backmatter,
+                        epub::WritingMode::HorizontalTb,
+                        &make_colophon(&novel, count, downloaded),
+                    )?;
+                }
+                if previous.is_some() {
+                    layout = epub.content_names().into_iter().map(String::from).collect();
+                }
+                epub.finish()?;
+                drop(epub);
+                volumes.push(temporary);
+                if episodes.peek().is_none()
+                    || INTERRUPTED.load(std::sync::atomic::Ordering::SeqCst)
+                {
+                    break;
+                }
+            }
+            let rebuild = match &previous {
+                Some(previous) => {
+                    let changed = previous.hashes.changed(&current.borrow());
+                    if cmd.verbose {
+                        for number in &changed {
+                            report::line(&format!("第{}話は前回の本から変わっています。", number));
+                        }
+                    }
+                    !changed.is_empty()
+                        || !previous.same_update(novel.last_update())
+                        || previous.summary.contents != layout
+                }
+                None => true,
+            };
+            // 変わっていなければ一時ファイルを捨てて前回の本をそのまま残す
+            if let Some(previous) = previous.as_ref().filter(|_| !rebuild) {
+                volumes.clear();
+                if !cmd.quiet {
+                    report::line(&format!(
+                        "{} は前回から変わっていないので作り直しません。",
+                        previous.summary.title
+                    ));
+                }
+            }
+            let total = volumes.len();
+            let mut size = if rebuild {
+                0
+            } else {
+                std::fs::metadata(&path).map_or(0, |x| x.len())
+            };
+            for (i, mut temporary) in volumes.into_iter().enumerate() {
+                if split_by.is_some() {
+                    temporary.true_name = output::volume_path(&path, i + 1, total);
+                    if i == 0 {
+                        summary.output = Some(temporary.true_name.clone());
+                    }
+                }
+                temporary.finish()?;
+                size += std::fs::metadata(&temporary.true_name).map_or(0, |x| x.len());
+            }
+            summary.size = Some(size);
+        }
+    }
+    progress.finish();
+    drop(episodes);
+    if !cmd.quiet {
+        for (number, error) in iter.skipped() {
+            report::line(&format!(
+                "第{}話は取得できなかったので飛ばしました。 ({})",
+                number, error
+            ));
+        }
+    }
+    if INTERRUPTED.load(std::sync::atomic::Ordering::SeqCst) {
+        return Err(narou::Error::Interrupted);
     }
-    epub.finish()?;
-    drop(epub);
-    temporary.finish()?;
     Ok(())
 }
 
@@ -267,33 +949,795 @@ unsafe extern "system" fn handler(_: u32) -> i32 {
 }
 
 fn main() {
-    let cmd = match command::Cmd::parse() {
+    let mut cmd = match command::Cmd::parse() {
         Err(e) => {
-            println!("{}", e);
+            report::init(Path::new("."));
+            report::line(&e.to_string());
             std::process::exit(2);
         }
         Ok(s) => s,
     };
+    report::init(&output::output_directory(cmd.output.as_deref()));
+    narou::set_over18(cmd.over18);
+    narou::set_user_agent(cmd.user_agent.clone());
+    narou::set_proxy(cmd.proxy.clone());
+    if !cmd.force_no_wait && cmd.wait.raise_to(command::MIN_WAIT) {
+        report::line(&format!(
+            "アクセスの間隔が短すぎるため {} 秒にします。 (--force-no-wait で無効にできます)",
+            command::MIN_WAIT
+        ));
+    }
 
     // CTRL+C を押された場合を処理するハンドラを追加
     unsafe { SetConsoleCtrlHandler(Some(handler), 1) };
 
-    for ncode in cmd.ncodes {
-        if let Err(x) = make_epub(&ncode, cmd.horizontal, cmd.wait) {
-            println!("{}", x);
+    let wait = Duration::from_secs_f64(cmd.wait.min);
+    let novel_wait = match cmd.novel_wait {
+        Some(seconds) if !cmd.force_no_wait && seconds < command::MIN_WAIT => {
+            report::line(&format!(
+                "作品の間の待機時間が短すぎるため {} 秒にします。 (--force-no-wait で無効にできます)",
+                command::MIN_WAIT
+            ));
+            Duration::from_secs_f64(command::MIN_WAIT)
+        }
+        Some(seconds) => Duration::from_secs_f64(seconds),
+        None => wait,
+    };
+    let mut ncodes = cmd.ncodes.clone();
+    // 作品の一覧を取得できなかった作者は失敗として記録し、他の作品の変換は続ける
+    let mut failed_authors: BatchResults = vec![];
+    for &userid in &cmd.authors {
+        match narou::author_works(userid, wait) {
+            Ok(works) => ncodes.extend(works),
+            Err(x) => failed_authors.push((userid.to_string(), Err(x))),
+        }
+    }
+
+    let results = match run_batch(
+        &ncodes,
+        novel_wait,
+        &mut SystemClock,
+        &INTERRUPTED,
+        |ncode| {
+            let start = Instant::now();
+            let mut summary = Summary::new(ncode);
+            let result = make_epub(ncode, &cmd, &mut summary);
+            if cmd.json_summary {
+                summary.elapsed = start.elapsed();
+                summary.error = result.as_ref().err().map(ToString::to_string);
+                println!("{}", summary.to_json());
+            }
+            result
+        },
+    ) {
+        Ok(results) => failed_authors
+            .into_iter()
+            .chain(results)
+            .collect::<BatchResults>(),
+        Err(x) => {
+            report::line(&x.to_string());
             std::process::exit(2);
         }
+    };
+    // 失敗はここで一度だけ表示する。複数の作品を変換したときは成否の一覧にする
+    let listing = results.len() > 1;
+    for (ncode, result) in &results {
+        match result {
+            Ok(()) if listing => report::line(&format!("成功: {}", ncode)),
+            Ok(()) => {}
+            Err(x) if listing => report::line(&format!("失敗: {}: {}", ncode, x)),
+            Err(x) => report::line(&format!("{}: {}", ncode, x)),
+        }
+    }
+    if results.iter().any(|(_, result)| result.is_err()) {
+        std::process::exit(2);
+    }
+}
+
+// 作品ごとの変換の結果
+type BatchResults = Vec<(String, Result<(), narou::Error>)>;
+
+// 作品の間の待機に使う時計で、試験では実際には眠らずに記録する
+trait Clock {
+    fn sleep(&mut self, duration: Duration);
+}
+
+struct SystemClock;
+
+impl Clock for SystemClock {
+    fn sleep(&mut self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+}
+
+// 中断に素早く応じられるように区切って眠る
+const PAUSE_STEP: Duration = Duration::from_millis(100);
+
+// 待機の途中で中断されたら打ち切って false を返す
+fn pause(wait: Duration, clock: &mut impl Clock, interrupted: &AtomicBool) -> bool {
+    let mut remaining = wait;
+    while !remaining.is_zero() {
+        if interrupted.load(std::sync::atomic::Ordering::SeqCst) {
+            return false;
+        }
+        let step = remaining.min(PAUSE_STEP);
+        clock.sleep(step);
+        remaining -= step;
+    }
+    !interrupted.load(std::sync::atomic::Ordering::SeqCst)
+}
+
+// 一つの作品の失敗では止めずに残りを続けるが、中断された場合はその場で止める
+fn run_batch(
+    ncodes: &[String],
+    wait: Duration,
+    clock: &mut impl Clock,
+    interrupted: &AtomicBool,
+    mut build: impl FnMut(&str) -> Result<(), narou::Error>,
+) -> Result<BatchResults, narou::Error> {
+    let mut results = vec![];
+    for (i, ncode) in ncodes.iter().enumerate() {
+        if interrupted.load(std::sync::atomic::Ordering::SeqCst)
+            || (i > 0 && !pause(wait, clock, interrupted))
+        {
+            return Err(narou::Error::Interrupted);
+        }
+        match build(ncode) {
+            Err(narou::Error::Interrupted) => return Err(narou::Error::Interrupted),
+            result => results.push((ncode.clone(), result)),
+        }
     }
+    Ok(results)
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
 
+    #[cfg(windows)]
+    #[test]
+    fn overwrite_read_only() {
+        let path = std::env::temp_dir().join("narou-epub-test-overwrite.txt");
+        std::fs::write(&path, "old").unwrap();
+        let mut permissions = std::fs::metadata(&path).unwrap().permissions();
+        permissions.set_readonly(true);
+        std::fs::set_permissions(&path, permissions).unwrap();
+
+        let mut temporary = TemporaryFile::new(&path).unwrap();
+        temporary
+            .handle
+            .as_mut()
+            .unwrap()
+            .write_all(b"new")
+            .unwrap();
+        temporary.finish().unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn chapter_title_escape() {
+        let page = make_chapter("前編 & <後編>");
+        assert!(page.contains("<h1>前編 &amp; &lt;後編&gt;</h1>"));
+    }
+
+    #[test]
+    fn colophon() {
+        let novel = narou::Novel::fixture("表題 & 副題", "作者", 12);
+        let downloaded = DateTime::from_unix_time(1709241917).unwrap();
+        let page = make_colophon(&novel, 12, &downloaded);
+        assert!(page.contains(r#"<a href="https://ncode.syosetu.com/n0000a/">"#));
+        assert!(page.contains("収録話数: 12 話"));
+        assert!(page.contains("掲載状況: 連載中"));
+        assert!(page.contains("取得日時: 2024年3月1日 6時25分17秒 (日本時間)"));
+        assert!(page.contains("<p>表題 &amp; 副題</p>"));
+        assert!(page.contains(env!("CARGO_PKG_VERSION")));
+        // 文字数の分からない作品には読了時間を載せない
+        assert!(!page.contains("文字数"));
+    }
+
+    // 完結した連載と短編は検索できるように主題に完結を加える
+    #[test]
+    fn completion() {
+        let recorded = std::sync::Arc::new(narou::Recorded::new());
+        let novel = narou::Novel::with_fetcher("n0003a", recorded.clone()).unwrap();
+        assert_eq!(
+            subjects(&novel),
+            ["ファンタジー", "ハイファンタジー", "完結"]
+        );
+        assert_eq!(status_label(&novel), "完結");
+        let novel = narou::Novel::with_fetcher("n0002a", recorded.clone()).unwrap();
+        assert_eq!(subjects(&novel), ["文芸", "純文学", "完結"]);
+        assert_eq!(status_label(&novel), "短編");
+        let novel = narou::Novel::with_fetcher("n0001a", recorded).unwrap();
+        assert!(!subjects(&novel).contains(&"完結".to_string()));
+    }
+
+    #[test]
+    fn reading_time() {
+        assert_eq!(reading_minutes(0), 0);
+        assert_eq!(reading_minutes(1), 1);
+        assert_eq!(reading_minutes(500), 1);
+        assert_eq!(reading_minutes(100001), 201);
+    }
+
+    #[test]
+    fn title_override() {
+        let novel = narou::Novel::fixture("表題", "作者", 1);
+        assert_eq!(book_title(&novel, None), "表題");
+        let title = book_title(&novel, Some("表題 (第2巻)"));
+        assert_eq!(title, "表題 (第2巻)");
+        let fields = NameFields {
+            ncode: "n0000a",
+            author: novel.author_name(),
+            title,
+            date: "2024-01-01",
+        };
+        assert_eq!(
+            output::output_path(None, output::DEFAULT_TEMPLATE, &fields, "epub"),
+            PathBuf::from("[作者] 表題 (第2巻).epub")
+        );
+        // 表題のページは本来の表題のまま
+        assert!(make_title_page(&novel).contains("<h1>表題</h1>"));
+    }
+
+    #[test]
+    fn direction() {
+        assert!(matches!(
+            detect_direction("吾輩は猫である。名前はまだ無い。"),
+            epub::Direction::Rtl
+        ));
+        assert!(matches!(
+            detect_direction("fn main() { println!(\"Hello, world!\"); } を実行する"),
+            epub::Direction::Ltr
+        ));
+        assert!(matches!(
+            detect_direction("Café crème et œuvre"),
+            epub::Direction::Ltr
+        ));
+        // 英字が少し混ざる程度なら縦書き
+        assert!(matches!(
+            detect_direction("彼は VRMMO の世界で HP を回復した。"),
+            epub::Direction::Rtl
+        ));
+        assert!(matches!(detect_direction(""), epub::Direction::Rtl));
+    }
+
+    #[test]
+    fn image_type_to_media_type_test() {
+        assert!(image_type_to_media_type(ImageType::Webp) == MediaType::Webp);
+        assert!(image_type_to_media_type(ImageType::Gif) == MediaType::Gif);
+    }
+
+    #[test]
+    fn load_style_test() {
+        let path = std::env::temp_dir().join("narou-epub-test-style.css");
+        std::fs::write(&path, "p { margin: 1em; }").unwrap();
+        let style = load_style(path.to_str(), false);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(style.unwrap().as_ref(), b"p { margin: 1em; }");
+
+        std::fs::write(&path, "").unwrap();
+        let style = load_style(path.to_str(), false);
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(style, Err(narou::Error::InvalidStyleSheet)));
+
+        assert_eq!(
+            load_style(None, true).unwrap().as_ref(),
+            include_bytes!("horizontal_style.css")
+        );
+    }
+
+    // 本文が一段落だけの話
+    fn episode(number: u32, chapter: Option<&str>) -> narou::Result<Episode> {
+        Ok(Episode {
+            number,
+            chapter: chapter.map(String::from),
+            title: format!("第{}話", number),
+            blocks: narou::block::parse("<p>本文</p>"),
+            series: true,
+            updated: None,
+            images: vec![],
+        })
+    }
+
+    // 組み立てた本の話数と収録したファイルの並び
+    struct Built {
+        count: u32,
+        contents: Vec<String>,
+        levels: Vec<u32>,
+        resources: Vec<String>,
+    }
+
+    // 試験で組み立てる本の一時ファイル。確かめる途中で落ちても消えるように Drop で後始末する
+    struct TempBook(PathBuf);
+
+    impl TempBook {
+        fn new(name: &str) -> Self {
+            Self(std::env::temp_dir().join(["narou-epub-test-", name, ".epub"].concat()))
+        }
+
+        // prepare でメタデータを設定してから話を並べて本を閉じる。同じファイルに何度でも作り直せる
+        fn assemble(
+            &self,
+            novel: &narou::Novel,
+            episodes: impl Iterator<Item = narou::Result<Episode>>,
+            front: FrontMatter,
+            interrupted: &AtomicBool,
+            prepare: impl FnOnce(&mut Epub),
+        ) -> Built {
+            let mut file = File::create(&self.0).unwrap();
+            let mut epub = Epub::new(&mut file).unwrap();
+            prepare(&mut epub);
+            let count = assemble_epub(
+                novel,
+                episodes,
+                &mut epub,
+                b"",
+                front,
+                &mut progress::Silent,
+                interrupted,
+            )
+            .unwrap();
+            epub.finish().unwrap();
+            Built {
+                count,
+                contents: epub
+                    .content_levels()
+                    .iter()
+                    .map(|x| x.0.to_string())
+                    .collect(),
+                levels: epub.content_levels().iter().map(|x| x.1).collect(),
+                resources: epub
+                    .resource_names()
+                    .iter()
+                    .map(|x| x.to_string())
+                    .collect(),
+            }
+        }
+    }
+
+    impl Drop for TempBook {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn text_book() {
+        let mut book = TextBook {
+            text: String::new(),
+        };
+        let episodes =
+            (1..=3).map(|number| episode(number, Some(if number < 3 { "一章" } else { "二章" })));
+        write_episodes(
+            episodes,
+            &mut progress::Silent,
+            &mut book,
+            &AtomicBool::new(false),
+        )
+        .unwrap();
+        for title in ["第1話", "第2話", "第3話"] {
+            assert!(book.text.contains(&["◆ ", title, "\n\n本文\n"].concat()));
+        }
+        assert_eq!(book.text.matches("■ 一章").count(), 1);
+        assert_eq!(book.text.matches("■ 二章").count(), 1);
+    }
+
+    // 話を一つ書くたびにその番号で進捗を知らせる
+    #[test]
+    fn progress_advance() {
+        #[derive(Default)]
+        struct Recording {
+            total: Option<u32>,
+            advanced: Vec<u32>,
+        }
+        impl Progress for Recording {
+            fn start(&mut self, total: u32) {
+                self.total = Some(total);
+            }
+            fn advance(&mut self, current: u32) {
+                self.advanced.push(current);
+            }
+            fn finish(&mut self) {}
+        }
+        let episodes = (1..=3).map(|number| episode(number, None));
+        let mut book = TextBook {
+            text: String::new(),
+        };
+        let mut recording = Recording::default();
+        recording.start(3);
+        write_episodes(episodes, &mut recording, &mut book, &AtomicBool::new(false)).unwrap();
+        assert_eq!(recording.total, Some(3));
+        assert_eq!(recording.advanced, [1, 2, 3]);
+    }
+
+    // 章が話ごとに続けて変わっても、見出しはそれぞれの最初の話の直前にだけ入る
+    #[test]
+    fn chapter_headings() {
+        #[derive(Default)]
+        struct Calls(Vec<String>);
+        impl BookWriter for Calls {
+            fn add_chapter(&mut self, title: &str) -> Result<(), narou::Error> {
+                self.0.push(format!("章:{}", title));
+                Ok(())
+            }
+            fn add_episode(&mut self, episode: Episode) -> Result<(), narou::Error> {
+                self.0.push(format!("話:{}", episode.title));
+                Ok(())
+            }
+        }
+        let mut calls = Calls::default();
+        let episodes = vec![
+            episode(1, Some("一章")),
+            episode(2, Some("二章")),
+            episode(3, Some("三章")),
+            episode(4, Some("三章")),
+            Err(narou::Error::Interrupted),
+            episode(5, Some("四章")),
+        ];
+        let count = write_episodes(
+            episodes.into_iter(),
+            &mut progress::Silent,
+            &mut calls,
+            &AtomicBool::new(false),
+        )
+        .unwrap();
+        assert_eq!(count, 4);
+        assert_eq!(
+            calls.0,
+            [
+                "章:一章",
+                "話:第1話",
+                "章:二章",
+                "話:第2話",
+                "章:三章",
+                "話:第3話",
+                "話:第4話"
+            ]
+        );
+    }
+
+    #[test]
+    fn assemble() {
+        let novel = narou::Novel::fixture("表題", "作者", 3);
+        let episodes = (1..=3).map(|number| {
+            let mut episode = episode(number, Some(if number < 3 { "一章" } else { "二章" }))?;
+            if number == 1 {
+                episode.images = vec![ImageInfo {
+                    name: "1.png".to_string(),
+                    image_type: ImageType::Png,
+                    body: vec![0].into(),
+                }];
+            }
+            Ok(episode)
+        });
+        let book = TempBook::new("assemble");
+        let built = book.assemble(
+            &novel,
+            episodes,
+            FrontMatter::default(),
+            &AtomicBool::new(false),
+            |_| {},
+        );
+        // 表題、一章、第一話、第二話、二章、第三話
+        assert_eq!(built.levels, [1, 1, 2, 2, 1, 2]);
+        assert_eq!(built.contents[0], "title.xhtml");
+        assert_eq!(built.resources, ["style.css", "1.png", "_nav.xhtml"]);
+    }
+
+    // 記録した応答から本を組み立て、収録されるファイルの並びを確かめる
+    #[test]
+    fn recorded_series() {
+        static NOT_INTERRUPTED: AtomicBool = AtomicBool::new(false);
+        let novel =
+            narou::Novel::with_fetcher("n0001a", std::sync::Arc::new(narou::Recorded::new()))
+                .unwrap();
+        assert_eq!(novel.title(), "試験の物語");
+        assert_eq!(
+            subjects(&novel),
+            ["ファンタジー", "ハイファンタジー", "異世界", "冒険"]
+        );
+        let episodes = novel
+            .episodes(2, Duration::ZERO..=Duration::ZERO, &NOT_INTERRUPTED)
+            .unwrap();
+        let book = TempBook::new("recorded");
+        let built = book.assemble(
+            &novel,
+            episodes,
+            FrontMatter::default(),
+            &NOT_INTERRUPTED,
+            |epub| {
+                epub.set_modified(novel.last_update().clone());
+            },
+        );
+        assert_eq!(built.count, 2);
+        // 表題、一章、第一話、二章、第二話
+        assert_eq!(
+            built.contents,
+            ["title.xhtml", "0.xhtml", "1.xhtml", "2.xhtml", "3.xhtml"]
+        );
+        assert_eq!(built.levels, [1, 1, 2, 1, 2]);
+        // 二つの話の同じ挿絵は一度だけ収録する
+        assert_eq!(built.resources, ["style.css", "0.png", "_nav.xhtml"]);
+        // --update で前回の本として読み戻すと、書き留めたハッシュは取り直した同じ話と一致する
+        let previous = PreviousBook::read(&book.0).unwrap();
+        assert!(previous.same_update(novel.last_update()));
+        assert_eq!(previous.summary.contents, built.contents);
+        let stored = previous.hashes;
+        let episodes = novel
+            .episodes(2, Duration::ZERO..=Duration::ZERO, &NOT_INTERRUPTED)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let mut fetched = EpisodeHashes::new();
+        for episode in &episodes {
+            fetched.insert(episode);
+        }
+        assert_eq!(stored.to_string().lines().count(), 2);
+        assert_eq!(stored, fetched);
+    }
+
+    // 作った表紙は表紙の画像として収録し、表題のページからも参照する
     #[test]
-    fn test() {
-        assert_eq!(ncode_validate_and_normalize("n1234a").unwrap(), "n1234a");
-        assert_eq!(ncode_validate_and_normalize("N1234a").unwrap(), "n1234a");
-        assert_eq!(ncode_validate_and_normalize("n1234A").unwrap(), "n1234a");
+    fn recorded_cover() {
+        static NOT_INTERRUPTED: AtomicBool = AtomicBool::new(false);
+        let novel =
+            narou::Novel::with_fetcher("n0002a", std::sync::Arc::new(narou::Recorded::new()))
+                .unwrap();
+        let episodes = novel
+            .episodes(1, Duration::ZERO..=Duration::ZERO, &NOT_INTERRUPTED)
+            .unwrap();
+        let built = TempBook::new("cover").assemble(
+            &novel,
+            episodes,
+            FrontMatter {
+                cover: true,
+                ..Default::default()
+            },
+            &NOT_INTERRUPTED,
+            |_| {},
+        );
+        assert_eq!(built.resources, ["style.css", "cover.svg", "_nav.xhtml"]);
+        assert!(make_cover_page(&novel).contains(r#"<img class="cover" src="cover.svg""#));
+    }
+
+    // 表題のページを省いても本として読み戻せる
+    #[test]
+    fn recorded_without_title_page() {
+        static NOT_INTERRUPTED: AtomicBool = AtomicBool::new(false);
+        let novel =
+            narou::Novel::with_fetcher("n0002a", std::sync::Arc::new(narou::Recorded::new()))
+                .unwrap();
+        let episodes = novel
+            .episodes(1, Duration::ZERO..=Duration::ZERO, &NOT_INTERRUPTED)
+            .unwrap();
+        let book = TempBook::new("no-title-page");
+        let built = book.assemble(
+            &novel,
+            episodes,
+            FrontMatter {
+                title_page: false,
+                ..Default::default()
+            },
+            &NOT_INTERRUPTED,
+            |epub| {
+                epub.set_title(novel.title().to_string());
+            },
+        );
+        assert_eq!(built.count, 1);
+        assert!(built.contents.iter().all(|name| name != "title.xhtml"));
+        let summary = epub::read::read(&book.0).unwrap();
+        assert_eq!(summary.title, novel.title());
+        assert_eq!(summary.contents, built.contents);
+    }
+
+    // 作者の読み仮名が登録されていなくても本を作れる
+    #[test]
+    fn recorded_without_yomikata() {
+        static NOT_INTERRUPTED: AtomicBool = AtomicBool::new(false);
+        let novel =
+            narou::Novel::with_fetcher("n0005a", std::sync::Arc::new(narou::Recorded::new()))
+                .unwrap();
+        let episodes = novel
+            .episodes(1, Duration::ZERO..=Duration::ZERO, &NOT_INTERRUPTED)
+            .unwrap();
+        let built = TempBook::new("no-yomikata").assemble(
+            &novel,
+            episodes,
+            FrontMatter::default(),
+            &NOT_INTERRUPTED,
+            |epub| {
+                epub.set_author(
+                    novel.author_name().to_string(),
+                    novel.author_yomigana().map(String::from),
+                );
+            },
+        );
+        assert_eq!(built.count, 1);
+    }
+
+    #[test]
+    fn partial() {
+        let novel = narou::Novel::fixture("表題", "作者", 4);
+        let interrupted = AtomicBool::new(false);
+        // 三話目を取り出したところで中断されたことにする
+        let episodes = (1..=4).map(|number| {
+            if number == 3 {
+                interrupted.store(true, std::sync::atomic::Ordering::SeqCst);
+            }
+            episode(number, None)
+        });
+        let book = TempBook::new("partial");
+        let built = book.assemble(
+            &novel,
+            episodes,
+            FrontMatter::default(),
+            &interrupted,
+            |_| {},
+        );
+        assert_eq!(built.count, 2);
+        // 表題と二話分
+        assert_eq!(built.contents.len(), 3);
+        assert!(std::fs::metadata(&book.0).unwrap().len() > 0);
+    }
+
+    // 十話を四話ずつ分冊すると四話、四話、二話の三巻になり、目次はそれぞれの巻の話だけを載せる
+    #[test]
+    fn split_volumes() {
+        let novel = narou::Novel::fixture("表題", "作者", 10);
+        let mut episodes = (1..=10).map(|number| episode(number, None)).peekable();
+        let book = TempBook::new("split");
+        let mut counts = vec![];
+        while episodes.peek().is_some() {
+            let built = book.assemble(
+                &novel,
+                Volume::new(&mut episodes, 4),
+                FrontMatter::default(),
+                &AtomicBool::new(false),
+                |_| {},
+            );
+            // 表題と各話
+            assert_eq!(built.contents.len() as u32, built.count + 1);
+            counts.push(built.count);
+        }
+        assert_eq!(counts, [4, 4, 2]);
+
+        // 章の途中では区切らない
+        let chapters = [
+            "一章", "一章", "一章", "二章", "二章", "二章", "三章", "三章", "三章", "四章",
+        ];
+        let mut episodes = (1..=10)
+            .map(|number| episode(number, Some(chapters[number as usize - 1])))
+            .peekable();
+        let mut counts = vec![];
+        while episodes.peek().is_some() {
+            counts.push(Volume::new(&mut episodes, 4).count());
+        }
+        assert_eq!(counts, [6, 4]);
+    }
+
+    // 巻の区切りをまたいで同じ挿絵を使っても、どの巻にも画像を収録する
+    #[test]
+    fn split_volumes_images() {
+        static NOT_INTERRUPTED: AtomicBool = AtomicBool::new(false);
+        let novel =
+            narou::Novel::with_fetcher("n0001a", std::sync::Arc::new(narou::Recorded::new()))
+                .unwrap();
+        let mut episodes = novel
+            .episodes(2, Duration::ZERO..=Duration::ZERO, &NOT_INTERRUPTED)
+            .unwrap()
+            .peekable();
+        let book = TempBook::new("split-images");
+        let mut volumes = vec![];
+        while episodes.peek().is_some() {
+            let built = book.assemble(
+                &novel,
+                Volume::new(&mut episodes, 1),
+                FrontMatter::default(),
+                &NOT_INTERRUPTED,
+                |_| {},
+            );
+            volumes.push(built.resources.join(" "));
+        }
+        assert_eq!(
+            volumes,
+            ["style.css 0.png _nav.xhtml", "style.css 0.png _nav.xhtml"]
+        );
+    }
+
+    #[derive(Default)]
+    struct RecordingClock(Vec<Duration>);
+
+    impl Clock for RecordingClock {
+        fn sleep(&mut self, duration: Duration) {
+            self.0.push(duration);
+        }
+    }
+
+    // 続けて変換する三つの作品
+    fn batch_ncodes() -> Vec<String> {
+        ["n0001a", "n0002a", "n0003a"]
+            .into_iter()
+            .map(String::from)
+            .collect()
+    }
+
+    #[test]
+    fn batch() {
+        let mut clock = RecordingClock::default();
+        let interrupted = AtomicBool::new(false);
+        let ncodes = batch_ncodes();
+        let mut built = vec![];
+        let results = run_batch(&ncodes, Duration::ZERO, &mut clock, &interrupted, |ncode| {
+            built.push(ncode.to_string());
+            if ncode == "n0002a" {
+                Err(narou::Error::InvalidData)
+            } else {
+                Ok(())
+            }
+        })
+        .unwrap();
+        assert_eq!(built, ncodes);
+        assert!(results[0].1.is_ok());
+        assert!(matches!(results[1].1, Err(narou::Error::InvalidData)));
+        assert!(results[2].1.is_ok());
+
+        let mut built = vec![];
+        let results = run_batch(&ncodes, Duration::ZERO, &mut clock, &interrupted, |ncode| {
+            built.push(ncode.to_string());
+            Err(narou::Error::Interrupted)
+        });
+        assert!(matches!(results, Err(narou::Error::Interrupted)));
+        assert_eq!(built, ["n0001a"]);
+    }
+
+    // 作品の間にだけ待機し、待機中に中断されたら次の作品に進まない
+    #[test]
+    fn batch_wait() {
+        let ncodes = batch_ncodes();
+        let interrupted = AtomicBool::new(false);
+        let mut clock = RecordingClock::default();
+        let results = run_batch(
+            &ncodes,
+            Duration::from_millis(250),
+            &mut clock,
+            &interrupted,
+            |_| Ok(()),
+        )
+        .unwrap();
+        assert_eq!(results.len(), 3);
+        let step = |millis| Duration::from_millis(millis);
+        assert_eq!(
+            clock.0,
+            [
+                step(100),
+                step(100),
+                step(50),
+                step(100),
+                step(100),
+                step(50)
+            ]
+        );
+
+        struct Interrupting<'a>(&'a AtomicBool);
+        impl Clock for Interrupting<'_> {
+            fn sleep(&mut self, _: Duration) {
+                self.0.store(true, std::sync::atomic::Ordering::SeqCst);
+            }
+        }
+        let mut built = vec![];
+        let results = run_batch(
+            &ncodes,
+            Duration::from_secs(1),
+            &mut Interrupting(&interrupted),
+            &interrupted,
+            |ncode| {
+                built.push(ncode.to_string());
+                Ok(())
+            },
+        );
+        assert!(matches!(results, Err(narou::Error::Interrupted)));
+        assert_eq!(built, ["n0001a"]);
     }
 }
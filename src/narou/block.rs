@@ -0,0 +1,317 @@
+use super::unescape::Unescape;
+use crate::epub::Escape;
+use std::vec::IntoIter;
+
+/// 段落の中に並ぶ要素
+#[derive(Debug, PartialEq, Clone)]
+pub enum Inline {
+    /// 実体参照を戻した文字
+    Text(String),
+    Ruby {
+        base: String,
+        reading: String,
+    },
+    Image(String),
+    LineBreak,
+    /// 解釈しないタグで、XHTML にするときはそのまま戻す
+    Markup(String),
+}
+
+/// 本文を組み立てる段落などの区画
+#[derive(Debug, PartialEq, Clone)]
+pub enum Block {
+    Paragraph(Vec<Inline>),
+    /// 作者が間を取るために入れた空行
+    Blank,
+    /// 挿絵だけの段落
+    Image(String),
+    /// 場面転換の区切り線
+    Break,
+    Preface(Vec<Block>),
+    Afterword(Vec<Block>),
+    /// 段落に入っていない文字やタグ
+    Loose(Vec<Inline>),
+}
+
+const BLANK: &str = r#"<p class="blank"><br/></p>"#;
+
+enum Token<'a> {
+    Tag(&'a str),
+    Text(&'a str),
+}
+
+type Tokens<'a> = IntoIter<Token<'a>>;
+
+// 閉じていない < は文字として扱う
+fn tokenize(s: &str) -> Tokens<'_> {
+    let mut tokens = vec![];
+    let mut rest = s;
+    while !rest.is_empty() {
+        let (token, r) = match (rest.find('<'), rest.find('>')) {
+            (Some(0), Some(end)) => (Token::Tag(&rest[..=end]), &rest[end + 1..]),
+            (Some(start), Some(_)) if start > 0 => (Token::Text(&rest[..start]), &rest[start..]),
+            _ => (Token::Text(rest), ""),
+        };
+        tokens.push(token);
+        rest = r;
+    }
+    tokens.into_iter()
+}
+
+// ルビは rp の中の括弧を捨てて親文字と読みだけを取る
+fn ruby(tokens: &mut Tokens) -> Inline {
+    let mut base = String::new();
+    let mut reading = String::new();
+    let mut inside = "";
+    for token in tokens.by_ref() {
+        match token {
+            Token::Tag("</ruby>") => break,
+            Token::Tag(tag @ ("<rp>" | "<rt>")) => inside = tag,
+            Token::Tag("</rp>" | "</rt>") => inside = "",
+            Token::Tag(_) => {}
+            Token::Text(text) => match inside {
+                "<rt>" => reading.push_str(&text.unescape()),
+                "<rp>" => {}
+                _ => base.push_str(&text.unescape()),
+            },
+        }
+    }
+    Inline::Ruby { base, reading }
+}
+
+fn inline(token: Token, tokens: &mut Tokens) -> Inline {
+    match token {
+        Token::Tag("<br/>") => Inline::LineBreak,
+        Token::Tag("<ruby>") => ruby(tokens),
+        Token::Tag(tag) => match tag
+            .strip_prefix(r#"<img src=""#)
+            .and_then(|x| x.strip_suffix(r#""/>"#))
+        {
+            Some(src) => Inline::Image(src.unescape()),
+            None => Inline::Markup(tag.to_string()),
+        },
+        Token::Text(text) => Inline::Text(text.unescape()),
+    }
+}
+
+fn inlines(tokens: &mut Tokens) -> Vec<Inline> {
+    let mut inlines = vec![];
+    while let Some(token) = tokens.next() {
+        if matches!(token, Token::Tag("</p>")) {
+            break;
+        }
+        inlines.push(inline(token, tokens));
+    }
+    inlines
+}
+
+// 前書きと後書きの中では対応する </div> で終わる
+fn blocks(tokens: &mut Tokens, nested: bool) -> Vec<Block> {
+    let mut blocks = vec![];
+    let mut loose = vec![];
+    while let Some(token) = tokens.next() {
+        let block = match token {
+            Token::Tag("<p>") => match inlines(tokens).as_slice() {
+                [Inline::Image(name)] => Block::Image(name.clone()),
+                paragraph => Block::Paragraph(paragraph.to_vec()),
+            },
+            Token::Tag(r#"<p class="blank">"#) => {
+                inlines(tokens);
+                Block::Blank
+            }
+            Token::Tag("<hr/>") => Block::Break,
+            Token::Tag(r#"<div class="preface">"#) => Block::Preface(self::blocks(tokens, true)),
+            Token::Tag(r#"<div class="afterword">"#) => {
+                Block::Afterword(self::blocks(tokens, true))
+            }
+            Token::Tag("</div>") if nested => break,
+            token => {
+                loose.push(inline(token, tokens));
+                continue;
+            }
+        };
+        if !loose.is_empty() {
+            blocks.push(Block::Loose(std::mem::take(&mut loose)));
+        }
+        blocks.push(block);
+    }
+    if !loose.is_empty() {
+        blocks.push(Block::Loose(loose));
+    }
+    blocks
+}
+
+/// 整形済みの本文の XHTML を区画の並びにする
+pub fn parse(body: &str) -> Vec<Block> {
+    blocks(&mut tokenize(body), false)
+}
+
+fn render_inlines(out: &mut String, inlines: &[Inline]) {
+    for inline in inlines {
+        match inline {
+            Inline::Text(text) => out.push_str(&text.escape()),
+            Inline::Ruby { base, reading } => {
+                out.push_str("<ruby>");
+                out.push_str(&base.escape());
+                out.push_str("<rp>(</rp><rt>");
+                out.push_str(&reading.escape());
+                out.push_str("</rt><rp>)</rp></ruby>");
+            }
+            Inline::Image(name) => {
+                out.push_str(&[r#"<img src=""#, &name.escape(), r#""/>"#].concat())
+            }
+            Inline::LineBreak => out.push_str("<br/>"),
+            Inline::Markup(tag) => out.push_str(tag),
+        }
+    }
+}
+
+fn render_blocks(out: &mut String, blocks: &[Block]) {
+    for block in blocks {
+        match block {
+            Block::Paragraph(inlines) => {
+                out.push_str("<p>");
+                render_inlines(out, inlines);
+                out.push_str("</p>");
+            }
+            Block::Blank => out.push_str(BLANK),
+            Block::Image(name) => {
+                out.push_str(&[r#"<p><img src=""#, &name.escape(), r#""/></p>"#].concat())
+            }
+            Block::Break => out.push_str("<hr/>"),
+            Block::Preface(blocks) => {
+                out.push_str(r#"<div class="preface">"#);
+                render_blocks(out, blocks);
+                out.push_str("</div>");
+            }
+            Block::Afterword(blocks) => {
+                out.push_str(r#"<div class="afterword">"#);
+                render_blocks(out, blocks);
+                out.push_str("</div>");
+            }
+            Block::Loose(inlines) => render_inlines(out, inlines),
+        }
+    }
+}
+
+/// 区画の並びを本文の XHTML にする
+pub fn render(blocks: &[Block]) -> String {
+    let mut out = String::new();
+    render_blocks(&mut out, blocks);
+    out
+}
+
+// 段落の終わりの改行は段落の区切りと重なるので数えない
+fn plain_inlines(out: &mut String, inlines: &[Inline], paragraph: bool) {
+    for (i, inline) in inlines.iter().enumerate() {
+        match inline {
+            Inline::Text(text) => out.push_str(text),
+            Inline::Ruby { base, reading } => out.push_str(&[base, "(", reading, ")"].concat()),
+            Inline::Image(name) => out.push_str(&["[image: ", name, "]"].concat()),
+            Inline::LineBreak if !paragraph || i + 1 < inlines.len() => out.push('\n'),
+            Inline::LineBreak | Inline::Markup(_) => {}
+        }
+    }
+}
+
+/// タグを除いたテキストで、画像は `[image: 名前]` に置き換える
+pub fn plain_text(blocks: &[Block]) -> String {
+    let mut text = String::new();
+    for block in blocks {
+        match block {
+            Block::Paragraph(inlines) => {
+                plain_inlines(&mut text, inlines, true);
+                text.push('\n');
+            }
+            Block::Blank => text.push('\n'),
+            Block::Image(name) => text.push_str(&["[image: ", name, "]\n"].concat()),
+            Block::Break => {}
+            Block::Preface(blocks) | Block::Afterword(blocks) => text.push_str(&plain_text(blocks)),
+            Block::Loose(inlines) => plain_inlines(&mut text, inlines, false),
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Block, Inline, parse, plain_text, render};
+
+    fn text(s: &str) -> Inline {
+        Inline::Text(s.to_string())
+    }
+
+    #[test]
+    fn parse_body() {
+        const BODY: &str = concat!(
+            r#"<div class="preface"><p>前書き</p></div>"#,
+            "<p>　吾輩は<ruby>猫<rp>(</rp><rt>ねこ</rt><rp>)</rp></ruby>である。<br/>名前は&quot;まだ&quot;無い。</p>",
+            r#"<p class="blank"><br/></p>"#,
+            r#"<p><img src="0.png"/></p>"#,
+            "<hr/>",
+            r#"<p>挿絵<img src="1.jpg"/>と<em>強調</em></p>"#,
+            r#"<div class="afterword"><p>後書き</p></div>"#,
+        );
+        let blocks = parse(BODY);
+        assert_eq!(
+            blocks,
+            [
+                Block::Preface(vec![Block::Paragraph(vec![text("前書き")])]),
+                Block::Paragraph(vec![
+                    text("　吾輩は"),
+                    Inline::Ruby {
+                        base: "猫".to_string(),
+                        reading: "ねこ".to_string()
+                    },
+                    text("である。"),
+                    Inline::LineBreak,
+                    text("名前は\"まだ\"無い。"),
+                ]),
+                Block::Blank,
+                Block::Image("0.png".to_string()),
+                Block::Break,
+                Block::Paragraph(vec![
+                    text("挿絵"),
+                    Inline::Image("1.jpg".to_string()),
+                    text("と"),
+                    Inline::Markup("<em>".to_string()),
+                    text("強調"),
+                    Inline::Markup("</em>".to_string()),
+                ]),
+                Block::Afterword(vec![Block::Paragraph(vec![text("後書き")])]),
+            ]
+        );
+        assert_eq!(render(&blocks), BODY);
+        assert_eq!(
+            plain_text(&blocks),
+            "前書き\n　吾輩は猫(ねこ)である。\n名前は\"まだ\"無い。\n\n[image: 0.png]\n挿絵[image: 1.jpg]と強調\n後書き\n"
+        );
+    }
+
+    #[test]
+    fn ruby_with_rb() {
+        assert_eq!(
+            parse("<ruby><rb>漢字</rb><rp>（</rp><rt>かんじ</rt><rp>）</rp></ruby>"),
+            [Block::Loose(vec![Inline::Ruby {
+                base: "漢字".to_string(),
+                reading: "かんじ".to_string()
+            }])]
+        );
+    }
+
+    // 段落の外の文字や閉じていないタグも落とさない
+    #[test]
+    fn loose() {
+        let blocks = parse("本文<br/>続き<p>段落</p>後<");
+        assert_eq!(
+            blocks,
+            [
+                Block::Loose(vec![text("本文"), Inline::LineBreak, text("続き")]),
+                Block::Paragraph(vec![text("段落")]),
+                Block::Loose(vec![text("後<")]),
+            ]
+        );
+        assert_eq!(render(&blocks), "本文<br/>続き<p>段落</p>後&lt;");
+        assert_eq!(plain_text(&blocks), "本文\n続き段落\n後<");
+    }
+}
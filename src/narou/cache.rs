@@ -0,0 +1,139 @@
+use super::error::{Error, Result};
+use super::internet::{Internet, Query};
+use crate::uuid::UUIDv5;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// URL ごとに本文と検証用のヘッダを保存するディスクキャッシュ
+///
+/// ファイル名は URL から生成した UUIDv5 で、本文を `.html` に、
+/// ETag と Last-Modified を `.meta` に保存する。
+/// 古いエントリの削除は行わない。
+pub struct Cache {
+    dir: PathBuf,
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct Validators {
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+impl Validators {
+    fn parse(s: &str) -> Self {
+        let mut validators = Self::default();
+        for line in s.lines() {
+            match line.split_once(": ") {
+                Some(("ETag", v)) => validators.etag = Some(v.to_string()),
+                Some(("Last-Modified", v)) => validators.last_modified = Some(v.to_string()),
+                _ => {}
+            }
+        }
+        validators
+    }
+
+    // 条件付きリクエストのためのヘッダ
+    fn request_headers(&self) -> String {
+        let mut headers = String::new();
+        if let Some(etag) = &self.etag {
+            headers.push_str(&["If-None-Match: ", etag, "\r\n"].concat());
+        }
+        if let Some(last_modified) = &self.last_modified {
+            headers.push_str(&["If-Modified-Since: ", last_modified, "\r\n"].concat());
+        }
+        headers
+    }
+}
+
+impl std::fmt::Display for Validators {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(etag) = &self.etag {
+            writeln!(f, "ETag: {}", etag)?;
+        }
+        if let Some(last_modified) = &self.last_modified {
+            writeln!(f, "Last-Modified: {}", last_modified)?;
+        }
+        Ok(())
+    }
+}
+
+impl Cache {
+    pub fn new(dir: &Path) -> Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    fn paths(&self, url: &str) -> Option<(PathBuf, PathBuf)> {
+        let name = UUIDv5::new(url.as_bytes())?.to_string();
+        Some((
+            self.dir.join([&name, ".html"].concat()),
+            self.dir.join([&name, ".meta"].concat()),
+        ))
+    }
+
+    fn load(&self, url: &str) -> Option<(Validators, String)> {
+        let (body_path, meta_path) = self.paths(url)?;
+        let validators = Validators::parse(&std::fs::read_to_string(meta_path).ok()?);
+        let body = std::fs::read_to_string(body_path).ok()?;
+        Some((validators, body))
+    }
+
+    fn store(&self, url: &str, validators: &Validators, body: &str) -> Result<()> {
+        if let Some((body_path, meta_path)) = self.paths(url) {
+            std::fs::write(body_path, body)?;
+            std::fs::write(meta_path, validators.to_string())?;
+        }
+        Ok(())
+    }
+
+    /// キャッシュが残っていれば条件付きで取得し、 304 ならキャッシュの内容を返す
+    ///
+    /// 返り値はステータスコードと本文。トップページへの転送は作品がないものとしてエラーにする
+    pub fn fetch(&self, internet: &Internet, url: &str) -> Result<(u32, String)> {
+        let cached = self.load(url);
+        let headers = cached
+            .as_ref()
+            .map(|(validators, _)| validators.request_headers())
+            .unwrap_or_default();
+        let mut response = internet.open_with_headers(url, &headers)?;
+        let status = response.status_code()?;
+        super::episode::check_redirect(&response, status)?;
+        match (status, cached) {
+            (304, Some((_, body))) => Ok((304, body)),
+            (200, _) => {
+                let mut body = String::new();
+                response.read_to_string(&mut body)?;
+                let validators = Validators {
+                    etag: response.header(Query::ETag).ok(),
+                    last_modified: response.header(Query::LastModified).ok(),
+                };
+                if validators != Validators::default() {
+                    self.store(url, &validators, &body)?;
+                }
+                Ok((200, body))
+            }
+            (code, _) => Err(Error::BadStatus(code)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Validators;
+
+    #[test]
+    fn validators() {
+        let validators = Validators {
+            etag: Some("\"abc\"".to_string()),
+            last_modified: Some("Wed, 21 Oct 2015 07:28:00 GMT".to_string()),
+        };
+        assert_eq!(Validators::parse(&validators.to_string()), validators);
+        assert_eq!(
+            validators.request_headers(),
+            "If-None-Match: \"abc\"\r\nIf-Modified-Since: Wed, 21 Oct 2015 07:28:00 GMT\r\n"
+        );
+        assert_eq!(Validators::default().request_headers(), "");
+    }
+}
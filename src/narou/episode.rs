@@ -1,30 +1,50 @@
 use super::Internet;
+use super::Site;
+use super::block::{self, Block};
+use super::cache::Cache;
 pub use super::error::{Error, Result};
-use super::internet::Query;
+use super::fetcher::Fetcher;
+use super::internet::{Query, Response};
+use super::limiter::RateLimiter;
 use super::unescape::Unescape;
-use crate::epub::Escape;
+use crate::epub::time::{Chronological, FromJST};
+use crate::epub::{Escape, Id};
+use std::collections::{HashMap, VecDeque};
 use std::fmt::Display;
 use std::io::Read;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use utcdatetime::DateTime;
 
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum ImageType {
     Jpg,
     Png,
     Gif,
+    Webp,
 }
 
+// 取得に成功するまでは None のままで、失敗したら次に参照した話が取得し直す
+pub(super) type ImageSlot = Arc<Mutex<Option<ImageInfo>>>;
+
+// 同じ挿絵は全話で中身を共有する
+#[derive(Clone)]
 pub struct ImageInfo {
     pub name: String,
     pub image_type: ImageType,
-    pub body: Vec<u8>,
+    pub body: Arc<[u8]>,
 }
 
 pub struct Episode {
-    #[allow(dead_code)]
     pub number: u32,
     pub chapter: Option<String>,
     pub title: String,
-    pub body: String,
+    // 本文は段落などの区画に分けて持ち、XHTML はそこから作る
+    pub blocks: Vec<Block>,
     pub series: bool,
+    // 話ごとの更新日時で、ページに見当たらなければ None
+    pub updated: Option<DateTime>,
     pub images: Vec<ImageInfo>,
 }
 
@@ -34,6 +54,7 @@ impl Display for ImageType {
             ImageType::Jpg => write!(f, "jpg"),
             ImageType::Png => write!(f, "png"),
             ImageType::Gif => write!(f, "gif"),
+            ImageType::Webp => write!(f, "webp"),
         }
     }
 }
@@ -45,35 +66,125 @@ impl std::str::FromStr for ImageType {
             "jpg" => Ok(ImageType::Jpg),
             "png" => Ok(ImageType::Png),
             "gif" => Ok(ImageType::Gif),
+            "webp" => Ok(ImageType::Webp),
             _ => Err(Error::UnknownImageType),
         }
     }
 }
 
 impl ImageType {
+    // URL のクエリとフラグメントは拡張子の判別の前に取り除く
     fn from_extension(s: &str) -> Result<Self> {
+        let s = s.split(['?', '#']).next().unwrap_or(s);
         if s.ends_with(".jpg") {
             Ok(ImageType::Jpg)
         } else if s.ends_with(".png") {
             Ok(ImageType::Png)
         } else if s.ends_with(".gif") {
             Ok(ImageType::Gif)
+        } else if s.ends_with(".webp") {
+            Ok(ImageType::Webp)
+        } else {
+            Err(Error::UnknownImageType)
+        }
+    }
+
+    // 拡張子から判別できない場合のためにファイルの先頭のマジックナンバーで判別する
+    fn from_bytes(body: &[u8]) -> Result<Self> {
+        if body.starts_with(b"\xff\xd8\xff") {
+            Ok(ImageType::Jpg)
+        } else if body.starts_with(b"\x89PNG\r\n\x1a\n") {
+            Ok(ImageType::Png)
+        } else if body.starts_with(b"GIF87a") || body.starts_with(b"GIF89a") {
+            Ok(ImageType::Gif)
+        } else if body.starts_with(b"RIFF") && body.get(8..12) == Some(b"WEBP") {
+            Ok(ImageType::Webp)
         } else {
             Err(Error::UnknownImageType)
         }
     }
 }
 
+// 各話の取得に必要な情報で、ワーカースレッドごとに複製して使う
+#[derive(Clone)]
+pub(super) struct EpisodeSource {
+    pub(super) series: bool,
+    pub(super) ncode: String,
+    pub(super) site: Site,
+    pub(super) id: Arc<Mutex<Id>>,
+    // 画像の URL ごとの取得結果。取得中の話が枠の鎖を持つので、他の話は終わるのを待って結果を使う
+    pub(super) images: Arc<Mutex<HashMap<String, ImageSlot>>>,
+    pub(super) limiter: Arc<RateLimiter>,
+    pub(super) interrupted: &'static AtomicBool,
+    pub(super) cancel: Arc<AtomicBool>,
+    pub(super) cache: Option<Arc<Cache>>,
+    pub(super) fetcher: Option<Arc<dyn Fetcher>>,
+    pub(super) outline_only: bool,
+    // 更新日時がこれより前の話は画像を取得せずに飛ばす
+    pub(super) since: Option<DateTime>,
+    pub(super) verbose: bool,
+    pub(super) no_images: bool,
+    pub(super) strip_notes: bool,
+    pub(super) keep_links: bool,
+    pub(super) max_image_size: u64,
+    pub(super) remaining_size: Arc<AtomicU64>,
+}
+
+// 最大 jobs 話までを先行して並列に取得し、話の順に返すイテレータ
 pub struct EpisodeIter {
     pub(super) cur: u32,
     pub(super) max: u32,
-    pub(super) series: bool,
-    pub(super) ncode: String,
-    pub(super) id: crate::epub::Id,
+    pub(super) jobs: usize,
+    pub(super) source: EpisodeSource,
+    pub(super) skip_errors: bool,
+    pub(super) skipped: Vec<(u32, Error)>,
+    pub(super) pending: VecDeque<(u32, JoinHandle<Result<Episode>>)>,
 }
 
+// 話の題名と本文を囲む目印で、サイトの構成の変更に備えて新しいものから順に試す
+const TITLE_MARKERS: &[(&str, &str)] = &[
+    (
+        r#"<h1 class="p-novel__title p-novel__title--rensai">"#,
+        "</h1>",
+    ),
+    (r#"<p class="novel_subtitle">"#, "</p>"),
+];
+const SHORT_TITLE_MARKERS: &[(&str, &str)] = &[
+    (r#"<h1 class="p-novel__title">"#, "</h1>"),
+    (r#"<p class="novel_title">"#, "</p>"),
+];
+// 削除や公開停止された作品は、ステータスコード 200 のままエラーのページを返すことがある
+const UNAVAILABLE_MARKERS: &[&str] = &[
+    "この小説は作者によって削除されたか",
+    "運営により公開が停止されています",
+    "この小説は削除されました",
+];
+// 話ごとの更新日時を囲む目印で、中身は "2023/04/05 06:07" の形
+const UPDATE_MARKERS: &[(&str, &str)] = &[(r#"<div class="p-novel__date">"#, "</div>")];
+const BODY_MARKERS: &[&str] = &[
+    r#"<div class="js-novel-text p-novel__text">"#,
+    r#"<div id="novel_honbun" class="novel_view">"#,
+];
+// 作者の前書きは本文の前に、後書きは本文の直後に置かれる
+const PREFACE_MARKERS: &[(&str, &str)] = &[
+    (
+        r#"<div class="js-novel-text p-novel__text p-novel__text--preface">"#,
+        "</div>",
+    ),
+    (r#"<div id="novel_p" class="novel_view">"#, "</div>"),
+];
+const AFTERWORD_MARKERS: &[(&str, &str)] = &[
+    (
+        r#"<div class="js-novel-text p-novel__text p-novel__text--afterword">"#,
+        "</div>",
+    ),
+    (r#"<div id="novel_a" class="novel_view">"#, "</div>"),
+];
+
 trait TextUtil {
     fn head_and_next(&self) -> Option<(char, &str)>;
+    fn split_once_any(&self, patterns: &[&str]) -> Option<(&str, &str)>;
+    fn between_any(&self, markers: &[(&str, &str)]) -> Option<(&str, &str)>;
     fn between_and_next(&self, before: &str, after: &str) -> Option<(&str, &str)>;
     fn find_between_and_next(&self, before: &str, after: &str) -> Option<(&str, &str, &str)>;
 }
@@ -94,6 +205,19 @@ impl TextUtil for str {
         }
     }
 
+    // 最初に見つかった目印で分割する
+    fn split_once_any(&self, patterns: &[&str]) -> Option<(&str, &str)> {
+        patterns.iter().find_map(|pattern| self.split_once(pattern))
+    }
+
+    // 最初に見つかった目印の組に囲まれた部分と、その後ろを返す
+    fn between_any(&self, markers: &[(&str, &str)]) -> Option<(&str, &str)> {
+        markers.iter().find_map(|(before, after)| {
+            let (_, rest) = self.split_once(before)?;
+            rest.split_once(after)
+        })
+    }
+
     fn between_and_next(&self, before: &str, after: &str) -> Option<(&str, &str)> {
         let rest = self.strip_prefix(before)?;
         let (matched, rest) = rest.split_once(after)?;
@@ -108,8 +232,77 @@ impl TextUtil for str {
 }
 
 impl EpisodeIter {
+    pub fn set_cache(&mut self, cache: Cache) -> &mut Self {
+        self.source.cache = Some(Arc::new(cache));
+        self
+    }
+
+    /// 取得する URL とステータスコードを標準エラー出力に表示する
+    pub fn set_verbose(&mut self) -> &mut Self {
+        self.source.verbose = true;
+        self
+    }
+
+    /// 画像を取得せず、代わりに省略したことを示す文字列を置く
+    pub fn set_no_images(&mut self) -> &mut Self {
+        self.source.no_images = true;
+        self
+    }
+
+    /// 画像一枚あたりと本全体の画像の合計の上限のバイト数
+    pub fn set_size_limits(&mut self, image: u64, total: u64) -> &mut Self {
+        self.source.max_image_size = image;
+        self.source.remaining_size = Arc::new(AtomicU64::new(total));
+        self
+    }
+
+    /// 章と話の題名だけを取り出し、本文の整形や画像の取得をしない
+    pub fn set_outline_only(&mut self) -> &mut Self {
+        self.source.outline_only = true;
+        self
+    }
+
+    /// 前書きと後書きを本文に含めない
+    pub fn set_strip_notes(&mut self) -> &mut Self {
+        self.source.strip_notes = true;
+        self
+    }
+
+    /// 本文の外部サイトへのリンクを外さずに残す
+    pub fn set_keep_links(&mut self) -> &mut Self {
+        self.source.keep_links = true;
+        self
+    }
+
+    /// 取得や解釈に失敗した話で止めずに飛ばし、飛ばした話は skipped で分かるようにする
+    pub fn set_skip_errors(&mut self) -> &mut Self {
+        self.skip_errors = true;
+        self
+    }
+
+    /// 失敗して飛ばした話の番号とその理由
+    pub fn skipped(&self) -> &[(u32, Error)] {
+        &self.skipped
+    }
+
+    /// 更新日時が since より前の話を飛ばす。更新日時の分からない話は飛ばさない
+    pub fn set_since(&mut self, since: DateTime) -> &mut Self {
+        self.source.since = Some(since);
+        self
+    }
+
+    // リンクを外す既定の整形
+    #[cfg(test)]
     fn correct(s: &str) -> String {
-        let mut corrected = String::new();
+        EpisodeIter::correct_links(s, false)
+    }
+
+    // keep_links なら外部サイトへのリンクを属性を href だけにして残す
+    fn correct_links(s: &str, keep_links: bool) -> String {
+        // 改行や属性を落とすので元の長さを超えることはほとんどない
+        let mut corrected = String::with_capacity(s.len());
+        // 開いたリンクごとに、閉じタグも残すかどうか
+        let mut links = vec![];
         let mut rest = s;
         while !rest.is_empty() {
             let (ch, next) = rest.head_and_next().unwrap();
@@ -117,19 +310,46 @@ impl EpisodeIter {
                 rest = next;
             } else if ch == '<' {
                 if let Some((_, r)) = rest.between_and_next(r#"<p id="L"#, r#"">"#) {
-                    corrected.push_str("<p>");
-                    rest = r;
-                } else if let Some((_, r)) = rest.between_and_next(r#"<a "#, ">") {
+                    // 作者が間を取るために入れた空行は、読書端末で潰れないように印を付けて残す
+                    if let Some(r) = ["</p>", "<br></p>", "<br/></p>", "<br /></p>"]
+                        .iter()
+                        .find_map(|blank| r.strip_prefix(blank))
+                    {
+                        corrected.push_str(r#"<p class="blank"><br/></p>"#);
+                        rest = r;
+                    } else {
+                        corrected.push_str("<p>");
+                        rest = r;
+                    }
+                } else if let Some((attributes, r)) = rest.between_and_next(r#"<a "#, ">") {
+                    let href = external_href(attributes).filter(|_| keep_links);
+                    if let Some(href) = &href {
+                        corrected.push_str(r#"<a href=""#);
+                        corrected.push_str(&href.escape());
+                        corrected.push_str(r#"">"#);
+                    }
+                    links.push(href.is_some());
                     rest = r;
                 } else if let Some(r) = rest.strip_prefix("<br>") {
                     corrected.push_str("<br/>");
                     rest = r;
+                } else if let Some(r) = ["<hr>", "<hr/>", "<hr />"]
+                    .iter()
+                    .find_map(|hr| rest.strip_prefix(hr))
+                {
+                    // 場面転換の区切り線
+                    corrected.push_str("<hr/>");
+                    rest = r;
                 } else if let Some(r) = rest.strip_prefix("</a>") {
+                    if links.pop() == Some(true) {
+                        corrected.push_str("</a>");
+                    }
                     rest = r;
                 } else if let Some((src, r)) = rest.between_and_next(r#"<img src=""#, r#"" "#) {
                     if let Some((_, r)) = r.split_once("/>") {
+                        // HTML では属性値の & をそのまま書けるので XML として正しい形に直す
                         corrected.push_str(r#"<img src=""#);
-                        corrected.push_str(src);
+                        corrected.push_str(&src.unescape().escape());
                         corrected.push_str(r#""/>"#);
                         rest = r;
                     } else {
@@ -145,12 +365,299 @@ impl EpisodeIter {
                 rest = next;
             }
         }
+        // 閉じ忘れたリンクは末尾で閉じる
+        for _ in links.into_iter().filter(|x| *x) {
+            corrected.push_str("</a>");
+        }
 
         corrected
     }
 
-    fn image_url_replace(&mut self, html: &str) -> Result<(String, Vec<ImageInfo>)> {
+    fn extract(raw_html: &str) -> Option<(Option<&str>, &str, &str)> {
+        let (chapter_title, rest) = match raw_html.split_once("<br>\n<span>") {
+            Some((_, rest)) => rest.split_once("</span>").map(|x| (Some(x.0), x.1))?,
+            None => (None, raw_html),
+        };
+        let (episode_title, rest) = rest.between_any(TITLE_MARKERS)?;
+        let (_, rest) = rest.split_once_any(BODY_MARKERS)?;
+        let (body, _) = rest.split_once("</div>")?;
+
+        Some((chapter_title, episode_title, body))
+    }
+
+    // 本文の前の前書きと直後の後書き
+    fn extract_notes(raw_html: &str) -> (Option<&str>, Option<&str>) {
+        let Some((head, rest)) = raw_html.split_once_any(BODY_MARKERS) else {
+            return (None, None);
+        };
+        let preface = head.between_any(PREFACE_MARKERS).map(|x| x.0);
+        let afterword = rest
+            .split_once("</div>")
+            .and_then(|(_, rest)| rest.between_any(AFTERWORD_MARKERS))
+            .map(|x| x.0);
+        (preface, afterword)
+    }
+
+    // 前書きと後書きは本文と見分けられるように囲んで前後に付ける
+    fn correct_with_notes(
+        body: &str,
+        preface: Option<&str>,
+        afterword: Option<&str>,
+        keep_links: bool,
+    ) -> String {
+        let mut corrected = String::new();
+        if let Some(preface) = preface {
+            corrected.push_str(r#"<div class="preface">"#);
+            corrected.push_str(&EpisodeIter::correct_links(preface, keep_links));
+            corrected.push_str("</div>");
+        }
+        corrected.push_str(&EpisodeIter::correct_links(body, keep_links));
+        if let Some(afterword) = afterword {
+            corrected.push_str(r#"<div class="afterword">"#);
+            corrected.push_str(&EpisodeIter::correct_links(afterword, keep_links));
+            corrected.push_str("</div>");
+        }
+        corrected
+    }
+
+    // 本文より前にある話の更新日時を日本時間として読む
+    fn extract_updated(raw_html: &str) -> Option<DateTime> {
+        let (head, _) = raw_html.split_once_any(BODY_MARKERS)?;
+        let (updated, _) = head.between_any(UPDATE_MARKERS)?;
+        let updated = updated.trim().get(..16)?.replace('/', "-");
+        DateTime::from_jst_str(&[updated.as_str(), ":00"].concat()).ok()
+    }
+
+    // 短編の題名は本文の前の見出しか、なければ title 要素からサイト名を除いて取る
+    fn extract_short(raw_html: &str) -> Option<(Option<&str>, &str)> {
+        let (head, rest) = raw_html.split_once_any(BODY_MARKERS)?;
+        let (body, _) = rest.split_once("</div>")?;
+        let title = head
+            .between_any(SHORT_TITLE_MARKERS)
+            .or_else(|| head.between_any(&[("<title>", "</title>")]))
+            .map(|(title, _)| title.trim_end_matches(" - 小説家になろう").trim())
+            .filter(|title| !title.is_empty());
+
+        Some((title, body))
+    }
+}
+
+// 小説家になろうと挿絵のサイトへのリンクは本の中では辿れないので残さない
+const INTERNAL_HOSTS: &[&str] = &["syosetu.com", "mitemin.net"];
+
+// a タグの属性から外部サイトへのリンク先を取り出す
+fn external_href(attributes: &str) -> Option<String> {
+    let (_, rest) = attributes.split_once(r#"href=""#)?;
+    let (href, _) = rest.split_once('"')?;
+    let href = href.unescape();
+    let host = href
+        .strip_prefix("https://")
+        .or_else(|| href.strip_prefix("http://"))?
+        .split(['/', '?', '#'])
+        .next()?;
+    if host.is_empty()
+        || INTERNAL_HOSTS
+            .iter()
+            .any(|x| host == *x || host.ends_with(&[".", x].concat()))
+    {
+        None
+    } else {
+        Some(href.to_string())
+    }
+}
+
+/// 画像一枚あたりの上限の既定値
+pub const DEFAULT_MAX_IMAGE_SIZE: u64 = 20 * 1024 * 1024;
+
+/// 本全体の画像の合計の上限の既定値
+pub const DEFAULT_MAX_TOTAL_SIZE: u64 = 1024 * 1024 * 1024;
+
+// 上限を一バイトでも超えたところで読むのをやめる
+fn read_limited(reader: impl Read, limit: u64, url: &str) -> Result<Vec<u8>> {
+    let mut body = Vec::new();
+    reader
+        .take(limit.saturating_add(1))
+        .read_to_end(&mut body)?;
+    if body.len() as u64 > limit {
+        Err(Error::ImageTooLarge(url.to_string()))
+    } else {
+        Ok(body)
+    }
+}
+
+// 本文を取り出せなかったページが作品のないことを知らせるものか、構成が変わったものかを見分ける
+fn extract_failure(html: &str) -> Error {
+    if UNAVAILABLE_MARKERS
+        .iter()
+        .any(|marker| html.contains(marker))
+    {
+        Error::NovelUnavailable
+    } else {
+        Error::LayoutChanged
+    }
+}
+
+// 作品がなければトップページに転送されることもある
+fn is_redirect_to_top(location: &str) -> bool {
+    matches!(
+        location.trim().trim_end_matches('/'),
+        "" | "https://syosetu.com" | "https://ncode.syosetu.com" | "https://novel18.syosetu.com"
+    )
+}
+
+// トップページへの転送は作品がないものとして扱う。キャッシュを使うときも使わないときも同じ判定にする
+pub(super) fn check_redirect(response: &Response, status: u32) -> Result<()> {
+    if matches!(status, 301 | 302 | 303 | 307 | 308)
+        && response
+            .header(Query::Location)
+            .is_ok_and(|location| is_redirect_to_top(&location))
+    {
+        Err(Error::NovelUnavailable)
+    } else {
+        Ok(())
+    }
+}
+
+// 本文の閉じタグまで読んだところで打ち切り、それより後のフッタなどは読まない
+//
+// 本文の直後に後書きが続くときは後書きの閉じタグまで読む
+fn read_until_body_end(mut reader: impl Read) -> Result<String> {
+    const BODY_END: &str = "</div>";
+    let mut page = Vec::new();
+    let mut chunk = [0u8; 8192];
+    // 閉じタグを探し始める位置と、見つけた閉じタグの直後の位置
+    let mut body_start = None;
+    let mut body_end = None;
+    let mut afterword = false;
+    loop {
+        let n = reader.read(&mut chunk)?;
+        if n == 0 {
+            break;
+        }
+        let read = page.len();
+        page.extend_from_slice(&chunk[..n]);
+        // 前回までに読んだ分との境目をまたぐ目印も見つかるように少し戻って探す
+        if body_start.is_none() {
+            body_start = BODY_MARKERS.iter().find_map(|marker| {
+                let from = read.saturating_sub(marker.len() - 1);
+                find_bytes(&page[from..], marker.as_bytes()).map(|i| from + i + marker.len())
+            });
+        }
+        loop {
+            let end = match (body_start, body_end) {
+                (_, Some(end)) => end,
+                (Some(start), None) => {
+                    let from = start.max(read.saturating_sub(BODY_END.len() - 1));
+                    match find_bytes(&page[from..], BODY_END.as_bytes()) {
+                        Some(i) => *body_end.insert(from + i + BODY_END.len()),
+                        None => break,
+                    }
+                }
+                (None, None) => break,
+            };
+            if afterword {
+                page.truncate(end);
+                return String::from_utf8(page).or(Err(Error::InvalidCharCode));
+            }
+            // 次の要素が後書きかどうか分かるまで読み進める
+            let next = page[end..].trim_ascii_start();
+            if let Some((marker, _)) = AFTERWORD_MARKERS
+                .iter()
+                .find(|(marker, _)| next.starts_with(marker.as_bytes()))
+            {
+                afterword = true;
+                body_start = Some(page.len() - next.len() + marker.len());
+                body_end = None;
+            } else if AFTERWORD_MARKERS
+                .iter()
+                .any(|(marker, _)| marker.as_bytes().starts_with(next))
+            {
+                break;
+            } else {
+                page.truncate(end);
+                return String::from_utf8(page).or(Err(Error::InvalidCharCode));
+            }
+        }
+    }
+    String::from_utf8(page).or(Err(Error::InvalidCharCode))
+}
+
+fn find_bytes(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+impl EpisodeSource {
+    // 指定がなければ前書きと後書きも本文に含める
+    fn correct_body(&self, text: &str, body: &str) -> String {
+        let (preface, afterword) = if self.strip_notes {
+            (None, None)
+        } else {
+            EpisodeIter::extract_notes(text)
+        };
+        EpisodeIter::correct_with_notes(body, preface, afterword, self.keep_links)
+    }
+
+    // 一枚の上限と本全体の残りの小さい方
+    fn image_limit(&self) -> u64 {
+        self.max_image_size
+            .min(self.remaining_size.load(Ordering::SeqCst))
+    }
+
+    // 取得した画像の分を本全体の残りから差し引く
+    fn consume(&self, size: usize, url: &str) -> Result<()> {
+        self.remaining_size
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |remaining| {
+                remaining.checked_sub(size as u64)
+            })
+            .map(|_| ())
+            .or(Err(Error::ImageTooLarge(url.to_string())))
+    }
+
+    fn log(&self, status: u32, url: &str) {
+        if self.verbose {
+            eprintln!("{} {}", status, url);
+        }
+    }
+
+    fn image_url_replace(&self, html: &str) -> Result<(String, Vec<ImageInfo>)> {
+        if let Some(fetcher) = &self.fetcher {
+            return self.replace_images(html, |image_url| {
+                let (rel_image_url, image_body) = fetcher.image(image_url)?;
+                if image_body.len() as u64 > self.image_limit() {
+                    return Err(Error::ImageTooLarge(image_url.to_string()));
+                }
+                self.consume(image_body.len(), image_url)?;
+                let image_type = ImageType::from_extension(&rel_image_url)
+                    .or_else(|_| ImageType::from_bytes(&image_body))?;
+                Ok((image_type, image_body))
+            });
+        }
         let internet = Internet::new()?;
+        self.replace_images(html, |image_url| {
+            let response = internet.open(image_url)?;
+            self.log(response.status_code()?, image_url);
+            let rel_image_url = response.header(Query::Location)?;
+            let response = internet.open(&rel_image_url)?;
+            self.log(response.status_code()?, &rel_image_url);
+            let response = response.error_for_status()?;
+            let image_body = read_limited(response, self.image_limit(), image_url)?;
+            self.consume(image_body.len(), image_url)?;
+            let image_type = ImageType::from_extension(&rel_image_url)
+                .or_else(|_| ImageType::from_bytes(&image_body))?;
+            Ok((image_type, image_body))
+        })
+    }
+
+    // 画像を取得して名前を割り当てる。
+    // 同じ URL の画像は全話を通して一度だけ取得するが、分冊しても各巻に収録できるように
+    // 参照する話はどれも画像を持ち、本に収録済みかどうかは書き出す側で判断する
+    fn replace_images(
+        &self,
+        html: &str,
+        fetch: impl Fn(&str) -> Result<(ImageType, Vec<u8>)>,
+    ) -> Result<(String, Vec<ImageInfo>)> {
         let mut out = String::new();
         let mut image_urls = Vec::new();
         let mut rest = html;
@@ -158,21 +665,45 @@ impl EpisodeIter {
             if let Some((processed, image_url, r)) =
                 rest.find_between_and_next("<img src=\"", "\"/>")
             {
-                let image_url = ["https:", image_url].concat();
-                let rel_image_url = internet.open(image_url.as_str())?.header(Query::Location)?;
-                let image_type = ImageType::from_extension(&rel_image_url)?;
-                let mut response = internet.open(&rel_image_url)?.error_for_status()?;
-                let mut image_body = Vec::<u8>::new();
-                response.read_to_end(&mut image_body)?;
-                let image_name = format!("{}.{}", self.id.next().unwrap(), image_type);
-                let image_tag = ["<img src=\"", &image_name, "\"/>"].concat();
-                image_urls.push(ImageInfo {
-                    name: image_name,
-                    image_type,
-                    body: image_body,
-                });
+                if self.no_images {
+                    out.push_str(processed);
+                    out.push_str("［画像省略］");
+                    rest = r;
+                    continue;
+                }
+                let image_url = ["https:", &image_url.unescape()].concat();
+                let slot = self
+                    .images
+                    .lock()
+                    .unwrap()
+                    .entry(image_url.clone())
+                    .or_default()
+                    .clone();
+                let mut slot = slot.lock().unwrap();
+                let image = match &*slot {
+                    Some(image) => image.clone(),
+                    None => {
+                        // 名前は取得に成功してから割り当てる
+                        let (image_type, image_body) = fetch(&image_url)?;
+                        let image = ImageInfo {
+                            name: format!(
+                                "{}.{}",
+                                self.id.lock().unwrap().next().unwrap(),
+                                image_type
+                            ),
+                            image_type,
+                            body: image_body.into(),
+                        };
+                        *slot = Some(image.clone());
+                        image
+                    }
+                };
+                drop(slot);
                 out.push_str(processed);
-                out.push_str(&image_tag);
+                out.push_str(&["<img src=\"", &image.name.escape(), "\"/>"].concat());
+                if !image_urls.iter().any(|x: &ImageInfo| x.name == image.name) {
+                    image_urls.push(image);
+                }
                 rest = r;
             } else {
                 out.push_str(rest);
@@ -182,60 +713,95 @@ impl EpisodeIter {
         Ok((out, image_urls))
     }
 
-    fn extract(raw_html: &str) -> Option<(Option<&str>, &str, &str)> {
-        let (chapter_title, rest) = match raw_html.split_once("<br>\n<span>") {
-            Some((_, rest)) => rest.split_once("</span>").map(|x| (Some(x.0), x.1))?,
-            None => (None, raw_html),
-        };
-        let (_, rest) = rest.split_once(r#"<h1 class="p-novel__title p-novel__title--rensai">"#)?;
-        let (episode_title, rest) = rest.split_once("</h1>")?;
-        let (_, rest) = rest.split_once(r#"<div class="js-novel-text p-novel__text">"#)?;
-        let (body, _) = rest.split_once("</div>")?;
-
-        Some((chapter_title, episode_title, body))
-    }
-
-    fn extract_short(raw_html: &str) -> Option<&str> {
-        let (_, rest) = raw_html.split_once(r#"<div class="js-novel-text p-novel__text">"#)?;
-        let (body, _) = rest.split_once("</div>")?;
-
-        Some(body)
+    // 話の更新日時が since より前か
+    fn is_older(&self, updated: Option<&DateTime>) -> bool {
+        match (&self.since, updated) {
+            (Some(since), Some(updated)) => updated.chronological_cmp(since).is_lt(),
+            _ => false,
+        }
     }
 
-    fn try_next(&mut self) -> Result<Episode> {
+    fn fetch(&self, number: u32) -> Result<Episode> {
+        if self.interrupted.load(Ordering::SeqCst) || self.cancel.load(Ordering::SeqCst) {
+            return Err(Error::Interrupted);
+        }
+        self.limiter.wait();
         let uri = if self.series {
-            format!("https://ncode.syosetu.com/{}/{}", self.ncode, self.cur)
+            format!("{}{}/{}", self.site.base(), self.ncode, number)
         } else {
-            ["https://ncode.syosetu.com/", &self.ncode].concat()
+            [self.site.base(), &self.ncode].concat()
+        };
+        let text = if let Some(fetcher) = &self.fetcher {
+            fetcher.text(&uri)?
+        } else if let Some(cache) = &self.cache {
+            let internet = Internet::new()?;
+            let (status, text) = cache.fetch(&internet, &uri)?;
+            self.log(status, &uri);
+            text
+        } else {
+            let internet = Internet::new()?;
+            let response = internet.open(&uri)?;
+            let status = response.status_code()?;
+            self.log(status, &uri);
+            check_redirect(&response, status)?;
+            read_until_body_end(response.error_for_status()?)?
         };
-        let internet = Internet::new()?;
-        let mut text = String::new();
-        internet
-            .open(&uri)?
-            .error_for_status()?
-            .read_to_string(&mut text)?;
         Ok(if self.series {
-            let (chapter, title, body) = Self::extract(&text).ok_or(Error::InvalidData)?;
-            let body = Self::correct(body);
+            let (chapter, title, body) =
+                EpisodeIter::extract(&text).ok_or_else(|| extract_failure(&text))?;
+            let chapter = chapter.map(|x| x.unescape());
+            let title = title.unescape();
+            let updated = EpisodeIter::extract_updated(&text);
+            // since より前の話は next で捨てるので、画像の取得や容量の消費をする前に中身なしで返す
+            if self.outline_only || self.is_older(updated.as_ref()) {
+                return Ok(Episode {
+                    number,
+                    chapter,
+                    title,
+                    blocks: vec![],
+                    series: self.series,
+                    updated,
+                    images: vec![],
+                });
+            }
+            let body = self.correct_body(&text, body);
+            // 画像を取得している間までページ全体を抱えておかない
+            drop(text);
             let (body, images) = self.image_url_replace(&body)?;
             Episode {
-                number: self.cur,
-                chapter: chapter.map(|x| x.unescape()),
-                title: title.unescape(),
-                body,
+                number,
+                chapter,
+                title,
+                blocks: block::parse(&body),
                 series: self.series,
+                updated,
                 images,
             }
         } else {
-            let body = Self::extract_short(&text).ok_or(Error::InvalidData)?;
-            let body = Self::correct(body);
+            let (title, body) =
+                EpisodeIter::extract_short(&text).ok_or_else(|| extract_failure(&text))?;
+            let title = title.map_or("本文".to_string(), |x| x.unescape());
+            if self.outline_only {
+                return Ok(Episode {
+                    number,
+                    chapter: None,
+                    title,
+                    blocks: vec![],
+                    series: self.series,
+                    updated: None,
+                    images: vec![],
+                });
+            }
+            let body = self.correct_body(&text, body);
+            drop(text);
             let (body, images) = self.image_url_replace(&body)?;
             Episode {
-                number: self.cur,
+                number,
                 chapter: None,
-                title: "本文".to_string(),
-                body,
+                title,
+                blocks: block::parse(&body),
                 series: self.series,
+                updated: None,
                 images,
             }
         })
@@ -245,16 +811,49 @@ impl EpisodeIter {
 impl Iterator for EpisodeIter {
     type Item = Result<Episode>;
     fn next(&mut self) -> Option<Self::Item> {
-        if self.cur <= self.max {
-            let episode = self.try_next();
-            self.cur += 1;
-            Some(episode)
-        } else {
-            None
+        loop {
+            while self.pending.len() < self.jobs && self.cur <= self.max {
+                let source = self.source.clone();
+                let number = self.cur;
+                self.pending
+                    .push_back((number, thread::spawn(move || source.fetch(number))));
+                self.cur += 1;
+            }
+            let (number, handle) = self.pending.pop_front()?;
+            match handle.join().unwrap() {
+                // 中断は失敗ではないので飛ばさない
+                Err(error) if self.skip_errors && !matches!(error, Error::Interrupted) => {
+                    self.skipped.push((number, error));
+                }
+                Ok(episode) if self.source.is_older(episode.updated.as_ref()) => {}
+                episode => return Some(episode),
+            }
         }
     }
 }
 
+impl Drop for EpisodeIter {
+    // 取得途中のワーカーを止めてから終了を待つ
+    fn drop(&mut self) {
+        self.source.cancel.store(true, Ordering::SeqCst);
+        for (_, handle) in self.pending.drain(..) {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Episode {
+    /// 本文の XHTML
+    pub fn body(&self) -> String {
+        block::render(&self.blocks)
+    }
+
+    /// 本文からタグを取り除いたテキストで、画像は `[image: 名前]` に置き換える
+    pub fn plain_body(&self) -> String {
+        block::plain_text(&self.blocks)
+    }
+}
+
 impl Display for Episode {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         if self.series {
@@ -262,10 +861,742 @@ impl Display for Episode {
                 f,
                 include_str!("episode.txt"),
                 self.title.escape(),
-                &self.body
+                self.body()
             )
         } else {
-            write!(f, include_str!("short_episode.txt"), &self.body)
+            write!(
+                f,
+                include_str!("short_episode.txt"),
+                self.title.escape(),
+                self.body()
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        DEFAULT_MAX_IMAGE_SIZE, DEFAULT_MAX_TOTAL_SIZE, Episode, EpisodeIter, EpisodeSource,
+        Fetcher, ImageType, Site, TextUtil, read_limited,
+    };
+    use crate::epub::Id;
+    use crate::epub::time::FromJST;
+    use crate::narou::Error;
+    use crate::narou::block;
+    use crate::narou::limiter::RateLimiter;
+    use std::cell::{Cell, RefCell};
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use utcdatetime::DateTime;
+
+    #[test]
+    fn image_type() {
+        assert_eq!(ImageType::Webp.to_string(), "webp");
+        assert_eq!("webp".parse::<ImageType>().unwrap(), ImageType::Webp);
+        assert_eq!(
+            ImageType::from_extension("https://example.com/a.webp").unwrap(),
+            ImageType::Webp
+        );
+        assert_eq!(
+            ImageType::from_bytes(b"RIFF\x24\x00\x00\x00WEBPVP8 ").unwrap(),
+            ImageType::Webp
+        );
+        assert_eq!(
+            ImageType::from_bytes(b"\xff\xd8\xff\xe0").unwrap(),
+            ImageType::Jpg
+        );
+        assert_eq!(
+            ImageType::from_bytes(b"\x89PNG\r\n\x1a\n").unwrap(),
+            ImageType::Png
+        );
+        assert_eq!(ImageType::from_bytes(b"GIF89a").unwrap(), ImageType::Gif);
+        assert_eq!(
+            ImageType::from_extension("https://example.com/pic.jpg?cache=1").unwrap(),
+            ImageType::Jpg
+        );
+        assert_eq!(
+            ImageType::from_extension("https://example.com/pic.png#top").unwrap(),
+            ImageType::Png
+        );
+        assert_eq!(
+            ImageType::from_extension("https://example.com/pic.gif?v=1#x").unwrap(),
+            ImageType::Gif
+        );
+        assert!(ImageType::from_extension("https://example.com/pic?name=a.jpg").is_err());
+        assert!(ImageType::from_bytes(b"RIFF\x24\x00\x00\x00WAVE").is_err());
+    }
+
+    #[test]
+    fn plain_body() {
+        let episode = Episode {
+            number: 1,
+            chapter: None,
+            title: "第一話".to_string(),
+            blocks: block::parse(
+                r#"<p>「はい」&amp;<br/>続き</p><p><br/></p><p><img src="0.jpg"/></p>"#,
+            ),
+            series: true,
+            updated: None,
+            images: vec![],
+        };
+        assert_eq!(episode.plain_body(), "「はい」&\n続き\n\n[image: 0.jpg]\n");
+    }
+
+    #[test]
+    fn horizontal_rule() {
+        assert_eq!(
+            EpisodeIter::correct("<p id=\"L1\">前の場面</p>\n<hr>\n<p id=\"L2\">次の場面</p>"),
+            "<p>前の場面</p><hr/><p>次の場面</p>"
+        );
+        assert_eq!(EpisodeIter::correct("<hr />"), "<hr/>");
+    }
+
+    // 再現できるように種を固定した xorshift で、タグの断片と多バイト文字を無作為に並べる
+    fn random_inputs(count: usize) -> Vec<String> {
+        const PIECES: &[&str] = &[
+            "<",
+            ">",
+            "/",
+            "\"",
+            " ",
+            "\n",
+            "<p id=\"L",
+            "\">",
+            "</p>",
+            "<a ",
+            "</a>",
+            "<br>",
+            "<br",
+            "<hr",
+            "<hr>",
+            "<hr />",
+            "<img src=\"",
+            "\" ",
+            "/>",
+            "&amp;",
+            "&#x",
+            "&#",
+            ";",
+            "&",
+            "L1",
+            "あ",
+            "漢",
+            "𠮷",
+            "é",
+            "\u{a0}",
+            "\u{ffff}",
+            "👨\u{200d}👩",
+        ];
+        let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        (0..count)
+            .map(|i| {
+                let length = (next() % 24) as usize;
+                if i % 4 == 0 {
+                    // 断片に揃わない任意のバイト列も、文字列になおして混ぜる
+                    let bytes: Vec<u8> = (0..length * 3).map(|_| next() as u8).collect();
+                    String::from_utf8_lossy(&bytes).into_owned()
+                } else {
+                    (0..length)
+                        .map(|_| PIECES[(next() % PIECES.len() as u64) as usize])
+                        .collect()
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn random_input() {
+        for input in random_inputs(20000) {
+            let corrected = EpisodeIter::correct(&input);
+            assert!(std::str::from_utf8(corrected.as_bytes()).is_ok());
+            let _ = EpisodeIter::correct_links(&input, true);
+            // タグでない部分の多バイト文字は落とさない
+            if !input.contains(['<', '&']) {
+                assert_eq!(corrected, input.replace('\n', ""));
+            }
+            let _ = EpisodeIter::extract(&input);
+            let _ = EpisodeIter::extract_short(&input);
+            let episode = Episode {
+                number: 1,
+                chapter: None,
+                title: String::new(),
+                blocks: block::parse(&corrected),
+                series: true,
+                updated: None,
+                images: vec![],
+            };
+            let _ = episode.plain_body();
+        }
+    }
+
+    #[test]
+    fn multibyte_around_tags() {
+        assert_eq!(EpisodeIter::correct("あ<い>う"), "あ<い>う");
+        assert_eq!(EpisodeIter::correct("<𠮷"), "<𠮷");
+        assert_eq!(EpisodeIter::correct("𠮷<br>𠮷"), "𠮷<br/>𠮷");
+        assert_eq!(EpisodeIter::correct("<p id=\"L漢"), "<p id=\"L漢");
+        assert_eq!(EpisodeIter::correct("<img src=\"あ\" "), "<img src=\"あ\" ");
+        assert_eq!(
+            EpisodeIter::correct("<img src=\"あ&amp;い\" alt=\"\" />後"),
+            "<img src=\"あ&amp;い\"/>後"
+        );
+        assert_eq!(EpisodeIter::correct("<a 漢"), "<a 漢");
+        assert_eq!(EpisodeIter::correct("<"), "<");
+    }
+
+    #[test]
+    fn keep_links() {
+        const BODY: &str = concat!(
+            r#"<p id="L1"><a href="https://example.com/?a=1&amp;b=2" target="_blank">参考</a>です</p>"#,
+            "\n",
+            r#"<p id="L2"><a href="https://ncode.syosetu.com/n1234a/2/">次話</a>へ</p>"#,
+        );
+        let kept = EpisodeIter::correct_links(BODY, true);
+        assert_eq!(
+            kept,
+            r#"<p><a href="https://example.com/?a=1&amp;b=2">参考</a>です</p><p>次話へ</p>"#
+        );
+        // 区画に分けて戻しても同じリンクになる
+        assert_eq!(block::render(&block::parse(&kept)), kept);
+        assert_eq!(EpisodeIter::correct(BODY), "<p>参考です</p><p>次話へ</p>");
+        // 閉じ忘れたリンクも XHTML として閉じる
+        assert_eq!(
+            EpisodeIter::correct_links(r#"<a href="http://example.org/">外"#, true),
+            r#"<a href="http://example.org/">外</a>"#
+        );
+        // 相対や挿絵のサイトへのリンクは残さない
+        assert_eq!(
+            EpisodeIter::correct_links(r#"<a href="//12345.mitemin.net/i1/">絵</a>"#, true),
+            "絵"
+        );
+        assert_eq!(
+            EpisodeIter::correct_links(r#"<a href="https://12345.mitemin.net/i1/">絵</a>"#, true),
+            "絵"
+        );
+    }
+
+    #[test]
+    fn blank_lines() {
+        const BLANK: &str = r#"<p class="blank"><br/></p>"#;
+        let corrected = EpisodeIter::correct(concat!(
+            "<p id=\"L1\">前</p>\n",
+            "<p id=\"L2\"></p>\n",
+            "<p id=\"L3\"><br></p>\n",
+            "<p id=\"L4\"><br /></p>\n",
+            "<p id=\"L5\">後</p>"
+        ));
+        assert_eq!(
+            corrected,
+            ["<p>前</p>", BLANK, BLANK, BLANK, "<p>後</p>"].concat()
+        );
+        assert_eq!(corrected.matches(BLANK).count(), 3);
+        // 改行を含む段落は空行ではない
+        assert_eq!(
+            EpisodeIter::correct("<p id=\"L1\">一<br>二</p>"),
+            "<p>一<br/>二</p>"
+        );
+        let episode = Episode {
+            number: 1,
+            chapter: None,
+            title: String::new(),
+            blocks: block::parse(&corrected),
+            series: true,
+            updated: None,
+            images: vec![],
+        };
+        assert_eq!(episode.plain_body(), "前\n\n\n\n後\n");
+    }
+
+    #[test]
+    fn short_title() {
+        const HTML: &str = concat!(
+            "<head><title>短編の題名 - 小説家になろう</title></head><body>",
+            r#"<h1 class="p-novel__title">短編の題名</h1>"#,
+            r#"<div class="js-novel-text p-novel__text"><p id="L1">本文</p></div>"#,
+        );
+        let (title, body) = EpisodeIter::extract_short(HTML).unwrap();
+        assert_eq!(title, Some("短編の題名"));
+        assert_eq!(body, r#"<p id="L1">本文</p>"#);
+
+        let html = HTML.replace(r#"<h1 class="p-novel__title">短編の題名</h1>"#, "");
+        let (title, _) = EpisodeIter::extract_short(&html).unwrap();
+        assert_eq!(title, Some("短編の題名"));
+
+        let (title, _) = EpisodeIter::extract_short(
+            r#"<div class="js-novel-text p-novel__text"><p id="L1">本文</p></div>"#,
+        )
+        .unwrap();
+        assert_eq!(title, None);
+    }
+
+    #[test]
+    fn layout() {
+        const CURRENT: &str = concat!(
+            "<br>\n<span>第一章</span>",
+            r#"<h1 class="p-novel__title p-novel__title--rensai">第一話</h1>"#,
+            r#"<div class="js-novel-text p-novel__text"><p id="L1">本文</p></div>"#,
+        );
+        const OLD: &str = concat!(
+            "<br>\n<span>第一章</span>",
+            r#"<p class="novel_subtitle">第一話</p>"#,
+            r#"<div id="novel_honbun" class="novel_view"><p id="L1">本文</p></div>"#,
+        );
+        for html in [CURRENT, OLD] {
+            assert_eq!(
+                EpisodeIter::extract(html),
+                Some((Some("第一章"), "第一話", r#"<p id="L1">本文</p>"#))
+            );
+        }
+        assert_eq!(
+            EpisodeIter::extract_short(
+                r#"<p class="novel_title">短編</p><div id="novel_honbun" class="novel_view">本文</div>"#
+            ),
+            Some((Some("短編"), "本文"))
+        );
+        assert_eq!(
+            EpisodeIter::extract(r#"<div class="unknown">本文</div>"#),
+            None
+        );
+    }
+
+    #[test]
+    fn updated() {
+        let page = |date: &str| {
+            [
+                r#"<h1 class="p-novel__title p-novel__title--rensai">第一話</h1>"#,
+                r#"<div class="p-novel__date">"#,
+                date,
+                r#"</div><div class="js-novel-text p-novel__text"><p id="L1">本文</p></div>"#,
+            ]
+            .concat()
+        };
+        assert_eq!(
+            EpisodeIter::extract_updated(&page("2023/04/05 06:07"))
+                .unwrap()
+                .to_string(),
+            "2023-04-04T21:07:00Z"
+        );
+        assert_eq!(EpisodeIter::extract_updated(&page("2023/04/05")), None);
+        assert_eq!(
+            EpisodeIter::extract_updated(&page("2023/13/05 06:07")),
+            None
+        );
+        // 本文の中にある目印は更新日時とみなさない
+        assert_eq!(
+            EpisodeIter::extract_updated(
+                r#"<div class="js-novel-text p-novel__text"><div class="p-novel__date">2023/04/05 06:07</div></div>"#
+            ),
+            None
+        );
+    }
+
+    // 削除された作品のページは構成の変更と区別する
+    #[test]
+    fn unavailable() {
+        let removed = include_str!("../../tests/fixtures/removed.html");
+        assert_eq!(EpisodeIter::extract(removed), None);
+        assert_eq!(EpisodeIter::extract_short(removed), None);
+        assert!(matches!(
+            super::extract_failure(removed),
+            Error::NovelUnavailable
+        ));
+        assert!(matches!(
+            super::extract_failure(r#"<div class="unknown">本文</div>"#),
+            Error::LayoutChanged
+        ));
+        assert!(super::is_redirect_to_top("https://syosetu.com/"));
+        assert!(super::is_redirect_to_top("https://ncode.syosetu.com"));
+        assert!(super::is_redirect_to_top("https://novel18.syosetu.com/"));
+        assert!(!super::is_redirect_to_top(
+            "https://ncode.syosetu.com/n0001a/1/"
+        ));
+    }
+
+    fn source() -> EpisodeSource {
+        static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+        EpisodeSource {
+            series: true,
+            ncode: "n1234a".to_string(),
+            site: Site::General,
+            id: Arc::new(Mutex::new(Id::new_for_name())),
+            images: Arc::new(Mutex::new(HashMap::new())),
+            limiter: Arc::new(RateLimiter::new(Duration::ZERO..=Duration::ZERO)),
+            interrupted: &INTERRUPTED,
+            cancel: Arc::new(AtomicBool::new(false)),
+            cache: None,
+            fetcher: None,
+            outline_only: false,
+            since: None,
+            verbose: false,
+            no_images: false,
+            strip_notes: false,
+            keep_links: false,
+            max_image_size: DEFAULT_MAX_IMAGE_SIZE,
+            remaining_size: Arc::new(AtomicU64::new(DEFAULT_MAX_TOTAL_SIZE)),
+        }
+    }
+
+    #[test]
+    fn size_limits() {
+        // 終わりのない読み込み元でも上限の次の一バイトで止まる
+        assert!(matches!(
+            read_limited(std::io::repeat(0), 1024, "https://example.com/a"),
+            Err(Error::ImageTooLarge(url)) if url == "https://example.com/a"
+        ));
+        assert_eq!(
+            read_limited(&[0u8; 1024][..], 1024, "").unwrap().len(),
+            1024
+        );
+
+        let mut source = source();
+        source.max_image_size = 10;
+        source.remaining_size = Arc::new(AtomicU64::new(15));
+        assert_eq!(source.image_limit(), 10);
+        source.consume(8, "a").unwrap();
+        assert_eq!(source.image_limit(), 7);
+        assert!(matches!(source.consume(8, "b"), Err(Error::ImageTooLarge(url)) if url == "b"));
+    }
+
+    #[test]
+    fn dedup_images() {
+        let source = source();
+        let fetched = Cell::new(0);
+        let fetch = |_: &str| {
+            fetched.set(fetched.get() + 1);
+            Ok((ImageType::Png, b"\x89PNG\r\n\x1a\n".to_vec()))
+        };
+        let (first, first_images) = source
+            .replace_images(r#"<p><img src="//example.com/banner"/></p>"#, fetch)
+            .unwrap();
+        let (second, second_images) = source
+            .replace_images(r#"<p><img src="//example.com/banner"/></p>"#, fetch)
+            .unwrap();
+        assert_eq!(fetched.get(), 1);
+        assert_eq!(first, r#"<p><img src="0.png"/></p>"#);
+        assert_eq!(second, first);
+        // どちらの話も同じ画像を持つ
+        assert_eq!(first_images.len(), 1);
+        assert_eq!(second_images.len(), 1);
+        assert_eq!(second_images[0].name, first_images[0].name);
+        assert!(Arc::ptr_eq(&second_images[0].body, &first_images[0].body));
+        // 一話の中で繰り返し使われても一つだけ持つ
+        let (_, images) = source
+            .replace_images(
+                r#"<p><img src="//example.com/banner"/><img src="//example.com/banner"/></p>"#,
+                fetch,
+            )
+            .unwrap();
+        assert_eq!(images.len(), 1);
+        assert_eq!(fetched.get(), 1);
+    }
+
+    // 取得に失敗した画像は名前を残さず、次に参照した話が取り直す
+    #[test]
+    fn failed_image() {
+        let source = source();
+        let fetched = Cell::new(0);
+        let fetch = |url: &str| {
+            fetched.set(fetched.get() + 1);
+            if url.ends_with("broken") && fetched.get() == 2 {
+                Err(Error::UnknownImageType)
+            } else {
+                Ok((ImageType::Png, b"\x89PNG\r\n\x1a\n".to_vec()))
+            }
+        };
+        let html = r#"<p><img src="//example.com/banner"/><img src="//example.com/broken"/></p>"#;
+        assert!(source.replace_images(html, fetch).is_err());
+        // 先に取得できた画像は中身ごと使い回す
+        let (_, images) = source
+            .replace_images(r#"<p><img src="//example.com/banner"/></p>"#, fetch)
+            .unwrap();
+        assert_eq!(images.len(), 1);
+        assert_eq!(fetched.get(), 2);
+        let (page, images) = source.replace_images(html, fetch).unwrap();
+        assert_eq!(fetched.get(), 3);
+        assert_eq!(page, r#"<p><img src="0.png"/><img src="1.png"/></p>"#);
+        assert_eq!(images.len(), 2);
+    }
+
+    // since より前の話は画像を取得せず、名前も容量も使わない
+    #[test]
+    fn since_skips_images() {
+        struct Dated(AtomicU64);
+        impl Fetcher for Dated {
+            fn text(&self, url: &str) -> crate::narou::Result<String> {
+                let number = url.rsplit('/').next().unwrap();
+                Ok(format!(
+                    r#"<h1 class="p-novel__title p-novel__title--rensai">第{}話</h1><div class="p-novel__date">2023/04/0{} 12:00</div><div class="js-novel-text p-novel__text"><p><img src="//example.com/{}"/></p></div>"#,
+                    number, number, number
+                ))
+            }
+            fn image(&self, url: &str) -> crate::narou::Result<(String, Vec<u8>)> {
+                self.0.fetch_add(1, Ordering::SeqCst);
+                Ok((url.to_string(), b"\x89PNG\r\n\x1a\n".to_vec()))
+            }
         }
+        let fetcher = Arc::new(Dated(AtomicU64::new(0)));
+        let mut source = source();
+        source.fetcher = Some(fetcher.clone());
+        source.since = Some(DateTime::from_jst_str("2023-04-02 00:00:00").unwrap());
+        let remaining = source.remaining_size.clone();
+        let total = remaining.load(Ordering::SeqCst);
+        let older = source.fetch(1).unwrap();
+        assert!(older.blocks.is_empty() && older.images.is_empty());
+        assert_eq!(fetcher.0.load(Ordering::SeqCst), 0);
+        assert_eq!(remaining.load(Ordering::SeqCst), total);
+        // 取り込む話の画像は最初の名前から付く
+        let newer = source.fetch(2).unwrap();
+        assert_eq!(fetcher.0.load(Ordering::SeqCst), 1);
+        assert_eq!(newer.images[0].name, "0.png");
+    }
+
+    // 並列に同じ画像を参照しても取得は一度だけ
+    #[test]
+    fn concurrent_images() {
+        let source = source();
+        let fetched = AtomicU64::new(0);
+        let fetch = |_: &str| {
+            fetched.fetch_add(1, Ordering::SeqCst);
+            std::thread::sleep(Duration::from_millis(20));
+            Ok((ImageType::Png, b"\x89PNG\r\n\x1a\n".to_vec()))
+        };
+        let names: Vec<String> = std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..4)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let (_, images) = source
+                            .replace_images(r#"<img src="//example.com/banner"/>"#, fetch)
+                            .unwrap();
+                        images[0].name.clone()
+                    })
+                })
+                .collect();
+            workers.into_iter().map(|x| x.join().unwrap()).collect()
+        });
+        assert_eq!(fetched.load(Ordering::SeqCst), 1);
+        assert!(names.iter().all(|x| x == "0.png"));
+    }
+
+    #[test]
+    fn image_url_escape() {
+        let corrected = EpisodeIter::correct(
+            r#"<p><img src="//example.com/a?x=1&y=2" alt="" border="0" /></p>"#,
+        );
+        assert_eq!(
+            corrected,
+            r#"<p><img src="//example.com/a?x=1&amp;y=2"/></p>"#
+        );
+        // 実体参照で書かれていても二重に変換しない
+        assert_eq!(
+            EpisodeIter::correct(r#"<img src="//example.com/a?x=1&amp;y=2" alt="" />"#),
+            r#"<img src="//example.com/a?x=1&amp;y=2"/>"#
+        );
+        let requested = RefCell::new(vec![]);
+        let (body, _) = source()
+            .replace_images(&corrected, |url| {
+                requested.borrow_mut().push(url.to_string());
+                Ok((ImageType::Png, b"\x89PNG\r\n\x1a\n".to_vec()))
+            })
+            .unwrap();
+        assert_eq!(*requested.borrow(), ["https://example.com/a?x=1&y=2"]);
+        assert_eq!(body, r#"<p><img src="0.png"/></p>"#);
+        // 本文中の & はすべて実体参照の始まりになっている
+        for (i, _) in corrected.match_indices('&') {
+            assert!(corrected[i..].starts_with("&amp;"));
+        }
+    }
+
+    const NOTES: &str = concat!(
+        r#"<h1 class="p-novel__title p-novel__title--rensai">第一話</h1>"#,
+        r#"<div class="js-novel-text p-novel__text p-novel__text--preface"><p id="Lp1">前書き</p></div>"#,
+        "\n",
+        r#"<div class="js-novel-text p-novel__text"><p id="L1">本文</p></div>"#,
+        "\n",
+        r#"<div class="js-novel-text p-novel__text p-novel__text--afterword"><p id="La1">後書き</p></div>"#,
+    );
+
+    #[test]
+    fn author_notes() {
+        const OLD: &str = concat!(
+            r#"<p class="novel_subtitle">第一話</p>"#,
+            r#"<div id="novel_p" class="novel_view">前書き</div>"#,
+            r#"<div id="novel_honbun" class="novel_view">本文</div>"#,
+            r#"<div id="novel_a" class="novel_view">後書き</div>"#,
+        );
+        assert_eq!(
+            EpisodeIter::extract_notes(NOTES),
+            (
+                Some(r#"<p id="Lp1">前書き</p>"#),
+                Some(r#"<p id="La1">後書き</p>"#)
+            )
+        );
+        assert_eq!(
+            EpisodeIter::extract(NOTES).unwrap().2,
+            r#"<p id="L1">本文</p>"#
+        );
+        assert_eq!(
+            EpisodeIter::extract_notes(OLD),
+            (Some("前書き"), Some("後書き"))
+        );
+        assert_eq!(EpisodeIter::extract(OLD).unwrap().2, "本文");
+        assert_eq!(
+            EpisodeIter::extract_notes(r#"<div id="novel_honbun" class="novel_view">本文</div>"#),
+            (None, None)
+        );
+
+        struct Page;
+        impl Fetcher for Page {
+            fn text(&self, _: &str) -> crate::narou::Result<String> {
+                Ok(NOTES.to_string())
+            }
+            fn image(&self, _: &str) -> crate::narou::Result<(String, Vec<u8>)> {
+                Err(Error::BadStatus(404))
+            }
+        }
+        let mut source = source();
+        source.fetcher = Some(Arc::new(Page));
+        assert_eq!(
+            source.fetch(1).unwrap().body(),
+            [
+                r#"<div class="preface">"#,
+                &EpisodeIter::correct(r#"<p id="Lp1">前書き</p>"#),
+                "</div>",
+                &EpisodeIter::correct(r#"<p id="L1">本文</p>"#),
+                r#"<div class="afterword">"#,
+                &EpisodeIter::correct(r#"<p id="La1">後書き</p>"#),
+                "</div>",
+            ]
+            .concat()
+        );
+        source.strip_notes = true;
+        assert_eq!(
+            source.fetch(1).unwrap().body(),
+            EpisodeIter::correct(r#"<p id="L1">本文</p>"#)
+        );
+    }
+
+    // 少しずつしか返さない読み込み元でも目印を取りこぼさず、本文の後は読まないこと
+    #[test]
+    fn read_until_body_end() {
+        struct Trickle<'a>(&'a [u8]);
+        impl std::io::Read for Trickle<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                let n = self.0.len().min(buf.len()).min(3);
+                buf[..n].copy_from_slice(&self.0[..n]);
+                self.0 = &self.0[n..];
+                Ok(n)
+            }
+        }
+        let page = [
+            r#"<p class="novel_subtitle">題</p><div id="novel_honbun" class="novel_view">"#,
+            "本文</div>",
+        ]
+        .concat();
+        let footer = "<div>フッタ</div>".repeat(10000);
+        let text =
+            super::read_until_body_end(Trickle([page.as_str(), &footer].concat().as_bytes()))
+                .unwrap();
+        assert_eq!(text, page);
+        assert_eq!(EpisodeIter::extract(&text).unwrap().2, "本文");
+
+        // 直後の後書きは閉じタグまで読む
+        let text =
+            super::read_until_body_end(Trickle([NOTES, &footer].concat().as_bytes())).unwrap();
+        assert_eq!(text, NOTES);
+
+        // 本文が見つからなければ最後まで読む
+        let text = super::read_until_body_end(footer.as_bytes()).unwrap();
+        assert_eq!(text, footer);
+        assert!(matches!(
+            super::read_until_body_end(&[0xffu8][..]),
+            Err(Error::InvalidCharCode)
+        ));
+    }
+
+    // 多バイト文字の境界をまたいでも panic しないこと
+    #[test]
+    fn text_util() {
+        assert_eq!("あい".head_and_next(), Some(('あ', "い")));
+        assert_eq!("あ".head_and_next(), Some(('あ', "")));
+        assert_eq!("".head_and_next(), None);
+        assert_eq!(
+            "「始」中身「終」残り".between_and_next("「始」", "「終」"),
+            Some(("中身", "残り"))
+        );
+        assert_eq!("前「始」中身".between_and_next("「始」", "「終」"), None);
+        assert_eq!(
+            "前置き<b>太字</b>後".find_between_and_next("<b>", "</b>"),
+            Some(("前置き", "太字", "後"))
+        );
+        assert_eq!(
+            "一二三".between_any(&[("四", "五"), ("一", "三")]),
+            Some(("二", ""))
+        );
+        assert_eq!("あいう".split_once_any(&["え", "い"]), Some(("あ", "う")));
+    }
+
+    #[test]
+    fn no_images() {
+        let mut source = source();
+        source.no_images = true;
+        let (body, images) = source
+            .replace_images(r#"<p>挿絵<img src="//example.com/a"/></p>"#, |_| {
+                panic!("画像を取得してはいけない")
+            })
+            .unwrap();
+        assert_eq!(body, "<p>挿絵［画像省略］</p>");
+        assert!(images.is_empty());
+    }
+
+    // 途中の話が取得できなくても、飛ばす指定があれば残りの話を集める
+    #[test]
+    fn skip_errors() {
+        struct Broken;
+        impl Fetcher for Broken {
+            fn text(&self, url: &str) -> crate::narou::Result<String> {
+                let number = url.rsplit('/').next().unwrap();
+                if number == "2" {
+                    Err(Error::BadStatus(500))
+                } else {
+                    Ok(format!(
+                        r#"<h1 class="p-novel__title p-novel__title--rensai">第{}話</h1><div class="js-novel-text p-novel__text"><p>本文</p></div>"#,
+                        number
+                    ))
+                }
+            }
+            fn image(&self, _: &str) -> crate::narou::Result<(String, Vec<u8>)> {
+                Err(Error::BadStatus(404))
+            }
+        }
+        let episodes = |skip_errors: bool| {
+            let mut source = source();
+            source.fetcher = Some(Arc::new(Broken));
+            EpisodeIter {
+                cur: 1,
+                max: 4,
+                jobs: 2,
+                source,
+                skip_errors,
+                skipped: vec![],
+                pending: Default::default(),
+            }
+        };
+        let mut iter = episodes(true);
+        let numbers: Vec<u32> = iter.by_ref().map(|x| x.unwrap().number).collect();
+        assert_eq!(numbers, [1, 3, 4]);
+        assert!(matches!(iter.skipped(), [(2, Error::BadStatus(500))]));
+
+        let results: Vec<_> = episodes(false).collect();
+        assert_eq!(results.len(), 4);
+        assert!(matches!(results[1], Err(Error::BadStatus(500))));
     }
 }
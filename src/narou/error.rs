@@ -13,6 +13,17 @@ pub enum Error {
     UnknownImageType,
     InvalidCharCode,
     BadStatus(u32),
+    InvalidStyleSheet,
+    LayoutChanged,
+    NoEpisodes,
+    EpisodeNotFound(u32),
+    MalformedXhtml(String),
+    DuplicateName(String),
+    ImageTooLarge(String),
+    NovelUnavailable,
+    Over18Required,
+    JsonParse(super::super::json::Error),
+    TimeParse(utcdatetime::DateTimeParseError),
 }
 
 impl Display for Error {
@@ -32,6 +43,40 @@ impl Display for Error {
                 "HTTP レスポンスのステータスコード ({}) が想定外です。",
                 code
             ),
+            Error::InvalidStyleSheet => {
+                write!(f, "スタイルシートを読み込めないか、中身が空です。")
+            }
+            Error::LayoutChanged => write!(
+                f,
+                "ページの構成が想定と異なります。サイトの変更に合わせてツールの更新が必要かもしれません。"
+            ),
+            Error::NoEpisodes => write!(f, "この作品にはまだ一話も投稿されていません。"),
+            Error::EpisodeNotFound(number) => write!(f, "第{}話はありません。", number),
+            Error::JsonParse(e) => {
+                write!(f, "API の応答の JSON を解釈できませんでした。 ({})", e)
+            }
+            Error::TimeParse(_) => write!(f, "API の応答の日時を解釈できませんでした。"),
+            Error::NovelUnavailable => write!(
+                f,
+                "作品が削除されたか公開が停止されているため取得できません。"
+            ),
+            Error::Over18Required => write!(
+                f,
+                "年齢制限のある作品です。年齢確認に同意する場合は --over18 を指定するか、環境変数 NAROU_OVER18 に yes を設定してください。"
+            ),
+            Error::ImageTooLarge(url) => {
+                write!(f, "画像が大きすぎるため取得を中止しました。 ({})", url)
+            }
+            Error::MalformedXhtml(name) => {
+                write!(f, "生成した XHTML ({}) が整形式ではありません。", name)
+            }
+            Error::DuplicateName(name) => {
+                write!(
+                    f,
+                    "ePub に同じ名前のファイル ({}) を二度追加しようとしました。",
+                    name
+                )
+            }
         }
     }
 }
@@ -46,21 +91,27 @@ impl From<internet::Error> for Error {
     }
 }
 
-impl From<zip_builder::Error> for Error {
-    fn from(_: zip_builder::Error) -> Self {
-        Error::EpubBuildFailure
+impl From<super::super::epub::Error> for Error {
+    fn from(value: super::super::epub::Error) -> Self {
+        match value {
+            super::super::epub::Error::Zip(_) => Error::EpubBuildFailure,
+            super::super::epub::Error::MalformedXhtml(name) => Error::MalformedXhtml(name),
+            super::super::epub::Error::DuplicateName(name) => Error::DuplicateName(name),
+            super::super::epub::Error::Io => Error::IoFailure,
+            super::super::epub::Error::InvalidArchive => Error::InvalidData,
+        }
     }
 }
 
 impl From<utcdatetime::DateTimeParseError> for Error {
-    fn from(_: utcdatetime::DateTimeParseError) -> Self {
-        Error::InvalidData
+    fn from(value: utcdatetime::DateTimeParseError) -> Self {
+        Error::TimeParse(value)
     }
 }
 
 impl From<super::super::json::Error> for Error {
-    fn from(_: super::super::json::Error) -> Self {
-        Error::InvalidData
+    fn from(value: super::super::json::Error) -> Self {
+        Error::JsonParse(value)
     }
 }
 
@@ -71,3 +122,21 @@ impl From<std::io::Error> for Error {
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+    use super::Error;
+    use crate::epub::time::FromJST;
+    use crate::json::JsonNode;
+    use utcdatetime::DateTime;
+
+    #[test]
+    fn causes() {
+        let json = "[{\"allcount\":1},".parse::<JsonNode>().unwrap_err();
+        let error = Error::from(json);
+        assert!(matches!(error, Error::JsonParse(_)));
+        assert!(error.to_string().contains("JSON"));
+        let time = DateTime::from_jst_str("2024-13-01 00:00:00").unwrap_err();
+        assert!(matches!(Error::from(time), Error::TimeParse(_)));
+    }
+}
@@ -0,0 +1,119 @@
+use super::error::Result;
+
+/// 通信の代わりに使う取得先
+///
+/// 試験で記録しておいた応答を返すために使い、指定しなければ WinInet で取得する
+pub trait Fetcher: Send + Sync {
+    /// ページの本文を返す
+    fn text(&self, url: &str) -> Result<String>;
+
+    /// 画像の URL を辿った先の URL と画像の本体を返す
+    fn image(&self, url: &str) -> Result<(String, Vec<u8>)>;
+}
+
+/// tests/fixtures に記録した応答を URL ごとに返す
+#[cfg(test)]
+pub struct Recorded {
+    pages: Vec<(&'static str, &'static str)>,
+    images: Vec<(&'static str, &'static str, &'static [u8])>,
+}
+
+#[cfg(test)]
+impl Recorded {
+    pub fn new() -> Self {
+        const IMAGE_URL: &str =
+            "https://12345.mitemin.net/userpageimage/viewimagebig/icode/i000001/";
+        Self {
+            pages: vec![
+                (
+                    "https://api.syosetu.com/novelapi/api/?ncode=n0001a&out=json&of=t-nu-s-w-u-nt-e-i-ga-k-bg-g-l",
+                    include_str!("../../tests/fixtures/novel.json"),
+                ),
+                (
+                    "https://api.syosetu.com/novelapi/api/?ncode=n0002a&out=json&of=t-nu-s-w-u-nt-e-i-ga-k-bg-g-l",
+                    include_str!("../../tests/fixtures/short.json"),
+                ),
+                (
+                    "https://api.syosetu.com/novelapi/api/?ncode=n0003a&out=json&of=t-nu-s-w-u-nt-e-i-ga-k-bg-g-l",
+                    include_str!("../../tests/fixtures/outline.json"),
+                ),
+                (
+                    "https://api.syosetu.com/novelapi/api/?ncode=n0004a&out=json&of=t-nu-s-w-u-nt-e-i-ga-k-bg-g-l",
+                    include_str!("../../tests/fixtures/missing.json"),
+                ),
+                (
+                    "https://api.syosetu.com/novel18api/api/?ncode=n0004a&out=json&of=t-nu-s-w-u-nt-e-i-ga-k-bg-g-l",
+                    include_str!("../../tests/fixtures/r18.json"),
+                ),
+                (
+                    "https://api.syosetu.com/novelapi/api/?ncode=n0005a&out=json&of=t-nu-s-w-u-nt-e-i-ga-k-bg-g-l",
+                    include_str!("../../tests/fixtures/anonymous.json"),
+                ),
+                (
+                    "https://api.syosetu.com/userapi/api/?userid=12345&out=json&of=y",
+                    include_str!("../../tests/fixtures/user.json"),
+                ),
+                (
+                    "https://api.syosetu.com/userapi/api/?userid=67890&out=json&of=y",
+                    include_str!("../../tests/fixtures/user_no_yomikata.json"),
+                ),
+                (
+                    "https://ncode.syosetu.com/n0001a/1",
+                    include_str!("../../tests/fixtures/episode_1.html"),
+                ),
+                (
+                    "https://ncode.syosetu.com/n0001a/2",
+                    include_str!("../../tests/fixtures/episode_2.html"),
+                ),
+                (
+                    "https://ncode.syosetu.com/n0003a/1",
+                    include_str!("../../tests/fixtures/outline_1.html"),
+                ),
+                (
+                    "https://ncode.syosetu.com/n0003a/2",
+                    include_str!("../../tests/fixtures/outline_2.html"),
+                ),
+                (
+                    "https://ncode.syosetu.com/n0003a/3",
+                    include_str!("../../tests/fixtures/outline_3.html"),
+                ),
+                (
+                    "https://novel18.syosetu.com/n0004a/1",
+                    include_str!("../../tests/fixtures/episode_1.html"),
+                ),
+                (
+                    "https://ncode.syosetu.com/n0002a",
+                    include_str!("../../tests/fixtures/short.html"),
+                ),
+                (
+                    "https://ncode.syosetu.com/n0005a",
+                    include_str!("../../tests/fixtures/short.html"),
+                ),
+            ],
+            images: vec![(
+                IMAGE_URL,
+                "https://img1.mitemin.net/ab/cd/12345/i000001.png",
+                include_bytes!("../../tests/fixtures/image.png"),
+            )],
+        }
+    }
+}
+
+#[cfg(test)]
+impl Fetcher for Recorded {
+    fn text(&self, url: &str) -> Result<String> {
+        self.pages
+            .iter()
+            .find(|(x, _)| *x == url)
+            .map(|(_, body)| body.to_string())
+            .ok_or(super::Error::BadStatus(404))
+    }
+
+    fn image(&self, url: &str) -> Result<(String, Vec<u8>)> {
+        self.images
+            .iter()
+            .find(|(x, _, _)| *x == url)
+            .map(|(_, location, body)| (location.to_string(), body.to_vec()))
+            .ok_or(super::Error::BadStatus(404))
+    }
+}
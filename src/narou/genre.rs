@@ -0,0 +1,56 @@
+/// 大ジャンルのコードに対応する名前
+pub fn big_genre_name(code: u32) -> Option<&'static str> {
+    match code {
+        1 => Some("恋愛"),
+        2 => Some("ファンタジー"),
+        3 => Some("文芸"),
+        4 => Some("SF"),
+        98 => Some("ノンジャンル"),
+        99 => Some("その他"),
+        _ => None,
+    }
+}
+
+/// ジャンルのコードに対応する名前
+pub fn genre_name(code: u32) -> Option<&'static str> {
+    match code {
+        101 => Some("異世界"),
+        102 => Some("現実世界"),
+        201 => Some("ハイファンタジー"),
+        202 => Some("ローファンタジー"),
+        301 => Some("純文学"),
+        302 => Some("ヒューマンドラマ"),
+        303 => Some("歴史"),
+        304 => Some("推理"),
+        305 => Some("ホラー"),
+        306 => Some("アクション"),
+        307 => Some("コメディー"),
+        401 => Some("VRゲーム"),
+        402 => Some("宇宙"),
+        403 => Some("空想科学"),
+        404 => Some("パニック"),
+        9801 => Some("ノンジャンル"),
+        9901 => Some("童話"),
+        9902 => Some("詩"),
+        9903 => Some("エッセイ"),
+        9904 => Some("リプレイ"),
+        9999 => Some("その他"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names() {
+        assert_eq!(big_genre_name(1), Some("恋愛"));
+        assert_eq!(big_genre_name(2), Some("ファンタジー"));
+        assert_eq!(big_genre_name(5), None);
+        assert_eq!(genre_name(101), Some("異世界"));
+        assert_eq!(genre_name(201), Some("ハイファンタジー"));
+        assert_eq!(genre_name(9999), Some("その他"));
+        assert_eq!(genre_name(0), None);
+    }
+}
@@ -1,8 +1,11 @@
 #![allow(dead_code)]
 use std::convert::From;
-use std::ffi::c_void;
+use std::ffi::{CString, c_void};
 use std::ptr::null;
 use std::str::Utf8Error;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 use windows_sys::Win32::Foundation::{ERROR_INSUFFICIENT_BUFFER, GetLastError, WIN32_ERROR};
 use windows_sys::Win32::Networking::WinInet::*;
 
@@ -29,34 +32,149 @@ impl From<Utf8Error> for Error {
 
 pub struct Internet {
     handle: *mut c_void,
+    headers: &'static str,
+}
+
+// 年齢確認に同意したことにする。全ての接続に共通なのでプロセス全体で一つだけ持つ
+static OVER18: AtomicBool = AtomicBool::new(false);
+
+/// 以後の全てのアクセスで年齢確認に同意したことを示すクッキーを送るかどうかを決める
+pub fn set_over18(over18: bool) {
+    OVER18.store(over18, Ordering::SeqCst);
+}
+
+/// 年齢確認に同意したことになっているかどうか
+pub fn over18() -> bool {
+    OVER18.load(Ordering::SeqCst)
+}
+
+// 名乗るユーザーエージェント。指定がなければ AGENT_NAME を使う
+static USER_AGENT: Mutex<Option<String>> = Mutex::new(None);
+
+/// 以後の全てのアクセスで名乗るユーザーエージェントを決める
+pub fn set_user_agent(agent: Option<String>) {
+    *USER_AGENT.lock().unwrap() = agent;
+}
+
+/// 名乗ることになっているユーザーエージェント
+pub fn user_agent() -> String {
+    USER_AGENT
+        .lock()
+        .unwrap()
+        .clone()
+        .unwrap_or_else(|| super::AGENT_NAME.to_string())
+}
+
+/// 接続に使うプロキシ
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Proxy {
+    /// ウィンドウズに設定されたプロキシを使う
+    #[default]
+    System,
+    /// プロキシを使わずに直接つなぐ
+    Direct,
+    /// 指定したプロキシを使う
+    Server(String),
+}
+
+impl Proxy {
+    // InternetOpenA に渡す接続の種類
+    fn open_type(&self) -> u32 {
+        match self {
+            Proxy::System => INTERNET_OPEN_TYPE_PRECONFIG,
+            Proxy::Direct => INTERNET_OPEN_TYPE_DIRECT,
+            Proxy::Server(_) => INTERNET_OPEN_TYPE_PROXY,
+        }
+    }
+
+    // InternetOpenA に渡すプロキシのサーバで、プロキシを指定しないときは None
+    fn server(&self) -> Option<CString> {
+        match self {
+            Proxy::Server(server) => Some(agent_cstring(server)),
+            _ => None,
+        }
+    }
+}
+
+// 接続に使うプロキシ。全ての接続に共通なのでプロセス全体で一つだけ持つ
+static PROXY: Mutex<Proxy> = Mutex::new(Proxy::System);
+
+/// 以後の全てのアクセスで使うプロキシを決める
+pub fn set_proxy(proxy: Proxy) {
+    *PROXY.lock().unwrap() = proxy;
+}
+
+/// 使うことになっているプロキシ
+pub fn proxy() -> Proxy {
+    PROXY.lock().unwrap().clone()
+}
+
+// InternetOpenA に渡せるように途中の NUL を除いて終端を付ける
+fn agent_cstring(agent: &str) -> CString {
+    CString::new(agent.replace('\0', "")).unwrap()
+}
+
+// 全てのアクセスに付ける追加のヘッダ
+fn consent_headers(over18: bool) -> &'static str {
+    if over18 { "Cookie: over18=yes\r\n" } else { "" }
 }
 
 impl Internet {
     pub fn new() -> Result<Self> {
+        let agent = agent_cstring(&user_agent());
+        let proxy = proxy();
+        let server = proxy.server();
         unsafe {
             let handle = InternetOpenA(
-                super::AGENT_NAME.as_ptr(),
-                INTERNET_OPEN_TYPE_PRECONFIG,
-                null(),
+                agent.as_ptr() as *const u8,
+                proxy.open_type(),
+                server.as_ref().map_or(null(), |x| x.as_ptr() as *const u8),
                 null(),
                 0,
             );
             if handle.is_null() {
                 Err(GetLastError())?
             } else {
-                Ok(Internet { handle })
+                Ok(Internet {
+                    handle,
+                    headers: consent_headers(over18()),
+                })
             }
         }
     }
 
     pub fn open(&self, url: &str) -> Result<Response> {
+        self.open_with_headers(url, "")
+    }
+
+    // headers は "名前: 値\r\n" を連ねたもの。
+    // 混雑 (429) や保守中 (503) の応答なら指示された時間だけ待ってやり直す
+    pub fn open_with_headers(&self, url: &str, headers: &str) -> Result<Response> {
+        let headers = [self.headers, headers].concat();
+        retrying(
+            || self.send(url, &headers),
+            |response| {
+                Ok((
+                    response.status_code()?,
+                    response.header(Query::RetryAfter).ok(),
+                ))
+            },
+            std::thread::sleep,
+        )
+    }
+
+    fn send(&self, url: &str, headers: &str) -> Result<Response> {
         unsafe {
             let nullterminated_url_string = [url, "\0"].concat();
             let handle = InternetOpenUrlA(
                 self.handle,
                 nullterminated_url_string.as_ptr(),
-                null(),
-                0,
+                if headers.is_empty() {
+                    null()
+                } else {
+                    headers.as_ptr()
+                },
+                headers.len() as u32,
                 INTERNET_FLAG_RELOAD | INTERNET_FLAG_SECURE | INTERNET_FLAG_NO_AUTO_REDIRECT,
                 0,
             );
@@ -73,6 +191,48 @@ pub struct Response {
     handle: *mut c_void,
 }
 
+// 一時的な拒否に対してやり直す回数と、待つ時間の既定値と上限
+const MAX_RETRIES: u32 = 3;
+const DEFAULT_RETRY_DELAY: Duration = Duration::from_secs(5);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+// やり直すべき応答なら待つ時間を返す。
+// Retry-After は秒数のみ解釈し、日時で指定されたときは既定値を使う
+fn retry_delay(status: u32, retry_after: Option<&str>) -> Option<Duration> {
+    if status != 429 && status != 503 {
+        return None;
+    }
+    Some(
+        retry_after
+            .and_then(|x| x.trim().parse().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_RETRY_DELAY)
+            .min(MAX_RETRY_DELAY),
+    )
+}
+
+// inspect で得たステータスコードと Retry-After を見て、必要なら sleep で待ってから send をやり直す。
+// 回数を使い切ったときは最後の応答をそのまま返し、判断は error_for_status に任せる
+fn retrying<T>(
+    mut send: impl FnMut() -> Result<T>,
+    inspect: impl Fn(&T) -> Result<(u32, Option<String>)>,
+    mut sleep: impl FnMut(Duration),
+) -> Result<T> {
+    let mut retries = 0;
+    loop {
+        let response = send()?;
+        let (status, retry_after) = inspect(&response)?;
+        match retry_delay(status, retry_after.as_deref()) {
+            Some(delay) if retries < MAX_RETRIES => {
+                drop(response);
+                sleep(delay);
+                retries += 1;
+            }
+            _ => return Ok(response),
+        }
+    }
+}
+
 impl Drop for Internet {
     fn drop(&mut self) {
         unsafe {
@@ -112,6 +272,9 @@ impl std::io::Read for Response {
 #[derive(Clone, Copy)]
 pub enum Query {
     Location = HTTP_QUERY_LOCATION,
+    ETag = HTTP_QUERY_ETAG,
+    LastModified = HTTP_QUERY_LAST_MODIFIED,
+    RetryAfter = HTTP_QUERY_RETRY_AFTER,
 }
 
 impl Response {
@@ -186,4 +349,78 @@ mod tests {
         assert_eq!(response.header(Query::Location)?, "https://example.com/");
         Ok(())
     }
+
+    #[test]
+    fn consent() {
+        assert_eq!(consent_headers(true), "Cookie: over18=yes\r\n");
+        assert_eq!(consent_headers(false), "");
+    }
+
+    #[test]
+    fn agent() {
+        assert!(user_agent().ends_with(env!("CARGO_PKG_VERSION")));
+        set_user_agent(Some("Mozilla/5.0 (compatible)".to_string()));
+        assert_eq!(user_agent(), "Mozilla/5.0 (compatible)");
+        assert_eq!(
+            agent_cstring(&user_agent()).as_bytes_with_nul(),
+            b"Mozilla/5.0 (compatible)\0"
+        );
+        set_user_agent(None);
+        assert_eq!(user_agent(), super::super::AGENT_NAME);
+        assert_eq!(agent_cstring("a\0b").as_bytes(), b"ab");
+    }
+
+    #[test]
+    fn proxy_open_type() {
+        assert_eq!(Proxy::default(), Proxy::System);
+        assert_eq!(Proxy::System.open_type(), INTERNET_OPEN_TYPE_PRECONFIG);
+        assert_eq!(Proxy::Direct.open_type(), INTERNET_OPEN_TYPE_DIRECT);
+        let server = Proxy::Server("http://proxy.example.com:8080".to_string());
+        assert_eq!(server.open_type(), INTERNET_OPEN_TYPE_PROXY);
+        assert_eq!(
+            server.server().unwrap().as_bytes_with_nul(),
+            b"http://proxy.example.com:8080\0"
+        );
+        assert!(Proxy::System.server().is_none());
+        assert!(Proxy::Direct.server().is_none());
+    }
+
+    #[test]
+    fn retry_delay_test() {
+        assert_eq!(retry_delay(200, Some("10")), None);
+        assert_eq!(retry_delay(404, None), None);
+        assert_eq!(retry_delay(429, Some(" 10")), Some(Duration::from_secs(10)));
+        assert_eq!(retry_delay(503, None), Some(DEFAULT_RETRY_DELAY));
+        assert_eq!(
+            retry_delay(503, Some("Wed, 21 Oct 2015 07:28:00 GMT")),
+            Some(DEFAULT_RETRY_DELAY)
+        );
+        assert_eq!(retry_delay(429, Some("86400")), Some(MAX_RETRY_DELAY));
+    }
+
+    #[test]
+    fn retrying_test() {
+        let mut responses = vec![(200, None), (429, Some("2".to_string()))];
+        let mut slept = vec![];
+        let result = retrying(
+            || Ok(responses.pop().unwrap()),
+            |response| Ok(response.clone()),
+            |delay| slept.push(delay),
+        );
+        assert_eq!(result.unwrap().0, 200);
+        assert_eq!(slept, [Duration::from_secs(2)]);
+
+        // 回数を使い切ったら最後の応答を返す
+        let mut sent = 0;
+        let result = retrying(
+            || {
+                sent += 1;
+                Ok((503, None))
+            },
+            |response| Ok(response.clone()),
+            |_| {},
+        );
+        assert_eq!(result.unwrap().0, 503);
+        assert_eq!(sent, MAX_RETRIES + 1);
+    }
 }
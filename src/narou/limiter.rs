@@ -0,0 +1,98 @@
+use std::ops::RangeInclusive;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use windows_sys::Win32::Foundation::STATUS_SUCCESS;
+use windows_sys::Win32::Security::Cryptography::{
+    BCRYPT_USE_SYSTEM_PREFERRED_RNG, BCryptGenRandom,
+};
+
+/// 複数のスレッドから共有して使うアクセス間隔の制限
+pub struct RateLimiter {
+    interval: RangeInclusive<Duration>,
+    next: Mutex<Option<Instant>>,
+}
+
+impl RateLimiter {
+    pub fn new(interval: RangeInclusive<Duration>) -> Self {
+        Self {
+            interval,
+            next: Mutex::new(None),
+        }
+    }
+
+    // 間隔の範囲から一様に選ぶ。乱数が得られなければ最小値にする
+    fn interval(&self) -> Duration {
+        let (min, max) = (*self.interval.start(), *self.interval.end());
+        if max <= min {
+            return min;
+        }
+        let mut random = [0u8; 8];
+        if unsafe {
+            BCryptGenRandom(
+                std::ptr::null_mut(),
+                random.as_mut_ptr(),
+                random.len() as u32,
+                BCRYPT_USE_SYSTEM_PREFERRED_RNG,
+            )
+        } != STATUS_SUCCESS
+        {
+            return min;
+        }
+        let ratio = (u64::from_le_bytes(random) >> 11) as f64 / (1u64 << 53) as f64;
+        min + (max - min).mul_f64(ratio)
+    }
+
+    // 前回のアクセスから interval 以上経過するまで待つ。間隔が 0 なら何もしない
+    pub fn wait(&self) {
+        if self.interval.end().is_zero() {
+            return;
+        }
+        let now = Instant::now();
+        let interval = self.interval();
+        let at = {
+            let mut next = self.next.lock().unwrap();
+            let at = next.map_or(now, |next| next.max(now));
+            *next = Some(at + interval);
+            at
+        };
+        if at > now {
+            std::thread::sleep(at - now);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RateLimiter;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn it_works() {
+        let interval = Duration::from_millis(50);
+        let limiter = RateLimiter::new(interval..=interval);
+        let start = Instant::now();
+        limiter.wait();
+        assert!(start.elapsed() < Duration::from_millis(50));
+        limiter.wait();
+        limiter.wait();
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn zero() {
+        let limiter = RateLimiter::new(Duration::ZERO..=Duration::ZERO);
+        for _ in 0..1000 {
+            limiter.wait();
+        }
+        assert!(limiter.next.lock().unwrap().is_none());
+    }
+
+    #[test]
+    fn jitter() {
+        let limiter = RateLimiter::new(Duration::from_millis(10)..=Duration::from_millis(20));
+        for _ in 0..100 {
+            let interval = limiter.interval();
+            assert!((Duration::from_millis(10)..=Duration::from_millis(20)).contains(&interval));
+        }
+    }
+}
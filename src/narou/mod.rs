@@ -1,133 +1,483 @@
+pub mod block;
+mod cache;
 pub mod episode;
 mod error;
+mod fetcher;
+mod genre;
 mod internet;
+mod limiter;
 mod unescape;
 use super::epub::time::FromJST;
-use episode::EpisodeIter;
+pub use cache::Cache;
+use episode::{Episode, EpisodeIter, EpisodeSource};
 pub use error::{Error, Result};
+pub use fetcher::Fetcher;
+#[cfg(test)]
+pub use fetcher::Recorded;
+pub use internet::{Proxy, set_over18, set_proxy, set_user_agent};
+use limiter::RateLimiter;
+use std::collections::{HashMap, VecDeque};
 use std::io::Read;
+use std::ops::RangeInclusive;
+use std::sync::atomic::{AtomicBool, AtomicU64};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use unescape::Unescape;
 use utcdatetime::DateTime;
-pub const AGENT_NAME: &str = concat!("narou-epub-agent/", env!("CARGO_PKG_VERSION"), "\0");
+pub const AGENT_NAME: &str = concat!("narou-epub-agent/", env!("CARGO_PKG_VERSION"));
 use crate::epub::Id;
 use crate::json::{JsonNode, Query};
 use internet::Internet;
 
+/// NCODE の形式を確かめて小文字にそろえる
+pub fn ncode_validate_and_normalize(s: &str) -> Option<String> {
+    let mut normalized = String::new();
+    let mut iter = s.chars();
+    normalized.push(iter.next().and_then(|ch| {
+        if ch == 'n' || ch == 'N' {
+            Some('n')
+        } else {
+            None
+        }
+    })?);
+    for _ in 0..4 {
+        normalized.push(iter.next().filter(char::is_ascii_digit)?);
+    }
+    for ch in iter {
+        normalized.push(
+            ch.is_ascii_alphabetic()
+                .then_some(ch.to_ascii_lowercase())?,
+        );
+    }
+    Some(normalized)
+}
+
+// 作品一覧の API の一頁分から総数と NCODE を取り出す
+fn parse_works(response: &str) -> Result<(u32, Vec<String>)> {
+    let response: JsonNode = response.parse()?;
+    let allcount = Query::new()
+        .get(0)
+        .get("allcount")
+        .execute(&response)
+        .and_then(JsonNode::as_u32)
+        .ok_or(Error::InvalidData)?;
+    let ncodes = (1..)
+        .map_while(|i| response.get(i))
+        .map(|work| {
+            work.get("ncode")
+                .and_then(JsonNode::as_str)
+                .map(|ncode| ncode.to_lowercase())
+                .ok_or(Error::InvalidData)
+        })
+        .collect::<Result<Vec<String>>>()?;
+    Ok((allcount, ncodes))
+}
+
+/// 作者の全作品の NCODE を取得する
+///
+/// 一度に取得できる件数には上限があるので、頁ごとに wait の間隔を空けて繰り返し問い合わせる
+pub fn author_works(userid: u32, wait: Duration) -> Result<Vec<String>> {
+    const LIMIT: usize = 500;
+    let internet = Internet::new()?;
+    let mut ncodes: Vec<String> = vec![];
+    loop {
+        let uri = format!(
+            "https://api.syosetu.com/novelapi/api/?userid={userid}&out=json&of=n&lim={LIMIT}&st={}",
+            ncodes.len() + 1
+        );
+        let mut response = String::new();
+        internet
+            .open(&uri)?
+            .error_for_status()?
+            .read_to_string(&mut response)?;
+        let (allcount, page) = parse_works(&response)?;
+        if page.is_empty() {
+            break;
+        }
+        ncodes.extend(page);
+        if ncodes.len() >= allcount as usize {
+            break;
+        }
+        if !wait.is_zero() {
+            std::thread::sleep(wait);
+        }
+    }
+    Ok(ncodes)
+}
+
+/// 目次の一項目で、本文を取得せずに分かる話の番号と章と題名
+#[derive(Debug, PartialEq)]
+pub struct OutlineEntry {
+    pub number: u32,
+    pub chapter: Option<String>,
+    pub title: String,
+}
+
+/// 作品の形式
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NovelType {
+    Serial,
+    ShortStory,
+}
+
+/// 掲載の状況で、短編は完結として扱う
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Status {
+    Completed,
+    Ongoing,
+    // 長期間更新が止まっている連載
+    Suspended,
+}
+
+impl std::fmt::Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Status::Completed => write!(f, "完結"),
+            Status::Ongoing => write!(f, "連載中"),
+            Status::Suspended => write!(f, "連載停止中"),
+        }
+    }
+}
+
 pub struct Novel {
     ncode: String,
     title: String,
     author_name: String,
-    author_yomigana: String,
+    // 作者名の読み仮名で、登録されていなければ None
+    author_yomigana: Option<String>,
     last_update: DateTime,
     story: String,
-    series: bool,
+    keywords: Vec<String>,
+    genres: Vec<&'static str>,
+    novel_type: NovelType,
+    status: Status,
     episode: u32,
+    // 本文の文字数で、応答に含まれなければ None
+    length: Option<u32>,
+    site: Site,
+    fetcher: Option<Arc<dyn Fetcher>>,
+}
+
+/// 作品が掲載されているサイト
+///
+/// 年齢制限のある作品は別のサイトに掲載され、作品の情報も別の API から取得する
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Site {
+    General,
+    R18,
+}
+
+impl Site {
+    fn api(&self) -> &'static str {
+        match self {
+            Site::General => "https://api.syosetu.com/novelapi/api/",
+            Site::R18 => "https://api.syosetu.com/novel18api/api/",
+        }
+    }
+
+    /// 各話のページの URL の共通部分
+    pub fn base(&self) -> &'static str {
+        match self {
+            Site::General => "https://ncode.syosetu.com/",
+            Site::R18 => "https://novel18.syosetu.com/",
+        }
+    }
+}
+
+// 小説 API の応答から作品の情報と作者のユーザ ID を取り出す。作者の読み仮名は別に取得する
+fn parse_novel(ncode: &str, response: &str) -> Result<(Novel, u32)> {
+    let response: JsonNode = response.parse()?;
+    let allcount = Query::new()
+        .get(0)
+        .get("allcount")
+        .execute(&response)
+        .and_then(JsonNode::as_u32)
+        .ok_or(Error::InvalidData)?;
+    // 年齢制限のある作品は一般の API からは見つからない
+    if allcount == 0 {
+        return Err(Error::NovelUnavailable);
+    }
+    if allcount != 1 {
+        return Err(Error::InvalidData);
+    };
+    let object = response.get(1).ok_or(Error::InvalidData)?;
+    let title = object
+        .get("title")
+        .and_then(JsonNode::as_str)
+        .ok_or(Error::InvalidData)?
+        .unescape();
+    let novel_type = match object.get("noveltype") {
+        Some(JsonNode::Number(1)) => NovelType::Serial,
+        Some(JsonNode::Number(2)) => NovelType::ShortStory,
+        _ => return Err(Error::InvalidData),
+    };
+    // end は短編と完結済みの連載で 0 になる。応答になければ連載中とみなす
+    let status = match (
+        novel_type,
+        object.get("end").and_then(JsonNode::as_u32),
+        object.get("isstop").and_then(JsonNode::as_u32),
+    ) {
+        (NovelType::ShortStory, _, _) | (_, Some(0), _) => Status::Completed,
+        (_, _, Some(1)) => Status::Suspended,
+        _ => Status::Ongoing,
+    };
+    let userid: u32 = object
+        .get("userid")
+        .and_then(JsonNode::as_u32)
+        .ok_or(Error::InvalidData)?;
+    let author_name = object
+        .get("writer")
+        .and_then(JsonNode::as_str)
+        .ok_or(Error::InvalidData)?
+        .unescape();
+    let story = object
+        .get("story")
+        .and_then(JsonNode::as_str)
+        .ok_or(Error::InvalidData)?
+        .unescape();
+    // キーワードは空白区切りで、登録されていない作品もある
+    let keywords = object
+        .get("keyword")
+        .and_then(JsonNode::as_str)
+        .map(|keyword| {
+            keyword
+                .unescape()
+                .split_whitespace()
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default();
+    // 知らないコードのジャンルは記録しない
+    let genres = [
+        object
+            .get("biggenre")
+            .and_then(JsonNode::as_u32)
+            .and_then(genre::big_genre_name),
+        object
+            .get("genre")
+            .and_then(JsonNode::as_u32)
+            .and_then(genre::genre_name),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    let last_update: DateTime = DateTime::from_jst_str(
+        object
+            .get("novelupdated_at")
+            .and_then(JsonNode::as_str)
+            .ok_or(Error::InvalidData)?,
+    )?;
+    let episode = object
+        .get("general_all_no")
+        .and_then(JsonNode::as_u32)
+        .ok_or(Error::InvalidData)?;
+    // 登録されたばかりで一話も投稿されていない作品は本にできない
+    if episode == 0 {
+        return Err(Error::NoEpisodes);
+    }
+    let length = object.get("length").and_then(JsonNode::as_u32);
+    Ok((
+        Novel {
+            ncode: ncode.to_string(),
+            title,
+            author_name,
+            author_yomigana: None,
+            last_update,
+            story,
+            keywords,
+            genres,
+            novel_type,
+            status,
+            episode,
+            length,
+            site: Site::General,
+            fetcher: None,
+        },
+        userid,
+    ))
+}
+
+// API の応答を文字列として読む
+fn fetch_text(internet: &Internet, uri: &str) -> Result<String> {
+    let mut response = String::new();
+    internet
+        .open(uri)?
+        .error_for_status()?
+        .read_to_string(&mut response)?;
+    Ok(response)
+}
+
+/// 作品の一話だけを取得する
+///
+/// 作品の情報を site の API で引いて連載か短編かを確かめる。短編の話数は 1 とする
+pub fn fetch_episode(ncode: &str, number: u32, site: Site) -> Result<Episode> {
+    fetch_one(ncode, number, site, None)
+}
+
+/// 通信の代わりに fetcher から取得する fetch_episode
+#[cfg(test)]
+pub fn fetch_episode_with_fetcher(
+    ncode: &str,
+    number: u32,
+    site: Site,
+    fetcher: Arc<dyn Fetcher>,
+) -> Result<Episode> {
+    fetch_one(ncode, number, site, Some(fetcher))
+}
+
+// fetcher がなければ通信して取得する
+fn fetch_one(
+    ncode: &str,
+    number: u32,
+    site: Site,
+    fetcher: Option<Arc<dyn Fetcher>>,
+) -> Result<Episode> {
+    static NOT_INTERRUPTED: AtomicBool = AtomicBool::new(false);
+    let ncode = ncode_validate_and_normalize(ncode).ok_or(Error::InvalidNcode)?;
+    let (mut novel, _) = match &fetcher {
+        Some(fetcher) => Novel::load_from(site, &ncode, |uri| fetcher.text(uri))?,
+        None => {
+            // 年齢確認への同意は利用者が明示したときだけ送る
+            if site == Site::R18 && !internet::over18() {
+                return Err(Error::Over18Required);
+            }
+            let internet = Internet::new()?;
+            Novel::load_from(site, &ncode, |uri| fetch_text(&internet, uri))?
+        }
+    };
+    if !(1..=novel.episode).contains(&number) {
+        return Err(Error::EpisodeNotFound(number));
+    }
+    novel.fetcher = fetcher;
+    let mut episodes = novel.episodes(1, Duration::ZERO..=Duration::ZERO, &NOT_INTERRUPTED)?;
+    episodes.cur = number;
+    episodes.max = number;
+    episodes.next().ok_or(Error::EpisodeNotFound(number))?
 }
 
 impl Novel {
     pub fn new(ncode: &str) -> Result<Self> {
-        let uri = [
-            "https://api.syosetu.com/novelapi/api/?ncode=",
-            ncode,
-            "&out=json&of=t-nu-s-w-u-nt-ga",
-        ]
-        .concat();
         let internet = Internet::new()?;
-        let mut response = String::new();
-        internet
-            .open(&uri)?
-            .error_for_status()?
-            .read_to_string(&mut response)?;
-        let response: JsonNode = response.parse()?;
-        let allcount = Query::new()
-            .get(0)
-            .get("allcount")
-            .execute(&response)
-            .and_then(JsonNode::get_number)
-            .ok_or(Error::InvalidData)?;
-        if allcount != 1 {
-            return Err(Error::InvalidData);
-        };
-        let object = response.get(1).ok_or(Error::InvalidData)?;
-        let title = object
-            .get("title")
-            .and_then(JsonNode::get_string)
-            .ok_or(Error::InvalidData)?
-            .unescape();
-        let series = match object.get("noveltype") {
-            Some(JsonNode::Number(1)) => true,
-            Some(JsonNode::Number(2)) => false,
-            _ => return Err(Error::InvalidData),
+        let novel = Self::load(ncode, |uri| fetch_text(&internet, uri))?;
+        // 年齢確認への同意は利用者が明示したときだけ送る
+        if novel.site == Site::R18 && !internet::over18() {
+            return Err(Error::Over18Required);
+        }
+        Ok(novel)
+    }
+
+    /// 通信の代わりに fetcher から作品の情報と各話を取得する
+    #[cfg(test)]
+    pub fn with_fetcher(ncode: &str, fetcher: Arc<dyn Fetcher>) -> Result<Self> {
+        let mut novel = Self::load(ncode, |uri| fetcher.text(uri))?;
+        novel.fetcher = Some(fetcher);
+        Ok(novel)
+    }
+
+    // 一般の API で見つからなければ年齢制限のある作品として探し直す
+    fn load(ncode: &str, fetch: impl Fn(&str) -> Result<String>) -> Result<Self> {
+        let (mut novel, userid) = match Self::load_from(Site::General, ncode, &fetch) {
+            Err(Error::NovelUnavailable) => Self::load_from(Site::R18, ncode, &fetch)?,
+            result => result?,
         };
-        let userid: u32 = object
-            .get("userid")
-            .and_then(JsonNode::get_number)
-            .ok_or(Error::InvalidData)?;
-        let author_name = object
-            .get("writer")
-            .and_then(JsonNode::get_string)
-            .ok_or(Error::InvalidData)?
-            .unescape();
-        let story = object
-            .get("story")
-            .and_then(JsonNode::get_string)
-            .ok_or(Error::InvalidData)?
-            .unescape();
-        let last_update: DateTime = DateTime::from_jst_str(
-            object
-                .get("novelupdated_at")
-                .and_then(JsonNode::get_string)
-                .ok_or(Error::InvalidData)?
-                .as_str(),
-        )?;
-        let episode = object
-            .get("general_all_no")
-            .and_then(JsonNode::get_number)
-            .ok_or(Error::InvalidData)?;
         let uri = format!("https://api.syosetu.com/userapi/api/?userid={userid}&out=json&of=y");
-        let mut response = String::new();
-        internet
-            .open(&uri)?
-            .error_for_status()?
-            .read_to_string(&mut response)?;
-        let response: JsonNode = response.parse()?;
+        let response: JsonNode = fetch(&uri)?.parse()?;
         let allcount = Query::new()
             .get(0)
             .get("allcount")
             .execute(&response)
-            .and_then(JsonNode::get_number)
+            .and_then(JsonNode::as_u32)
             .ok_or(Error::InvalidData)?;
         if allcount != 1 {
             return Err(Error::InvalidData);
         };
-        let author_yomigana = Query::new()
-            .get(1)
-            .get("yomikata")
-            .execute(&response)
-            .and_then(JsonNode::get_string)
-            .ok_or(Error::InvalidData)?;
-        Ok(Novel {
-            ncode: ncode.to_string(),
-            title,
-            author_name,
-            author_yomigana,
-            last_update,
-            story,
-            series,
-            episode,
-        })
+        // 読み仮名は登録されていない作者もいるので、なくても失敗にしない
+        novel.author_yomigana = response
+            .get_path(&[1.into(), "yomikata".into()])
+            .and_then(JsonNode::as_str)
+            .filter(|yomigana| !yomigana.trim().is_empty())
+            .map(String::from);
+        Ok(novel)
     }
 
-    pub fn episodes(&self) -> Result<EpisodeIter> {
+    fn load_from(
+        site: Site,
+        ncode: &str,
+        fetch: impl Fn(&str) -> Result<String>,
+    ) -> Result<(Self, u32)> {
+        let uri = [
+            site.api(),
+            "?ncode=",
+            ncode,
+            "&out=json&of=t-nu-s-w-u-nt-e-i-ga-k-bg-g-l",
+        ]
+        .concat();
+        let (mut novel, userid) = parse_novel(ncode, &fetch(&uri)?)?;
+        novel.site = site;
+        Ok((novel, userid))
+    }
+
+    /// jobs 個までの話を並列に取得するが、各話へのアクセスは wait の範囲の間隔を空ける
+    pub fn episodes(
+        &self,
+        jobs: usize,
+        wait: RangeInclusive<Duration>,
+        interrupted: &'static AtomicBool,
+    ) -> Result<EpisodeIter> {
         Ok(EpisodeIter {
             cur: 1,
             max: self.episode,
-            series: self.series,
-            ncode: self.ncode.clone(),
-            id: Id::new_for_name(),
+            jobs: jobs.max(1),
+            skip_errors: false,
+            skipped: vec![],
+            source: EpisodeSource {
+                series: self.novel_type == NovelType::Serial,
+                ncode: self.ncode.clone(),
+                site: self.site,
+                id: Arc::new(Mutex::new(Id::new_for_name())),
+                images: Arc::new(Mutex::new(HashMap::new())),
+                limiter: Arc::new(RateLimiter::new(wait)),
+                interrupted,
+                cancel: Arc::new(AtomicBool::new(false)),
+                cache: None,
+                fetcher: self.fetcher.clone(),
+                outline_only: false,
+                since: None,
+                verbose: false,
+                no_images: false,
+                strip_notes: false,
+                keep_links: false,
+                max_image_size: episode::DEFAULT_MAX_IMAGE_SIZE,
+                remaining_size: Arc::new(AtomicU64::new(episode::DEFAULT_MAX_TOTAL_SIZE)),
+            },
+            pending: VecDeque::new(),
         })
     }
 
+    /// 本文の整形や画像の取得をせずに、各話のページから章と題名だけを集める
+    ///
+    /// キャッシュなど各話の取得の仕方は configure で指定する
+    pub fn outline(
+        &self,
+        jobs: usize,
+        wait: RangeInclusive<Duration>,
+        interrupted: &'static AtomicBool,
+        configure: impl FnOnce(&mut EpisodeIter) -> Result<()>,
+    ) -> Result<Vec<OutlineEntry>> {
+        let mut episodes = self.episodes(jobs, wait, interrupted)?;
+        configure(&mut episodes)?;
+        episodes.set_outline_only();
+        episodes
+            .map(|episode| {
+                episode.map(|episode| OutlineEntry {
+                    number: episode.number,
+                    chapter: episode.chapter,
+                    title: episode.title,
+                })
+            })
+            .collect()
+    }
+
     pub fn title(&self) -> &str {
         &self.title
     }
@@ -136,14 +486,23 @@ impl Novel {
         &self.author_name
     }
 
-    pub fn author_yomigana(&self) -> &str {
-        &self.author_yomigana
+    pub fn author_yomigana(&self) -> Option<&str> {
+        self.author_yomigana.as_deref()
     }
 
     pub fn story(&self) -> &str {
         &self.story
     }
 
+    pub fn keywords(&self) -> &[String] {
+        &self.keywords
+    }
+
+    /// 大ジャンルとジャンルの名前
+    pub fn genres(&self) -> &[&'static str] {
+        &self.genres
+    }
+
     pub fn last_update(&self) -> &DateTime {
         &self.last_update
     }
@@ -151,4 +510,230 @@ impl Novel {
     pub fn episode(&self) -> u32 {
         self.episode
     }
+
+    pub fn novel_type(&self) -> NovelType {
+        self.novel_type
+    }
+
+    /// 作品の情報を取得した時点の掲載の状況
+    pub fn status(&self) -> Status {
+        self.status
+    }
+
+    /// 作品全体の文字数
+    pub fn char_count(&self) -> Option<u32> {
+        self.length
+    }
+
+    #[allow(dead_code)]
+    pub fn site(&self) -> Site {
+        self.site
+    }
+
+    /// 作品の目次のページの URL
+    pub fn source(&self) -> String {
+        [self.site.base(), &self.ncode, "/"].concat()
+    }
+
+    /// 通信せずに作品の情報だけを持つ値を作る
+    #[cfg(test)]
+    pub fn fixture(title: &str, author_name: &str, episode: u32) -> Self {
+        Self {
+            ncode: "n0000a".to_string(),
+            title: title.to_string(),
+            author_name: author_name.to_string(),
+            author_yomigana: None,
+            last_update: DateTime::from_jst_str("2024-01-01 00:00:00").unwrap(),
+            story: String::new(),
+            keywords: vec![],
+            genres: vec![],
+            novel_type: NovelType::Serial,
+            status: Status::Ongoing,
+            episode,
+            length: None,
+            site: Site::General,
+            fetcher: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Error, Novel, NovelType, OutlineEntry, Recorded, Site, Status, fetch_episode_with_fetcher,
+        ncode_validate_and_normalize, parse_novel, parse_works,
+    };
+    use crate::epub::time::FromJST;
+    use std::sync::Arc;
+    use std::sync::atomic::AtomicBool;
+    use std::time::Duration;
+    use utcdatetime::DateTime;
+
+    #[test]
+    fn works() {
+        let (allcount, ncodes) =
+            parse_works(r#"[{"allcount":3},{"ncode":"N1234AB"},{"ncode":"N5678C"}]"#).unwrap();
+        assert_eq!(allcount, 3);
+        assert_eq!(ncodes, ["n1234ab", "n5678c"]);
+        let (allcount, ncodes) = parse_works(r#"[{"allcount":0}]"#).unwrap();
+        assert_eq!(allcount, 0);
+        assert!(ncodes.is_empty());
+        assert!(parse_works(r#"[{"allcount":1},{"title":"x"}]"#).is_err());
+    }
+
+    #[test]
+    fn novel() {
+        let response = |episode: u32| {
+            format!(
+                r#"[{{"allcount":1}},{{"title":"表題","noveltype":1,"userid":12345,"writer":"作者","story":"あらすじ","keyword":"異世界 恋愛\u3000&amp;","biggenre":1,"genre":101,"novelupdated_at":"2024-01-02 03:04:05","general_all_no":{}}}]"#,
+                episode
+            )
+        };
+        let (novel, userid) = parse_novel("n1234a", &response(3)).unwrap();
+        assert_eq!(userid, 12345);
+        assert_eq!(novel.title(), "表題");
+        assert_eq!(novel.episode(), 3);
+        assert_eq!(novel.keywords(), ["異世界", "恋愛", "&"]);
+        assert_eq!(novel.genres(), ["恋愛", "異世界"]);
+        // 文字数は応答になくてもよい
+        assert_eq!(novel.char_count(), None);
+        let with_length =
+            response(3).replace(r#""general_all_no""#, r#""length":12345,"general_all_no""#);
+        let (novel, _) = parse_novel("n1234a", &with_length).unwrap();
+        assert_eq!(novel.char_count(), Some(12345));
+        assert!(matches!(
+            parse_novel("n1234a", &response(0)),
+            Err(Error::NoEpisodes)
+        ));
+        assert!(matches!(
+            parse_novel("n1234a", r#"[{"allcount":0}]"#),
+            Err(Error::NovelUnavailable)
+        ));
+    }
+
+    #[test]
+    fn ncode() {
+        assert_eq!(ncode_validate_and_normalize("n1234a").unwrap(), "n1234a");
+        assert_eq!(ncode_validate_and_normalize("N1234a").unwrap(), "n1234a");
+        assert_eq!(ncode_validate_and_normalize("n1234A").unwrap(), "n1234a");
+    }
+
+    #[test]
+    fn single_episode() {
+        let fetch = |ncode: &str, number: u32| {
+            fetch_episode_with_fetcher(ncode, number, Site::General, Arc::new(Recorded::new()))
+        };
+        let episode = fetch("N0001A", 2).unwrap();
+        assert_eq!(episode.number, 2);
+        assert_eq!(episode.chapter.as_deref(), Some("二章"));
+        assert_eq!(episode.title, "第二話");
+        assert_eq!(episode.images.len(), 1);
+        assert!(
+            episode
+                .body()
+                .contains(&format!(r#"<img src="{}""#, episode.images[0].name))
+        );
+        let episode = fetch("n0002a", 1).unwrap();
+        assert!(!episode.series);
+        assert!(matches!(fetch("n0001a", 3), Err(Error::EpisodeNotFound(3))));
+        assert!(matches!(fetch("n0001a", 0), Err(Error::EpisodeNotFound(0))));
+        assert!(matches!(fetch("x0001a", 1), Err(Error::InvalidNcode)));
+    }
+
+    // 指定の日時より前に更新された話は飛ばす
+    #[test]
+    fn since() {
+        static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+        let novel = Novel::with_fetcher("n0001a", Arc::new(Recorded::new())).unwrap();
+        let numbers = |since: &str| {
+            let mut episodes = novel
+                .episodes(2, Duration::ZERO..=Duration::ZERO, &INTERRUPTED)
+                .unwrap();
+            episodes.set_since(DateTime::from_jst_str(since).unwrap());
+            episodes.map(|x| x.unwrap().number).collect::<Vec<_>>()
+        };
+        assert_eq!(numbers("2023-04-01 00:00:00"), [1, 2]);
+        assert_eq!(numbers("2023-04-01 12:00:00"), [1, 2]);
+        assert_eq!(numbers("2023-04-02 00:00:00"), [2]);
+        assert_eq!(numbers("2023-04-06 00:00:00"), Vec::<u32>::new());
+    }
+
+    // 一般の API で見つかった作品は年齢制限のある API を引かない
+    #[test]
+    fn recorded_site() {
+        static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+        let novel = Novel::with_fetcher("n0001a", Arc::new(Recorded::new())).unwrap();
+        assert_eq!(novel.site(), Site::General);
+        assert_eq!(novel.source(), "https://ncode.syosetu.com/n0001a/");
+        assert_eq!(novel.status(), Status::Ongoing);
+
+        let novel = Novel::with_fetcher("n0004a", Arc::new(Recorded::new())).unwrap();
+        assert_eq!(novel.site(), Site::R18);
+        assert_eq!(novel.title(), "年齢制限のある物語");
+        assert_eq!(novel.author_yomigana(), Some("さっしゃ"));
+        assert_eq!(novel.source(), "https://novel18.syosetu.com/n0004a/");
+        assert_eq!(novel.status(), Status::Suspended);
+        assert_eq!(novel.status().to_string(), "連載停止中");
+        // 各話も年齢制限のあるサイトから取得する
+        let outline = novel
+            .outline(1, Duration::ZERO..=Duration::ZERO, &INTERRUPTED, |_| Ok(()))
+            .unwrap();
+        assert_eq!(outline.len(), 1);
+        assert_eq!(outline[0].title, "第一話");
+    }
+
+    #[test]
+    fn recorded_short() {
+        static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+        let novel = Novel::with_fetcher("n0002a", Arc::new(Recorded::new())).unwrap();
+        assert_eq!(novel.title(), "短編");
+        assert_eq!(novel.novel_type(), NovelType::ShortStory);
+        assert_eq!(novel.status(), Status::Completed);
+        assert_eq!(novel.author_yomigana(), Some("さっしゃ"));
+        assert_eq!(novel.genres(), ["文芸", "純文学"]);
+        assert!(novel.keywords().is_empty());
+        let episodes: Vec<_> = novel
+            .episodes(1, Duration::ZERO..=Duration::ZERO, &INTERRUPTED)
+            .unwrap()
+            .collect::<super::Result<_>>()
+            .unwrap();
+        assert_eq!(episodes.len(), 1);
+        assert_eq!(episodes[0].title, "短編");
+        assert_eq!(episodes[0].chapter, None);
+        assert_eq!(episodes[0].body(), "<p>　一話だけの物語。</p>");
+        assert!(matches!(
+            Novel::with_fetcher("n9999z", Arc::new(Recorded::new())),
+            Err(Error::BadStatus(404))
+        ));
+    }
+
+    // 読み仮名のない作者の作品も取得できる
+    #[test]
+    fn recorded_without_yomikata() {
+        let novel = Novel::with_fetcher("n0005a", Arc::new(Recorded::new())).unwrap();
+        assert_eq!(novel.author_name(), "名無し");
+        assert_eq!(novel.author_yomigana(), None);
+    }
+
+    // 挿絵の応答は記録していないので、画像を取得しようとすれば失敗する
+    #[test]
+    fn recorded_outline() {
+        static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+        let novel = Novel::with_fetcher("n0003a", Arc::new(Recorded::new())).unwrap();
+        // 完結済みの連載
+        assert_eq!(novel.novel_type(), NovelType::Serial);
+        assert_eq!(novel.status(), Status::Completed);
+        let outline = novel
+            .outline(2, Duration::ZERO..=Duration::ZERO, &INTERRUPTED, |_| Ok(()))
+            .unwrap();
+        let entry = |number: u32, title: &str| OutlineEntry {
+            number,
+            chapter: Some("序章".to_string()),
+            title: title.to_string(),
+        };
+        assert_eq!(
+            outline,
+            [entry(1, "第一話"), entry(2, "第二話"), entry(3, "第三話")]
+        );
+    }
 }
@@ -1,3 +1,13 @@
+// &#12354; や &#x3042; の形式の数値文字参照で、不正な符号位置なら None
+fn numeric_reference(s: &str) -> Option<char> {
+    let s = s.strip_prefix('#')?;
+    let code = match s.strip_prefix(['x', 'X']) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+        None => s.parse().ok()?,
+    };
+    char::from_u32(code)
+}
+
 pub trait Unescape {
     fn unescape(&self) -> String;
 }
@@ -24,6 +34,15 @@ impl<T: AsRef<str>> Unescape for T {
                             } else if tempstr == "quot" {
                                 newstr.push('"');
                                 break;
+                            } else if tempstr == "apos" {
+                                newstr.push('\'');
+                                break;
+                            } else if tempstr == "nbsp" {
+                                newstr.push('\u{a0}');
+                                break;
+                            } else if let Some(ch) = numeric_reference(&tempstr) {
+                                newstr.push(ch);
+                                break;
                             } else {
                                 newstr.push('&');
                                 newstr.push_str(&tempstr);
@@ -31,10 +50,23 @@ impl<T: AsRef<str>> Unescape for T {
                                 break;
                             }
                         }
-                        Some(ch) => {
+                        Some(ch) if ch.is_ascii_alphanumeric() || ch == '#' => {
                             tempstr.push(ch);
                         }
+                        // 参照になっていない & はそのまま残す
+                        Some(ch) => {
+                            newstr.push('&');
+                            newstr.push_str(&tempstr);
+                            if ch == '&' {
+                                tempstr.clear();
+                            } else {
+                                newstr.push(ch);
+                                continue 'outer;
+                            }
+                        }
                         None => {
+                            newstr.push('&');
+                            newstr.push_str(&tempstr);
                             break 'outer;
                         }
                     }
@@ -46,3 +78,21 @@ impl<T: AsRef<str>> Unescape for T {
         newstr
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Unescape;
+
+    #[test]
+    fn it_works() {
+        assert_eq!("&lt;&amp;&gt;&quot;&apos;".unescape(), "<&>\"'");
+        assert_eq!("a&nbsp;b".unescape(), "a\u{a0}b");
+        assert_eq!("&#12354;&#x3042;&#X3044;".unescape(), "ああい");
+        assert_eq!(
+            "&#xD800;&#99999999;&#x;&unknown;".unescape(),
+            "&#xD800;&#99999999;&#x;&unknown;"
+        );
+        assert_eq!("a?x=1&y=2".unescape(), "a?x=1&y=2");
+        assert_eq!("a&&amp;b&".unescape(), "a&&b&");
+    }
+}
@@ -0,0 +1,352 @@
+use crate::sanitize::{avoid_reserved, sanitize};
+use std::path::{Path, PathBuf};
+
+/// 出力ファイル名の雛形の既定値 (拡張子は含まない)
+pub const DEFAULT_TEMPLATE: &str = "[{author}] {title}";
+
+/// `--flat-name` で使う、表題だけの雛形
+pub const FLAT_TEMPLATE: &str = "{title}";
+
+/// `--author-folder` で使う、作者ごとのディレクトリに分ける雛形
+pub const AUTHOR_FOLDER_TEMPLATE: &str = "{author}/{title}";
+
+/// 本の出力形式
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum OutputFormat {
+    #[default]
+    Epub,
+    Txt,
+}
+
+/// 対応していない出力形式の指定
+#[derive(Debug, PartialEq)]
+pub struct ParseOutputFormatError;
+
+impl OutputFormat {
+    /// 対応している出力形式の一覧
+    pub const ALL: [OutputFormat; 2] = [OutputFormat::Epub, OutputFormat::Txt];
+
+    /// 出力ファイルの拡張子
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Epub => "epub",
+            OutputFormat::Txt => "txt",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            OutputFormat::Epub => "EPUB 3 の電子書籍",
+            OutputFormat::Txt => "作品全体を一つにまとめたプレーンテキスト",
+        }
+    }
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = ParseOutputFormatError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        OutputFormat::ALL
+            .into_iter()
+            .find(|format| s.eq_ignore_ascii_case(format.extension()))
+            .ok_or(ParseOutputFormatError)
+    }
+}
+
+impl std::fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.extension())
+    }
+}
+
+/// ファイル名の雛形に埋め込む値
+pub struct NameFields<'a> {
+    pub ncode: &'a str,
+    pub author: &'a str,
+    pub title: &'a str,
+    pub date: &'a str,
+}
+
+impl NameFields<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        match key {
+            "ncode" => Some(self.ncode),
+            "author" => Some(self.author),
+            "title" => Some(self.title),
+            "date" => Some(self.date),
+            _ => None,
+        }
+    }
+}
+
+// {ncode} などのプレースホルダを置き換える。知らないプレースホルダはそのまま残す
+pub fn expand_template(template: &str, fields: &NameFields) -> String {
+    let mut expanded = String::new();
+    let mut rest = template;
+    while let Some((before, after)) = rest.split_once('{') {
+        expanded.push_str(before);
+        match after
+            .split_once('}')
+            .and_then(|(key, r)| Some((fields.get(key)?, r)))
+        {
+            Some((value, r)) => {
+                expanded.push_str(&sanitize(value));
+                rest = r;
+            }
+            None => {
+                expanded.push('{');
+                rest = after;
+            }
+        }
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+/// 出力先が既存のディレクトリか区切り文字で終わっていればその中に雛形から作った名前で、
+/// そうでなければ出力先をそのままファイル名として使う
+///
+/// 雛形に書いた区切り文字はディレクトリの区切りになる。埋め込んだ値の区切り文字は sanitize で取り除く
+pub fn output_path(
+    output: Option<&str>,
+    template: &str,
+    fields: &NameFields,
+    extension: &str,
+) -> PathBuf {
+    let name = || {
+        let expanded = expand_template(template, fields);
+        let mut components: Vec<String> = expanded
+            .split(['/', '\\'])
+            .filter(|component| !component.is_empty())
+            .map(|component| avoid_reserved(component.to_string()))
+            .collect();
+        match components.last_mut() {
+            Some(last) => {
+                last.push('.');
+                last.push_str(extension);
+            }
+            None => components.push([&sanitize(""), ".", extension].concat()),
+        }
+        components.iter().collect::<PathBuf>()
+    };
+    match output {
+        None => name(),
+        Some(output) if output.ends_with(['/', '\\']) || Path::new(output).is_dir() => {
+            Path::new(output).join(name())
+        }
+        Some(output) => PathBuf::from(output),
+    }
+}
+
+/// 分冊したときの各巻のファイル名で、拡張子の前に `(1of5)` のような巻数を入れる
+pub fn volume_path(path: &Path, index: usize, total: usize) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let volume = format!("{} ({}of{})", stem, index, total);
+    match path.extension() {
+        Some(extension) => {
+            path.with_file_name([&volume, ".", &extension.to_string_lossy()].concat())
+        }
+        None => path.with_file_name(volume),
+    }
+}
+
+/// 上書きしない指定のときに、既にある出力ファイルの名前を返す
+///
+/// 分冊した本は巻の数が名前に入るので、一巻目があれば出力済みとみなす
+pub fn existing_output(path: &Path, overwrite: bool) -> Option<PathBuf> {
+    if overwrite {
+        return None;
+    }
+    if path.exists() {
+        return Some(path.to_path_buf());
+    }
+    let stem = path.file_stem()?.to_string_lossy();
+    let prefix = format!("{} (1of", stem);
+    let suffix = match path.extension() {
+        Some(extension) => [").", &extension.to_string_lossy()].concat(),
+        None => ")".to_string(),
+    };
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    std::fs::read_dir(directory)
+        .ok()?
+        .flatten()
+        .map(|entry| entry.path())
+        .find(|candidate| {
+            candidate.file_name().is_some_and(|name| {
+                let name = name.to_string_lossy();
+                name.starts_with(&prefix) && name.ends_with(&suffix)
+            })
+        })
+}
+
+/// 出力先のファイルを置くディレクトリ
+pub fn output_directory(output: Option<&str>) -> PathBuf {
+    match output {
+        Some(output) if output.ends_with(['/', '\\']) || Path::new(output).is_dir() => {
+            PathBuf::from(output)
+        }
+        Some(output) => match Path::new(output).parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => PathBuf::from("."),
+        },
+        None => PathBuf::from("."),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIELDS: NameFields = NameFields {
+        ncode: "n1234a",
+        author: "作者",
+        title: "表題: 副題",
+        date: "2024-01-02",
+    };
+
+    #[test]
+    fn template() {
+        assert_eq!(
+            expand_template(DEFAULT_TEMPLATE, &FIELDS),
+            "[作者] 表題 副題"
+        );
+        assert_eq!(
+            expand_template("{date}_{ncode}", &FIELDS),
+            "2024-01-02_n1234a"
+        );
+        assert_eq!(
+            expand_template("{unknown}{title", &FIELDS),
+            "{unknown}{title"
+        );
+    }
+
+    #[test]
+    fn path() {
+        assert_eq!(
+            output_path(None, DEFAULT_TEMPLATE, &FIELDS, "epub"),
+            PathBuf::from("[作者] 表題 副題.epub")
+        );
+        assert_eq!(
+            output_path(Some("books/"), "{ncode}", &FIELDS, "epub"),
+            Path::new("books/").join("n1234a.epub")
+        );
+        assert_eq!(
+            output_path(Some("books/novel.epub"), "{ncode}", &FIELDS, "epub"),
+            PathBuf::from("books/novel.epub")
+        );
+        assert_eq!(
+            output_path(None, "aux", &FIELDS, "epub"),
+            PathBuf::from("_aux.epub")
+        );
+    }
+
+    #[test]
+    fn layout() {
+        let fields = NameFields {
+            ncode: "n1234a",
+            author: "作者/別名",
+            title: "表題: 副題",
+            date: "2024-01-02",
+        };
+        assert_eq!(
+            output_path(None, FLAT_TEMPLATE, &fields, "epub"),
+            PathBuf::from("表題 副題.epub")
+        );
+        assert_eq!(
+            output_path(Some("books/"), FLAT_TEMPLATE, &fields, "txt"),
+            Path::new("books/").join("表題 副題.txt")
+        );
+        // 作者名の区切り文字はディレクトリの区切りにしない
+        assert_eq!(
+            output_path(None, AUTHOR_FOLDER_TEMPLATE, &fields, "epub"),
+            Path::new("作者別名").join("表題 副題.epub")
+        );
+        assert_eq!(
+            output_path(Some("books/"), AUTHOR_FOLDER_TEMPLATE, &fields, "epub"),
+            Path::new("books/").join("作者別名").join("表題 副題.epub")
+        );
+        // 各階層で予約されたデバイス名を避ける
+        let fields = NameFields {
+            author: "con",
+            title: "nul",
+            ..fields
+        };
+        assert_eq!(
+            output_path(None, AUTHOR_FOLDER_TEMPLATE, &fields, "epub"),
+            Path::new("_con").join("_nul.epub")
+        );
+        assert_eq!(
+            output_path(None, "aux/{title}", &fields, "epub"),
+            Path::new("_aux").join("_nul.epub")
+        );
+        assert_eq!(
+            output_path(None, "/", &fields, "epub"),
+            PathBuf::from("untitled.epub")
+        );
+    }
+
+    #[test]
+    fn volume() {
+        assert_eq!(
+            volume_path(Path::new("[作者] 表題.epub"), 1, 5),
+            PathBuf::from("[作者] 表題 (1of5).epub")
+        );
+        assert_eq!(
+            volume_path(&Path::new("books").join("novel.epub"), 5, 5),
+            Path::new("books").join("novel (5of5).epub")
+        );
+        assert_eq!(
+            volume_path(Path::new("novel"), 2, 3),
+            PathBuf::from("novel (2of3)")
+        );
+    }
+
+    #[test]
+    fn existing() {
+        let directory = std::env::temp_dir().join("narou-epub-test-existing");
+        let _ = std::fs::remove_dir_all(&directory);
+        std::fs::create_dir(&directory).unwrap();
+        let path = directory.join("[作者] 表題.epub");
+        assert_eq!(existing_output(&path, false), None);
+        std::fs::write(&path, b"").unwrap();
+        assert_eq!(existing_output(&path, false), Some(path.clone()));
+        // 上書きする指定なら既にあっても作り直す
+        assert_eq!(existing_output(&path, true), None);
+        std::fs::remove_file(&path).unwrap();
+        // 分冊した本は一巻目があれば出力済み
+        let volume = volume_path(&path, 1, 3);
+        std::fs::write(&volume, b"").unwrap();
+        assert_eq!(existing_output(&path, false), Some(volume.clone()));
+        assert_eq!(
+            existing_output(&directory.join("[作者] 別の表題.epub"), false),
+            None
+        );
+        assert_eq!(existing_output(&path.with_extension("txt"), false), None);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn directory() {
+        assert_eq!(output_directory(None), PathBuf::from("."));
+        assert_eq!(output_directory(Some("books/")), PathBuf::from("books/"));
+        assert_eq!(
+            output_directory(Some("books/novel.epub")),
+            PathBuf::from("books")
+        );
+        assert_eq!(output_directory(Some("novel.epub")), PathBuf::from("."));
+    }
+
+    #[test]
+    fn output_format() {
+        assert_eq!("epub".parse(), Ok(OutputFormat::Epub));
+        assert_eq!("TXT".parse(), Ok(OutputFormat::Txt));
+        assert_eq!(OutputFormat::default(), OutputFormat::Epub);
+        assert_eq!("azw3".parse::<OutputFormat>(), Err(ParseOutputFormatError));
+        assert_eq!("".parse::<OutputFormat>(), Err(ParseOutputFormatError));
+        for format in OutputFormat::ALL {
+            assert_eq!(format.to_string().parse(), Ok(format));
+        }
+    }
+}
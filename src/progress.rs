@@ -0,0 +1,22 @@
+/// 話の取得の進み具合を受け取るもの
+///
+/// コンソールでは Indicator が進捗表示を描き、表示しない場合は Silent を使う
+pub trait Progress {
+    /// 取得を始める前に全体の話数を受け取る
+    fn start(&mut self, total: u32);
+
+    /// 一話を取得するたびにその話の番号を受け取る
+    fn advance(&mut self, current: u32);
+
+    /// 全ての話を書き終えたときに呼ばれる
+    fn finish(&mut self);
+}
+
+/// 何も表示しない
+pub struct Silent;
+
+impl Progress for Silent {
+    fn start(&mut self, _total: u32) {}
+    fn advance(&mut self, _current: u32) {}
+    fn finish(&mut self) {}
+}
@@ -0,0 +1,71 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use windows_sys::Win32::System::Console::{ATTACH_PARENT_PROCESS, AttachConsole, GetConsoleWindow};
+
+/// コンソールがない場合に報告を書き出すファイルの名前
+pub const LOG_NAME: &str = "narou-epub.log";
+
+// コンソールがない場合の書き出し先
+static LOG: Mutex<Option<File>> = Mutex::new(None);
+
+// 自分のコンソールがあるか、親プロセスのコンソールに接続できたか
+fn has_console() -> bool {
+    unsafe { !GetConsoleWindow().is_null() || AttachConsole(ATTACH_PARENT_PROCESS) != 0 }
+}
+
+fn open_log(path: &Path) {
+    *LOG.lock().unwrap() = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .ok();
+}
+
+/// コンソールがなければ以後の報告を directory の中のログファイルに追記する
+pub fn init(directory: &Path) {
+    if !has_console() {
+        open_log(&directory.join(LOG_NAME));
+    }
+}
+
+fn write(s: &str, stderr: bool) {
+    match LOG.lock().unwrap().as_mut() {
+        Some(file) => {
+            let _ = writeln!(file, "{}", s);
+        }
+        None if stderr => eprintln!("{}", s),
+        None => println!("{}", s),
+    }
+}
+
+/// 結果やエラーを標準出力かログファイルに書き出す
+pub fn line(s: &str) {
+    write(s, false);
+}
+
+/// 進捗を標準エラー出力かログファイルに書き出す
+pub fn progress(s: &str) {
+    write(s, true);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn log() {
+        let path = std::env::temp_dir().join("narou-epub-test.log");
+        let _ = std::fs::remove_file(&path);
+        open_log(&path);
+        line("成功: n1234a");
+        progress("表題");
+        *LOG.lock().unwrap() = None;
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "成功: n1234a\n表題\n"
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
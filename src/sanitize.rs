@@ -3,6 +3,132 @@ fn forbidden_char(ch: char) -> bool {
     ch.is_control() || FORBIDDEN_LIST.contains(&ch)
 }
 
+// 拡張子の有無や大文字小文字に関わらずウィンドウズが予約しているデバイス名
+fn reserved_name(s: &str) -> bool {
+    const RESERVED_LIST: [&str; 4] = ["CON", "PRN", "AUX", "NUL"];
+    let stem = s
+        .split('.')
+        .next()
+        .unwrap_or(s)
+        .trim_end()
+        .to_ascii_uppercase();
+    RESERVED_LIST.contains(&stem.as_str())
+        || (stem.len() == 4
+            && (stem.starts_with("COM") || stem.starts_with("LPT"))
+            && matches!(stem.as_bytes()[3], b'1'..=b'9'))
+}
+
+/// 予約されたデバイス名なら先頭に `_` を付けて避ける
+pub fn avoid_reserved(s: String) -> String {
+    if reserved_name(&s) {
+        ["_", &s].concat()
+    } else {
+        s
+    }
+}
+
+/// 全角の英数字や記号と全角空白を半角に寄せる。
+/// 半角にするとファイル名に使えなくなる文字は全角のまま残す
+pub fn normalize_width(s: &str) -> String {
+    s.chars()
+        .map(|ch| {
+            let folded = match ch {
+                '\u{3000}' => ' ',
+                '\u{ff01}'..='\u{ff5e}' => char::from_u32(ch as u32 - 0xff01 + 0x21).unwrap(),
+                _ => ch,
+            };
+            if forbidden_char(folded) { ch } else { folded }
+        })
+        .collect()
+}
+
+/// 空になったときに代わりに使う名前
+const PLACEHOLDER: &str = "untitled";
+
+/// 一項目あたりの最大の長さで、ウィンドウズのパスの長さと同じく UTF-16 の単位で数える。
+/// 雛形に複数の項目を埋め込んでも MAX_PATH に収まるようにする
+const MAX_LENGTH: usize = 64;
+
 pub fn sanitize(s: &str) -> String {
-    s.trim().chars().filter(|&ch| !forbidden_char(ch)).collect()
+    let mut units = 0;
+    let sanitized: String = s
+        .trim()
+        .chars()
+        .filter(|&ch| !forbidden_char(ch))
+        .take_while(|ch| {
+            units += ch.len_utf16();
+            units <= MAX_LENGTH
+        })
+        .collect();
+    // 末尾のドットと空白はウィンドウズでは取り除かれてしまう
+    let sanitized = sanitized.trim_end_matches(['.', ' ']);
+    if sanitized.is_empty() {
+        PLACEHOLDER.to_string()
+    } else {
+        avoid_reserved(sanitized.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MAX_LENGTH, PLACEHOLDER, normalize_width, sanitize};
+
+    #[test]
+    fn reserved() {
+        for name in ["CON", "PRN", "AUX", "NUL"]
+            .into_iter()
+            .map(String::from)
+            .chain((1..=9).flat_map(|n| [format!("COM{}", n), format!("LPT{}", n)]))
+        {
+            assert_eq!(sanitize(&name), ["_", &name].concat());
+            let lower = name.to_lowercase();
+            assert_eq!(sanitize(&lower), ["_", &lower].concat());
+            let with_extension = [&name, ".txt"].concat();
+            assert_eq!(sanitize(&with_extension), ["_", &with_extension].concat());
+        }
+        assert_eq!(sanitize("COM0"), "COM0");
+        assert_eq!(sanitize("CONSOLE"), "CONSOLE");
+        assert_eq!(sanitize("コン"), "コン");
+    }
+
+    #[test]
+    fn trailing() {
+        assert_eq!(sanitize("終わり..."), "終わり");
+        assert_eq!(sanitize("題名. . "), "題名");
+        assert_eq!(sanitize("CON. "), "_CON");
+    }
+
+    #[test]
+    fn empty() {
+        assert_eq!(sanitize(""), PLACEHOLDER);
+        assert_eq!(sanitize("  "), PLACEHOLDER);
+        assert_eq!(sanitize("<>:\"/\\|?*\0\u{7}"), PLACEHOLDER);
+        assert_eq!(sanitize("..."), PLACEHOLDER);
+    }
+
+    #[test]
+    fn width() {
+        assert_eq!(normalize_width("第１２３話"), "第123話");
+        assert_eq!(normalize_width("表題\u{3000}副題"), "表題 副題");
+        assert_eq!(normalize_width("（ＡＢＣ）ｘｙｚ！"), "(ABC)xyz!");
+        // 半角にするとファイル名に使えない文字はそのまま
+        assert_eq!(normalize_width("前編：後編／？"), "前編：後編／？");
+        assert_eq!(normalize_width("ｶﾀｶﾅ・かな"), "ｶﾀｶﾅ・かな");
+        assert_eq!(sanitize(&normalize_width("\u{3000}題名\u{3000}")), "題名");
+    }
+
+    #[test]
+    fn long() {
+        let title = "長".repeat(MAX_LENGTH * 2);
+        let sanitized = sanitize(&title);
+        assert_eq!(sanitized.chars().count(), MAX_LENGTH);
+        assert!(title.starts_with(&sanitized));
+        // サロゲートペアになる文字は二つ分と数え、文字の途中では切らない
+        let title = ["a", &"𠮷".repeat(MAX_LENGTH)].concat();
+        let sanitized = sanitize(&title);
+        assert_eq!(sanitized.encode_utf16().count(), MAX_LENGTH - 1);
+        assert!(title.starts_with(&sanitized));
+        let title = ["a".repeat(MAX_LENGTH - 1), ". 続き".to_string()].concat();
+        assert_eq!(sanitize(&title), "a".repeat(MAX_LENGTH - 1));
+    }
 }
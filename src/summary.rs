@@ -0,0 +1,95 @@
+use crate::json::JsonNode;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// 一つの作品の変換結果の要約で、変換の途中で分かったところまでを記録する
+#[derive(Default)]
+pub struct Summary {
+    pub ncode: String,
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub episodes: Option<u32>,
+    pub output: Option<PathBuf>,
+    pub size: Option<u64>,
+    pub elapsed: Duration,
+    pub error: Option<String>,
+}
+
+impl Summary {
+    pub fn new(ncode: &str) -> Self {
+        Self {
+            ncode: ncode.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// JSON の数は u32 までなので、収まらない大きさや経過時間は丸めずに null にする
+    pub fn to_json(&self) -> JsonNode {
+        let string = |s: &Option<String>| s.as_deref().map_or(JsonNode::Null, JsonNode::from);
+        let number = |n: Option<u32>| n.map_or(JsonNode::Null, JsonNode::from);
+        JsonNode::Object(vec![
+            ("ncode".into(), self.ncode.as_str().into()),
+            ("title".into(), string(&self.title)),
+            ("author".into(), string(&self.author)),
+            ("episodes".into(), number(self.episodes)),
+            (
+                "output".into(),
+                string(
+                    &self
+                        .output
+                        .as_ref()
+                        .map(|x| x.to_string_lossy().into_owned()),
+                ),
+            ),
+            (
+                "size".into(),
+                number(self.size.and_then(|x| x.try_into().ok())),
+            ),
+            (
+                "elapsed_ms".into(),
+                number(self.elapsed.as_millis().try_into().ok()),
+            ),
+            ("success".into(), JsonNode::Bool(self.error.is_none())),
+            ("error".into(), string(&self.error)),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Summary;
+    use crate::json::JsonNode;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    #[test]
+    fn shape() {
+        let mut summary = Summary::new("n1234a");
+        summary.title = Some("表題".to_string());
+        summary.author = Some("作者".to_string());
+        summary.episodes = Some(3);
+        summary.output = Some(PathBuf::from("[作者] 表題.epub"));
+        summary.size = Some(2048);
+        summary.elapsed = Duration::from_millis(1500);
+        assert_eq!(
+            summary.to_json().to_string(),
+            r#"{"ncode":"n1234a","title":"表題","author":"作者","episodes":3,"output":"[作者] 表題.epub","size":2048,"elapsed_ms":1500,"success":true,"error":null}"#
+        );
+
+        let mut summary = Summary::new("n0000a");
+        summary.error = Some("失敗".to_string());
+        let json = summary.to_json();
+        assert_eq!(json["success"], JsonNode::Bool(false));
+        assert_eq!(json["error"], "失敗".into());
+        assert_eq!(json["title"], JsonNode::Null);
+
+        // u32 に収まらない値は上限に丸めずに null にする
+        summary.size = Some(u64::from(u32::MAX) + 1);
+        summary.elapsed = Duration::from_millis(u64::from(u32::MAX) + 1);
+        let json = summary.to_json();
+        assert_eq!(json["size"], JsonNode::Null);
+        assert_eq!(json["elapsed_ms"], JsonNode::Null);
+        summary.size = Some(u64::from(u32::MAX));
+        assert_eq!(summary.to_json()["size"], u32::MAX.into());
+    }
+}
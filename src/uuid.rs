@@ -1,7 +1,8 @@
 use std::fmt::{Display, Write};
 use windows_sys::Win32::Foundation::STATUS_SUCCESS;
 use windows_sys::Win32::Security::Cryptography::{
-    BCRYPT_SHA1_ALGORITHM, BCryptCloseAlgorithmProvider, BCryptHash, BCryptOpenAlgorithmProvider,
+    BCRYPT_SHA1_ALGORITHM, BCRYPT_USE_SYSTEM_PREFERRED_RNG, BCryptCloseAlgorithmProvider,
+    BCryptGenRandom, BCryptHash, BCryptOpenAlgorithmProvider,
 };
 
 struct Provider(*mut std::ffi::c_void);
@@ -20,11 +21,8 @@ impl Provider {
         }
     }
 
-    fn hash(&self, name: &[u8]) -> Option<[u8; 20]> {
-        const URL_NAMESPACE: [u8; 16] = 0x6ba7b811_9dad_11d1_80b4_00c04fd430c8_u128.to_be_bytes();
-        let mut input_data = Vec::new();
-        input_data.extend_from_slice(&URL_NAMESPACE);
-        input_data.extend_from_slice(name);
+    fn hash(&self, data: &[u8]) -> Option<[u8; 20]> {
+        let mut input_data = data.to_vec();
         let mut hash_result = [0u8; 20];
 
         if unsafe {
@@ -54,6 +52,26 @@ impl Drop for Provider {
     }
 }
 
+/// 名前空間を付けずにそのままのデータから求めた SHA-1
+pub fn sha1(data: &[u8]) -> Option<[u8; 20]> {
+    Provider::new()?.hash(data)
+}
+
+/// UUID の版に関わらない共通の表記
+pub trait Uuid {
+    fn as_bytes(&self) -> &[u8; 16];
+
+    fn write_uuid(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, byte) in self.as_bytes().iter().enumerate() {
+            if matches!(i, 4 | 6 | 8 | 10) {
+                f.write_char('-')?;
+            }
+            f.write_byte(*byte)?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Clone)]
 pub struct UUIDv5 {
     uuid: [u8; 16],
@@ -61,17 +79,50 @@ pub struct UUIDv5 {
 
 impl UUIDv5 {
     pub fn new(name: &[u8]) -> Option<Self> {
-        let provider = Provider::new()?;
-        let hash_result = provider.hash(name)?;
+        const URL_NAMESPACE: [u8; 16] = 0x6ba7b811_9dad_11d1_80b4_00c04fd430c8_u128.to_be_bytes();
+        let hash_result = sha1(&[&URL_NAMESPACE, name].concat())?;
         let mut uuid_bytes = [0u8; 16];
         uuid_bytes.copy_from_slice(&hash_result[..16]);
         uuid_bytes[6] = (uuid_bytes[6] & 0x0f) | 0x50;
         uuid_bytes[8] = (uuid_bytes[8] & 0x3f) | 0x80;
         Some(Self { uuid: uuid_bytes })
     }
+}
 
-    #[allow(dead_code)]
-    pub fn as_bytes(&self) -> &[u8; 16] {
+impl Uuid for UUIDv5 {
+    fn as_bytes(&self) -> &[u8; 16] {
+        &self.uuid
+    }
+}
+
+/// 乱数から作る UUID
+#[derive(Clone)]
+pub struct UUIDv4 {
+    uuid: [u8; 16],
+}
+
+impl UUIDv4 {
+    pub fn new() -> Option<Self> {
+        let mut uuid_bytes = [0u8; 16];
+        if unsafe {
+            BCryptGenRandom(
+                std::ptr::null_mut(),
+                uuid_bytes.as_mut_ptr(),
+                uuid_bytes.len() as u32,
+                BCRYPT_USE_SYSTEM_PREFERRED_RNG,
+            )
+        } != STATUS_SUCCESS
+        {
+            return None;
+        }
+        uuid_bytes[6] = (uuid_bytes[6] & 0x0f) | 0x40;
+        uuid_bytes[8] = (uuid_bytes[8] & 0x3f) | 0x80;
+        Some(Self { uuid: uuid_bytes })
+    }
+}
+
+impl Uuid for UUIDv4 {
+    fn as_bytes(&self) -> &[u8; 16] {
         &self.uuid
     }
 }
@@ -90,26 +141,13 @@ impl WriteByte for std::fmt::Formatter<'_> {
 
 impl Display for UUIDv5 {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_byte(self.uuid[0])?;
-        f.write_byte(self.uuid[1])?;
-        f.write_byte(self.uuid[2])?;
-        f.write_byte(self.uuid[3])?;
-        f.write_char('-')?;
-        f.write_byte(self.uuid[4])?;
-        f.write_byte(self.uuid[5])?;
-        f.write_char('-')?;
-        f.write_byte(self.uuid[6])?;
-        f.write_byte(self.uuid[7])?;
-        f.write_char('-')?;
-        f.write_byte(self.uuid[8])?;
-        f.write_byte(self.uuid[9])?;
-        f.write_char('-')?;
-        f.write_byte(self.uuid[10])?;
-        f.write_byte(self.uuid[11])?;
-        f.write_byte(self.uuid[12])?;
-        f.write_byte(self.uuid[13])?;
-        f.write_byte(self.uuid[14])?;
-        f.write_byte(self.uuid[15])
+        self.write_uuid(f)
+    }
+}
+
+impl Display for UUIDv4 {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.write_uuid(f)
     }
 }
 
@@ -131,4 +169,40 @@ mod tests {
         assert_eq!(uuid.as_bytes(), &expected_bytes);
         assert_eq!(uuid.to_string(), expected_string);
     }
+
+    #[test]
+    fn plain_sha1() {
+        let expected = 0x4706816a_ba3e2571_7850c26c_9cd0d89d_u128;
+        let hash = sha1(b"abc").unwrap();
+        assert_eq!(hash[..4], [0xa9, 0x99, 0x3e, 0x36]);
+        assert_eq!(hash[4..], expected.to_be_bytes());
+    }
+
+    // content.opf の識別子に使う出典の URL の形式
+    #[test]
+    fn source_url() {
+        let uuid = UUIDv5::new("https://ncode.syosetu.com/n1234a/".as_bytes()).unwrap();
+        let string = uuid.to_string();
+        assert_eq!(string, "270c7ebf-c164-5af5-8bda-4fb0e97144dd");
+        assert_eq!(string.len(), 36);
+        assert!(
+            string
+                .bytes()
+                .all(|b| b == b'-' || matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+        );
+    }
+
+    #[test]
+    fn random() {
+        let first = UUIDv4::new().unwrap();
+        let second = UUIDv4::new().unwrap();
+        assert_ne!(first.as_bytes(), second.as_bytes());
+        for uuid in [first, second] {
+            assert_eq!(uuid.as_bytes()[6] & 0xf0, 0x40);
+            assert_eq!(uuid.as_bytes()[8] & 0xc0, 0x80);
+            let string = uuid.to_string();
+            assert_eq!(string.len(), 36);
+            assert_eq!(&string[14..15], "4");
+        }
+    }
 }
@@ -0,0 +1,61 @@
+// コンソールでの表示幅。東アジアの全角の文字は半角二文字分として数える
+fn char_width(ch: char) -> usize {
+    match ch as u32 {
+        0x00..=0x1f | 0x7f..=0x9f => 0,
+        0x1100..=0x115f
+        | 0x2e80..=0x303e
+        | 0x3040..=0xa4cf
+        | 0xac00..=0xd7a3
+        | 0xf900..=0xfaff
+        | 0xfe30..=0xfe4f
+        | 0xff00..=0xff60
+        | 0xffe0..=0xffe6
+        | 0x1f300..=0x1f64f
+        | 0x1f900..=0x1f9ff
+        | 0x20000..=0x2fffd
+        | 0x30000..=0x3fffd => 2,
+        _ => 1,
+    }
+}
+
+/// 文字列全体の表示幅
+pub fn display_width(s: &str) -> usize {
+    s.chars().map(char_width).sum()
+}
+
+/// 表示幅が width を超えないように文字の境界で切り詰める
+pub fn truncate_to_width(s: &str, width: usize) -> &str {
+    let mut total = 0;
+    for (i, ch) in s.char_indices() {
+        total += char_width(ch);
+        if total > width {
+            return &s[..i];
+        }
+    }
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{display_width, truncate_to_width};
+
+    #[test]
+    fn width() {
+        assert_eq!(display_width("abc"), 3);
+        assert_eq!(display_width("第1話"), 5);
+        assert_eq!(display_width("ｶﾅ　全角"), 8);
+        assert_eq!(display_width("𠮷野家"), 6);
+    }
+
+    #[test]
+    fn truncate() {
+        assert_eq!(truncate_to_width("第1話 始まり", 5), "第1話");
+        // 全角の文字の途中で切れる場合はその手前まで
+        assert_eq!(truncate_to_width("第1話 始まり", 4), "第1");
+        assert_eq!(truncate_to_width("ab漢字cd", 3), "ab");
+        assert_eq!(truncate_to_width("ab漢字cd", 4), "ab漢");
+        assert_eq!(truncate_to_width("ab漢字cd", 100), "ab漢字cd");
+        assert_eq!(truncate_to_width("漢字", 1), "");
+        assert_eq!(truncate_to_width("", 0), "");
+    }
+}